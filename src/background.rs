@@ -0,0 +1,74 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use crate::{color::Color, image::Image, vector::Vector};
+
+/// Radiance arriving from infinitely far away, seen by rays that leave the scene
+pub trait Background: Send + Sync {
+    /// `direction` is the unit direction of the ray leaving the scene
+    fn color(&self, direction: Vector) -> Color;
+}
+
+impl Background for Color {
+    fn color(&self, _direction: Vector) -> Color {
+        *self
+    }
+}
+
+impl Background for Arc<dyn Background> {
+    fn color(&self, direction: Vector) -> Color {
+        self.as_ref().color(direction)
+    }
+}
+
+/// Blends vertically from `bottom` for rays pointing straight down to `top` for rays pointing
+/// straight up
+pub struct Gradient {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+impl Background for Gradient {
+    fn color(&self, direction: Vector) -> Color {
+        let t = 0.5 * (direction.y() + 1.0);
+        self.bottom.lerp(self.top, t)
+    }
+}
+
+/// An equirectangular environment map with +Y pointing up. The horizontal center of the image
+/// lies in the -Z direction.
+pub struct EnvironmentMap {
+    image: Image,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image) -> Self {
+        assert!(image.width() > 0 && image.height() > 0);
+        Self { image }
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, direction: Vector) -> Color {
+        let width = self.image.width();
+        let height = self.image.height();
+
+        let u = 0.5 + direction.x().atan2(-direction.z()) / (2.0 * PI);
+        let v = direction.y().clamp(-1.0, 1.0).acos() / PI;
+
+        // Bilinear filtering between pixel centers, wrapping around horizontally
+        let x = u * width as f32 - 0.5;
+        let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let x0 = (x0 as i64).rem_euclid(width as i64) as u32;
+        let x1 = (x0 + 1) % width;
+        let y0 = y0 as u32;
+        let y1 = (y0 + 1).min(height - 1);
+
+        let pixel = |x: u32, y: u32| self.image.pixels[y as usize * width as usize + x as usize];
+        let top = pixel(x0, y0).lerp(pixel(x1, y0), tx);
+        let bottom = pixel(x0, y1).lerp(pixel(x1, y1), tx);
+        top.lerp(bottom, ty)
+    }
+}
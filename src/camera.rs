@@ -10,9 +10,16 @@ pub struct Camera {
     u: Vector,
     v: Vector,
     lens_radius: f32,
+    aperture_blades: u32,
 }
 
 impl Camera {
+    /// `aperture_blades` shapes the lens opening as a regular polygon with that many sides,
+    /// giving polygonal bokeh. Fewer than 3 blades mean a circular opening.
+    ///
+    /// Degenerate inputs are replaced rather than rejected: a `lookat` equal to `lookfrom`
+    /// looks down the negative z axis, and a zero `vup` points up the y axis.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Vector,
         mut lookat: Vector,
@@ -20,14 +27,9 @@ impl Camera {
         vfov: f32,
         aspect_ratio: f32,
         aperture: f32,
+        aperture_blades: u32,
         focus_dist: f32,
     ) -> Self {
-        let theta = vfov.to_radians();
-        let h = (theta / 2.0).tan();
-
-        let vp_height = 2.0 * h;
-        let vp_width = aspect_ratio * vp_height;
-
         if (lookat - lookfrom).is_almost_zero() {
             lookat = lookat + Vector::from_xyz(0.0, 0.0, -1.0);
         }
@@ -39,22 +41,99 @@ impl Camera {
         let u = vup.cross3(w).normalize_unchecked();
         let v = w.cross3(u);
 
+        Self::from_basis(
+            lookfrom,
+            u,
+            v,
+            w,
+            vfov,
+            aspect_ratio,
+            aperture,
+            aperture_blades,
+            focus_dist,
+        )
+    }
+
+    /// Like [`Camera::new`], but with the field of view of a lens with `focal_length` in front
+    /// of a sensor `sensor_width` wide, both in millimetres, and the aperture of that lens at
+    /// `f_number`. The aperture takes scene units to be metres. An infinite f-number gives a
+    /// pinhole camera.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_physical(
+        lookfrom: Vector,
+        lookat: Vector,
+        vup: Vector,
+        sensor_width: f32,
+        focal_length: f32,
+        f_number: f32,
+        aspect_ratio: f32,
+        aperture_blades: u32,
+        focus_dist: f32,
+    ) -> Self {
+        Self::new(
+            lookfrom,
+            lookat,
+            vup,
+            vfov_from_focal_length(focal_length, sensor_width, aspect_ratio),
+            aspect_ratio,
+            aperture_from_f_number(focal_length, f_number),
+            aperture_blades,
+            focus_dist,
+        )
+    }
+
+    /// Places the camera at `origin` with an orthonormal basis of `u` pointing right, `v`
+    /// pointing up and `w` pointing backwards, away from the view direction. Unlike
+    /// [`Camera::new`], the basis is used as given, e.g. taken from the camera matrix of
+    /// another application.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_basis(
+        origin: Vector,
+        u: Vector,
+        v: Vector,
+        w: Vector,
+        vfov: f32,
+        aspect_ratio: f32,
+        aperture: f32,
+        aperture_blades: u32,
+        focus_dist: f32,
+    ) -> Self {
+        let theta = vfov.to_radians();
+        let h = (theta / 2.0).tan();
+
+        let vp_height = 2.0 * h;
+        let vp_width = aspect_ratio * vp_height;
+
         let horizontal = focus_dist * vp_width * u;
         let vertical = focus_dist * vp_height * v;
 
         Self {
-            origin: lookfrom,
+            origin,
             horizontal,
             vertical,
-            lower_left_corner: lookfrom - horizontal / 2.0 - vertical / 2.0 - focus_dist * w,
+            lower_left_corner: origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w,
             u,
             v,
             lens_radius: aperture / 2.0,
+            aperture_blades,
         }
     }
 
     pub fn get_ray(&self, s: f32, t: f32, state: &mut WorkerState) -> Ray {
-        let rd = self.lens_radius * random_lens_position(state);
+        // A pinhole camera needs no lens sample
+        if self.lens_radius == 0.0 {
+            return Ray::new(
+                self.origin,
+                self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin,
+            );
+        }
+
+        let rd = if self.aperture_blades < 3 {
+            random_lens_position(state)
+        } else {
+            random_polygon_position(self.aperture_blades, state)
+        };
+        let rd = self.lens_radius * rd;
         let offset = self.u * rd.x() + self.v * rd.y();
 
         Ray::new(
@@ -64,10 +143,41 @@ impl Camera {
     }
 }
 
+/// Vertical field of view in degrees of a lens with `focal_length` in front of a sensor
+/// `sensor_width` wide, both in millimetres, for images of `aspect_ratio`
+pub fn vfov_from_focal_length(focal_length: f32, sensor_width: f32, aspect_ratio: f32) -> f32 {
+    let sensor_height = sensor_width / aspect_ratio;
+    (2.0 * (sensor_height / (2.0 * focal_length)).atan()).to_degrees()
+}
+
+/// Diameter in metres of the opening of a lens with `focal_length` in millimetres at `f_number`
+pub fn aperture_from_f_number(focal_length: f32, f_number: f32) -> f32 {
+    focal_length / f_number / 1000.0
+}
+
+/// Samples a point uniformly inside the unit disc. The area within a radius grows with its
+/// square, so the radius is the square root of a uniform number.
 fn random_lens_position(state: &mut WorkerState) -> Vector {
     let [angle, len, ..] = state.gen_random_floats();
 
     let theta = angle * TAU;
 
-    Vector::from_xyz(theta.sin(), theta.cos(), 0.0) * len
+    Vector::from_xyz(theta.sin(), theta.cos(), 0.0) * len.sqrt()
+}
+
+/// Samples a point uniformly inside a regular polygon with `sides` corners on the unit circle
+fn random_polygon_position(sides: u32, state: &mut WorkerState) -> Vector {
+    let [segment, len, along, _] = state.gen_random_floats();
+
+    // Each side spans a triangle with the center. All triangles have the same area, so pick one
+    // uniformly and then sample a point uniformly within it.
+    let idx = ((segment * sides as f32) as u32).min(sides - 1);
+    let step = TAU / sides as f32;
+    let theta0 = idx as f32 * step;
+    let theta1 = theta0 + step;
+
+    let v0 = Vector::from_xyz(theta0.sin(), theta0.cos(), 0.0);
+    let v1 = Vector::from_xyz(theta1.sin(), theta1.cos(), 0.0);
+
+    len.sqrt() * ((1.0 - along) * v0 + along * v1)
 }
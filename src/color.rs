@@ -1,4 +1,7 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign};
+use std::{
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub},
+    str::FromStr,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Color {
@@ -24,29 +27,97 @@ impl Color {
     }
 
     pub fn to_rgb_bytes_8bit(self) -> [u8; 3] {
-        let r = (self.r * 255.0).round() as u8;
-        let g = (self.g * 255.0).round() as u8;
-        let b = (self.b * 255.0).round() as u8;
+        let Self { r, g, b } = self.saturate();
+        let r = (r * 255.0).round() as u8;
+        let g = (g * 255.0).round() as u8;
+        let b = (b * 255.0).round() as u8;
 
         [r, g, b]
     }
 
     pub fn to_rgb_bytes_16bit(self) -> [u8; 6] {
-        let [rh, rl] = ((self.r * 65535.0).round() as u16).to_be_bytes();
-        let [gh, gl] = ((self.g * 65535.0).round() as u16).to_be_bytes();
-        let [bh, bl] = ((self.b * 65535.0).round() as u16).to_be_bytes();
+        let Self { r, g, b } = self.saturate();
+        let [rh, rl] = ((r * 65535.0).round() as u16).to_be_bytes();
+        let [gh, gl] = ((g * 65535.0).round() as u16).to_be_bytes();
+        let [bh, bl] = ((b * 65535.0).round() as u16).to_be_bytes();
 
         [rh, rl, gh, gl, bh, bl]
     }
 
-    pub fn apply_gamma(self) -> Self {
-        fn apply(v: f32) -> f32 {
-            if v <= 0.0031308 {
-                v * 12.92
-            } else {
-                1.055 * v.powf(1.0 / 2.4) - 0.055
-            }
+    /// Clamps every channel to [`min`, `max`], panicking if `min > max`
+    pub fn clamp(self, min: f32, max: f32) -> Self {
+        Self {
+            r: self.r.clamp(min, max),
+            g: self.g.clamp(min, max),
+            b: self.b.clamp(min, max),
         }
+    }
+
+    /// Clamps every channel to [0, 1]
+    pub fn saturate(self) -> Self {
+        self.clamp(0.0, 1.0)
+    }
+
+    /// Creates a color from a hue in degrees and saturation and value in [0, 1]
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let h = hue.rem_euclid(360.0) / 60.0;
+        let c = value * saturation;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let m = value - c;
+
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        Self::from_rgb(r + m, g + m, b + m)
+    }
+
+    /// Returns hue in degrees in [0, 360), saturation and value, the inverse of
+    /// [`Color::from_hsv`]
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == self.r {
+            60.0 * ((self.g - self.b) / delta).rem_euclid(6.0)
+        } else if max == self.g {
+            60.0 * ((self.b - self.r) / delta + 2.0)
+        } else {
+            60.0 * ((self.r - self.g) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+        (hue, saturation, max)
+    }
+
+    /// Relative luminance of a linear color using the Rec. 709 primaries
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn is_finite(self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+
+    pub fn apply_gamma(self) -> Self {
+        self.encode(TransferFunction::Srgb)
+    }
+
+    /// Converts the linear color into the encoding of `transfer`
+    pub fn encode(self, transfer: TransferFunction) -> Self {
+        let apply = |v: f32| match transfer {
+            TransferFunction::Srgb if v <= 0.0031308 => v * 12.92,
+            TransferFunction::Srgb => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+            TransferFunction::Gamma(gamma) => v.max(0.0).powf(1.0 / gamma),
+            TransferFunction::Linear => v,
+        };
 
         Self {
             r: apply(self.r),
@@ -68,6 +139,32 @@ impl Color {
     }
 }
 
+/// Encoding of the color values written to integer image formats
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TransferFunction {
+    #[default]
+    Srgb,
+    /// A pure power curve with the given exponent, e.g. 2.2
+    Gamma(f32),
+    Linear,
+}
+
+impl FromStr for TransferFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "srgb" => Ok(Self::Srgb),
+            None if s == "linear" => Ok(Self::Linear),
+            Some(("gamma", gamma)) => match gamma.parse::<f32>() {
+                Ok(gamma) if gamma > 0.0 && gamma.is_finite() => Ok(Self::Gamma(gamma)),
+                _ => Err(format!("invalid gamma: {gamma}")),
+            },
+            _ => Err(format!("unsupported transfer function: {s}")),
+        }
+    }
+}
+
 impl Add<Self> for Color {
     type Output = Self;
 
@@ -88,6 +185,18 @@ impl AddAssign<Self> for Color {
     }
 }
 
+impl Sub<Self> for Color {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self {
+            r: self.r - rhs.r,
+            g: self.g - rhs.g,
+            b: self.b - rhs.b,
+        }
+    }
+}
+
 impl Mul<f32> for Color {
     type Output = Self;
 
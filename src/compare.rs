@@ -0,0 +1,110 @@
+use std::fmt;
+
+use crate::image::Image;
+
+// Both metrics assume a dynamic range of 1, so HDR values above it count as larger errors
+const PEAK: f32 = 1.0;
+
+const SSIM_WINDOW: u32 = 8;
+
+#[derive(Debug)]
+pub enum CompareError {
+    SizeMismatch {
+        first: (u32, u32),
+        second: (u32, u32),
+    },
+}
+
+impl fmt::Display for CompareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SizeMismatch { first, second } => write!(
+                f,
+                "cannot compare a {}x{} image with a {}x{} image",
+                first.0, first.1, second.0, second.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+fn check_sizes(a: &Image, b: &Image) -> Result<(), CompareError> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(CompareError::SizeMismatch {
+            first: (a.width(), a.height()),
+            second: (b.width(), b.height()),
+        });
+    }
+    Ok(())
+}
+
+/// Peak signal-to-noise ratio in decibels over the linear RGB channels. Identical images give
+/// infinity.
+pub fn psnr(a: &Image, b: &Image) -> Result<f32, CompareError> {
+    check_sizes(a, b)?;
+
+    let sum_squared: f64 = a
+        .pixels
+        .iter()
+        .zip(&b.pixels)
+        .flat_map(|(a, b)| [a.r - b.r, a.g - b.g, a.b - b.b])
+        .map(|diff| (diff as f64) * (diff as f64))
+        .sum();
+    let mse = sum_squared / (a.pixels.len() * 3) as f64;
+
+    Ok((10.0 * (PEAK as f64 * PEAK as f64 / mse).log10()) as f32)
+}
+
+/// Mean structural similarity of the linear luminance over non-overlapping 8x8 windows, where 1
+/// means identical
+pub fn ssim(a: &Image, b: &Image) -> Result<f32, CompareError> {
+    check_sizes(a, b)?;
+
+    let c1 = (0.01 * PEAK as f64).powi(2);
+    let c2 = (0.03 * PEAK as f64).powi(2);
+
+    let width = a.width();
+    let height = a.height();
+    let luminance = |image: &Image, x: u32, y: u32| {
+        image.pixels[y as usize * width as usize + x as usize].luminance() as f64
+    };
+
+    let mut total = 0.0;
+    let mut num_windows = 0;
+    for window_y in (0..height).step_by(SSIM_WINDOW as usize) {
+        for window_x in (0..width).step_by(SSIM_WINDOW as usize) {
+            let xs = window_x..(window_x + SSIM_WINDOW).min(width);
+            let ys = window_y..(window_y + SSIM_WINDOW).min(height);
+            let n = (xs.len() * ys.len()) as f64;
+
+            let (mut sum_a, mut sum_b) = (0.0, 0.0);
+            let (mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0);
+            for y in ys {
+                for x in xs.clone() {
+                    let (la, lb) = (luminance(a, x, y), luminance(b, x, y));
+                    sum_a += la;
+                    sum_b += lb;
+                    sum_aa += la * la;
+                    sum_bb += lb * lb;
+                    sum_ab += la * lb;
+                }
+            }
+
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+                / ((mean_a * mean_a + mean_b * mean_b + c1) * (var_a + var_b + c2));
+            num_windows += 1;
+        }
+    }
+
+    Ok(if num_windows == 0 {
+        1.0
+    } else {
+        (total / num_windows as f64) as f32
+    })
+}
@@ -1,21 +1,35 @@
-use std::{alloc::Layout, ops::Range};
+use std::{
+    alloc::Layout,
+    ops::{ControlFlow, Range},
+    sync::OnceLock,
+    thread,
+};
 
 use bumpalo::Bump;
-use ctor::ctor;
 
 use crate::{
+    material::Material,
     ray::Ray,
     vector::{Dimension, Vector, Vector3x8},
 };
 
 use super::{Hit, Object, ObjectList, aabb::Aabb};
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 enum Node {
     Leaf { offset: u32, length: u16 },
     Branch { idx: u32 },
 }
 
+impl Node {
+    fn offset_branch(self, base: u32) -> Self {
+        match self {
+            Self::Branch { idx } => Self::Branch { idx: idx + base },
+            leaf => leaf,
+        }
+    }
+}
+
 #[repr(align(64))]
 struct Branch {
     aabb_min: Vector3x8,
@@ -29,6 +43,7 @@ pub struct Bvh<L> {
     branches: Box<[Branch]>,
     root: Node,
     max_depth: usize,
+    original_indices: Box<[u32]>,
 }
 
 struct ObjectInfo {
@@ -37,8 +52,41 @@ struct ObjectInfo {
     idx: usize,
 }
 
+pub const DEFAULT_MAX_LEAF_SIZE: usize = 4;
+
+/// Work done by [`Bvh::hit_counted`], including the traversal of any BVHs nested inside the
+/// objects, such as those of meshes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TraversalCounts {
+    pub nodes: u32,
+    pub primitives: u32,
+}
+
+/// The nearest hit found by [`Bvh::trace`]
+pub struct HitInfo<'a> {
+    pub point: Vector,
+    /// Surface normal facing against the ray
+    pub normal: Vector,
+    pub front_face: bool,
+    /// Position of the hit along the ray, which is its distance for unit length directions
+    pub t: f32,
+    pub material: &'a dyn Material,
+    /// Index of the hit object in the list passed to [`Bvh::new`]
+    pub object: usize,
+}
+
 impl<L: ObjectList<Object = O>, O> Bvh<L> {
-    pub fn new(mut object_list: L) -> Self {
+    pub fn new(object_list: L, max_leaf_size: usize) -> Self {
+        Self::with_parallel_threshold(object_list, max_leaf_size, PARALLEL_BUILD_THRESHOLD)
+    }
+
+    fn with_parallel_threshold(
+        mut object_list: L,
+        max_leaf_size: usize,
+        parallel_threshold: usize,
+    ) -> Self {
+        let max_leaf_size = max_leaf_size.clamp(1, u16::MAX.into());
+
         let mut obj_infos: Vec<_> = (0..object_list.len())
             .map(|idx| ObjectInfo {
                 centroid: object_list.centroid(idx),
@@ -49,7 +97,15 @@ impl<L: ObjectList<Object = O>, O> Bvh<L> {
 
         let mut branches = Vec::new();
 
-        let (root, aabb, max_depth) = build(obj_infos.as_mut_slice(), 0, &mut branches);
+        let (root, aabb, max_depth) = build(
+            obj_infos.as_mut_slice(),
+            0,
+            max_leaf_size,
+            parallel_threshold,
+            &mut branches,
+        );
+
+        let original_indices = obj_infos.iter().map(|info| info.idx as u32).collect();
 
         // Reorder objects
         let objects = object_list.objects_mut();
@@ -70,29 +126,156 @@ impl<L: ObjectList<Object = O>, O> Bvh<L> {
             branches: branches.into_boxed_slice(),
             root,
             max_depth,
+            original_indices,
         }
     }
 }
 
 type IntersectionsTest = unsafe fn(Ray, &Vector3x8, &Vector3x8, Range<f32>) -> u8;
 
-#[ctor]
-static INTERSECTIONS_TEST: IntersectionsTest = {
+// Picks the fastest implementation the CPU supports on the first call
+fn intersections_test() -> IntersectionsTest {
+    static FUNC: OnceLock<IntersectionsTest> = OnceLock::new();
+    *FUNC.get_or_init(detect_intersections_test)
+}
+
+fn detect_intersections_test() -> IntersectionsTest {
     #[allow(unused_mut)]
     let mut func: IntersectionsTest = intersections_generic;
 
     #[cfg(target_arch = "x86_64")]
-    if is_x86_feature_detected!("avx") {
+    if is_x86_feature_detected!("avx512f") {
+        func = intersections_x86_avx512;
+    } else if is_x86_feature_detected!("avx") {
         func = intersections_x86_avx;
     } else if is_x86_feature_detected!("sse") {
         func = intersections_x86_sse;
     }
 
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        func = intersections_aarch64_neon;
+    }
+
     func
-};
+}
 
-impl<L: ObjectList<Object = O> + Send + Sync, O> Object for Bvh<L> {
-    fn hit(&self, ray: Ray, mut t_range: Range<f32>, arena: &Bump) -> Option<Hit<'_>> {
+impl<L: ObjectList> Bvh<L> {
+    /// Finds the nearest hit along with the index of the hit object in the (reordered) object
+    /// list. Use [`Bvh::original_index`] to map it back to the index passed to [`Bvh::new`].
+    ///
+    /// `arena` holds the traversal stack and whatever the objects allocate for their hits. It
+    /// can be reset once the returned hit is no longer needed.
+    pub fn hit_indexed(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+    ) -> Option<(Hit<'_>, usize)> {
+        self.nearest_hit::<false>(ray, t_range, arena, &mut TraversalCounts::default())
+    }
+
+    /// Like [`Bvh::hit_indexed`], but also counts the nodes and primitives visited on the way
+    pub fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+    ) -> (Option<(Hit<'_>, usize)>, TraversalCounts) {
+        let mut counts = TraversalCounts::default();
+        let hit = self.nearest_hit::<true>(ray, t_range, arena, &mut counts);
+        (hit, counts)
+    }
+
+    // Only adds to `counts` if `COUNT` is set, so that the uncounted traversal does no extra
+    // work
+    fn nearest_hit<const COUNT: bool>(
+        &self,
+        ray: Ray,
+        mut t_range: Range<f32>,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<(Hit<'_>, usize)> {
+        let mut nearest_hit = None;
+
+        // Nearest hit traversal never stops early
+        let (_, own_counts) = self.traverse::<COUNT>(ray, &mut t_range, arena, |leaf, t_range| {
+            if COUNT {
+                for i in leaf {
+                    let hit = self
+                        .object_list
+                        .hit_counted(ray, t_range.clone(), i, arena, counts);
+                    if let Some(hit) = hit {
+                        t_range.end = hit.t;
+                        nearest_hit = Some((hit, i));
+                    }
+                }
+            } else if let Some((hit, i)) =
+                self.object_list
+                    .hit_nearest(ray, t_range.clone(), leaf, arena)
+            {
+                t_range.end = hit.t;
+                nearest_hit = Some((hit, i));
+            }
+            ControlFlow::Continue(())
+        });
+        counts.nodes += own_counts.nodes;
+        counts.primitives += own_counts.primitives;
+
+        nearest_hit
+    }
+
+    /// Returns whether anything is hit within `t_range`. This stops at the first intersection
+    /// found instead of searching for the nearest one, which is all that shadow and occlusion
+    /// rays need. Objects are asked through [`Object::occludes`], so nested BVHs like those of
+    /// meshes stop early as well.
+    pub fn any_hit(&self, ray: Ray, mut t_range: Range<f32>, arena: &Bump) -> bool {
+        let (flow, _) = self.traverse::<false>(ray, &mut t_range, arena, |leaf, t_range| {
+            for i in leaf {
+                if self.object_list.occludes(ray, t_range.clone(), i, arena) {
+                    return ControlFlow::Break(());
+                }
+            }
+            ControlFlow::Continue(())
+        });
+
+        flow.is_break()
+    }
+
+    /// Returns whether anything blocks the ray before it has travelled `t_max`, e.g. the distance
+    /// to a light. The origin has to be offset from the surface the ray starts on, since hits
+    /// right at it count as well.
+    pub fn occluded(&self, ray: Ray, t_max: f32, arena: &Bump) -> bool {
+        self.any_hit(ray, 0.0..t_max, arena)
+    }
+
+    /// Finds the nearest hit in front of the ray origin, e.g. to pick objects in a viewer or to
+    /// place geometry on surfaces. Every call allocates its own arena, so rendering should use
+    /// [`Bvh::hit_indexed`] instead.
+    pub fn trace(&self, ray: Ray) -> Option<HitInfo<'_>> {
+        let arena = Bump::new();
+        let (hit, index) = self.hit_indexed(ray, 0.0..f32::INFINITY, &arena)?;
+        Some(HitInfo {
+            point: hit.point,
+            normal: hit.normal,
+            front_face: hit.front_face,
+            t: hit.t,
+            material: hit.material,
+            object: self.original_index(index),
+        })
+    }
+
+    /// Calls `visit` with the indices of the objects in every leaf whose bounds the ray
+    /// intersects within `t_range`. `visit` may shrink the range to prune the remaining nodes. With `COUNT`
+    /// set, the nodes and primitives visited are returned as well, not counting those of the
+    /// objects themselves.
+    fn traverse<const COUNT: bool>(
+        &self,
+        ray: Ray,
+        t_range: &mut Range<f32>,
+        arena: &Bump,
+        mut visit: impl FnMut(Range<usize>, &mut Range<f32>) -> ControlFlow<()>,
+    ) -> (ControlFlow<()>, TraversalCounts) {
         let pending_nodes_cap = self.max_depth * 7 + 1;
         let pending_nodes = arena
             .alloc_layout(Layout::array::<Node>(pending_nodes_cap).unwrap())
@@ -103,9 +286,11 @@ impl<L: ObjectList<Object = O> + Send + Sync, O> Object for Bvh<L> {
         }
         let mut pending_nodes_len = 1;
 
-        let mut nearest_hit = None;
+        let intersections_test = intersections_test();
+        let mut flow = ControlFlow::Continue(());
+        let mut counts = TraversalCounts::default();
 
-        loop {
+        'traversal: loop {
             if pending_nodes_len == 0 {
                 break;
             }
@@ -113,24 +298,28 @@ impl<L: ObjectList<Object = O> + Send + Sync, O> Object for Bvh<L> {
                 pending_nodes_len -= 1;
                 pending_nodes.add(pending_nodes_len).read()
             };
+            if COUNT {
+                counts.nodes += 1;
+            }
 
             match node {
                 Node::Leaf { offset, length } => {
                     let start = offset as usize;
                     let end = start + length as usize;
 
-                    for i in start..end {
-                        if let Some(hit) = self.object_list.hit(ray, t_range.clone(), i, arena) {
-                            t_range.end = hit.t;
-                            nearest_hit = Some(hit);
-                        }
+                    if COUNT {
+                        counts.primitives += u32::from(length);
+                    }
+                    flow = visit(start..end, t_range);
+                    if flow.is_break() {
+                        break 'traversal;
                     }
                 }
                 Node::Branch { idx, .. } => {
                     let branch = &self.branches[idx as usize];
 
                     let mut intersections = unsafe {
-                        INTERSECTIONS_TEST(ray, &branch.aabb_min, &branch.aabb_max, t_range.clone())
+                        intersections_test(ray, &branch.aabb_min, &branch.aabb_max, t_range.clone())
                     };
 
                     loop {
@@ -149,7 +338,91 @@ impl<L: ObjectList<Object = O> + Send + Sync, O> Object for Bvh<L> {
             }
         }
 
-        nearest_hit
+        (flow, counts)
+    }
+
+    /// Number of interior nodes, each of which has up to eight children
+    pub fn num_branches(&self) -> usize {
+        self.branches.len()
+    }
+
+    /// Bytes a single traversal takes from the arena for its stack of pending nodes. Traversals
+    /// of nested BVHs, like those of meshes, take their own on top of that.
+    pub fn traversal_stack_size(&self) -> usize {
+        Layout::array::<Node>(self.max_depth * 7 + 1)
+            .unwrap()
+            .size()
+    }
+
+    pub fn original_index(&self, index: usize) -> usize {
+        self.original_indices[index] as usize
+    }
+
+    /// Gives access to the objects in the order of the BVH. [`Bvh::refit`] has to be called
+    /// after changing the bounds of any of them.
+    pub fn objects_mut(&mut self) -> &mut [L::Object] {
+        self.object_list.objects_mut()
+    }
+
+    /// Recomputes the bounds of all nodes from the current object bounds without changing the
+    /// structure of the tree. This is a lot cheaper than building a new BVH, but the tree was
+    /// split for the original object positions, so traversal gets slower the further objects
+    /// move away from them.
+    pub fn refit(&mut self) {
+        self.bounding_box = self.refit_node(self.root);
+    }
+
+    fn refit_node(&mut self, node: Node) -> Aabb {
+        match node {
+            Node::Leaf { offset, length } => {
+                let offset = offset as usize;
+                (offset..offset + length as usize)
+                    .map(|idx| self.object_list.bounding_box(idx))
+                    .reduce(|a, b| a.merge(&b))
+                    .unwrap_or(Aabb::ZERO)
+            }
+            Node::Branch { idx } => {
+                let idx = idx as usize;
+                let mut aabb: Option<Aabb> = None;
+                for (i, child) in self.branches[idx].children.into_iter().enumerate() {
+                    // Unused slots are empty leaves and keep their zero bounds
+                    if let Node::Leaf { length: 0, .. } = child {
+                        continue;
+                    }
+
+                    let child_aabb = self.refit_node(child);
+                    let branch = &mut self.branches[idx];
+                    branch.aabb_min.set_vec(i, child_aabb.minimum.into());
+                    branch.aabb_max.set_vec(i, child_aabb.maximum.into());
+
+                    aabb = aabb
+                        .map(|aabb| aabb.merge(&child_aabb))
+                        .or(Some(child_aabb));
+                }
+                aabb.unwrap_or(Aabb::ZERO)
+            }
+        }
+    }
+}
+
+impl<L: ObjectList<Object = O> + Send + Sync, O> Object for Bvh<L> {
+    fn hit(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> Option<Hit<'_>> {
+        self.hit_indexed(ray, t_range, arena).map(|(hit, _)| hit)
+    }
+
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        self.nearest_hit::<true>(ray, t_range, arena, counts)
+            .map(|(hit, _)| hit)
+    }
+
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> bool {
+        self.any_hit(ray, t_range, arena)
     }
 
     fn bounding_box(&self) -> Aabb {
@@ -313,15 +586,148 @@ unsafe fn intersections_x86_avx(
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+unsafe fn intersections_x86_avx512(
+    ray: Ray,
+    aabb_min: &Vector3x8,
+    aabb_max: &Vector3x8,
+    t_range: Range<f32>,
+) -> u8 {
+    unsafe {
+        use std::arch::x86_64::*;
+
+        // Each register holds the minimum corners of all eight bounding boxes in its lower half
+        // and the maximum corners in its upper half, so both slabs are computed at once.
+        #[inline]
+        #[target_feature(enable = "avx512f")]
+        unsafe fn load_bounds(min: &[f32; 8], max: &[f32; 8]) -> __m512 {
+            unsafe {
+                let min = _mm256_castps_pd(_mm256_load_ps(min.as_ptr()));
+                let max = _mm256_castps_pd(_mm256_load_ps(max.as_ptr()));
+                _mm512_castpd_ps(_mm512_insertf64x4::<1>(_mm512_castpd256_pd512(min), max))
+            }
+        }
+
+        #[inline]
+        #[target_feature(enable = "avx512f")]
+        fn swap_halves(v: __m512) -> __m512 {
+            _mm512_shuffle_f32x4::<0b01_00_11_10>(v, v)
+        }
+
+        let vel_rcp = 1.0 / ray.direction;
+        let vel_rcp_x = _mm512_set1_ps(vel_rcp.x());
+        let vel_rcp_y = _mm512_set1_ps(vel_rcp.y());
+        let vel_rcp_z = _mm512_set1_ps(vel_rcp.z());
+
+        let origin_x = _mm512_set1_ps(ray.origin.x());
+        let origin_y = _mm512_set1_ps(ray.origin.y());
+        let origin_z = _mm512_set1_ps(ray.origin.z());
+
+        let bounds_x = load_bounds(aabb_min.x(), aabb_max.x());
+        let bounds_y = load_bounds(aabb_min.y(), aabb_max.y());
+        let bounds_z = load_bounds(aabb_min.z(), aabb_max.z());
+
+        let t_x = _mm512_mul_ps(_mm512_sub_ps(bounds_x, origin_x), vel_rcp_x);
+        let t_y = _mm512_mul_ps(_mm512_sub_ps(bounds_y, origin_y), vel_rcp_y);
+        let t_z = _mm512_mul_ps(_mm512_sub_ps(bounds_z, origin_z), vel_rcp_z);
+
+        let mut tmin = _mm512_set1_ps(t_range.start);
+        for t in [t_x, t_y, t_z] {
+            let t = _mm512_max_ps(t, tmin);
+            tmin = _mm512_min_ps(t, swap_halves(t));
+        }
+
+        let mut tmax = _mm512_set1_ps(t_range.end);
+        for t in [t_x, t_y, t_z] {
+            let t = _mm512_min_ps(t, tmax);
+            tmax = _mm512_max_ps(t, swap_halves(t));
+        }
+
+        tmax = _mm512_mul_ps(tmax, _mm512_set1_ps(1.0 + 2.0 * gamma(3)));
+        let mask = _mm512_cmp_ps_mask::<_CMP_LE_OQ>(tmin, tmax);
+
+        mask as u8
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn intersections_aarch64_neon(
+    ray: Ray,
+    aabb_min: &Vector3x8,
+    aabb_max: &Vector3x8,
+    t_range: Range<f32>,
+) -> u8 {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        let vel_rcp = 1.0 / ray.direction;
+        let vel_rcp_x = vdupq_n_f32(vel_rcp.x());
+        let vel_rcp_y = vdupq_n_f32(vel_rcp.y());
+        let vel_rcp_z = vdupq_n_f32(vel_rcp.z());
+
+        let origin_x = vdupq_n_f32(ray.origin.x());
+        let origin_y = vdupq_n_f32(ray.origin.y());
+        let origin_z = vdupq_n_f32(ray.origin.z());
+
+        let t_start = vdupq_n_f32(t_range.start);
+        let t_end = vdupq_n_f32(t_range.end);
+
+        let lane_bits = vld1q_u32([1, 2, 4, 8].as_ptr());
+
+        let mut intersections = 0;
+
+        for off in (0..8).step_by(4) {
+            let aabb_min_x = vld1q_f32(aabb_min.x().as_ptr().add(off));
+            let aabb_min_y = vld1q_f32(aabb_min.y().as_ptr().add(off));
+            let aabb_min_z = vld1q_f32(aabb_min.z().as_ptr().add(off));
+
+            let aabb_max_x = vld1q_f32(aabb_max.x().as_ptr().add(off));
+            let aabb_max_y = vld1q_f32(aabb_max.y().as_ptr().add(off));
+            let aabb_max_z = vld1q_f32(aabb_max.z().as_ptr().add(off));
+
+            let t0_x = vmulq_f32(vsubq_f32(aabb_min_x, origin_x), vel_rcp_x);
+            let t0_y = vmulq_f32(vsubq_f32(aabb_min_y, origin_y), vel_rcp_y);
+            let t0_z = vmulq_f32(vsubq_f32(aabb_min_z, origin_z), vel_rcp_z);
+
+            let t1_x = vmulq_f32(vsubq_f32(aabb_max_x, origin_x), vel_rcp_x);
+            let t1_y = vmulq_f32(vsubq_f32(aabb_max_y, origin_y), vel_rcp_y);
+            let t1_z = vmulq_f32(vsubq_f32(aabb_max_z, origin_z), vel_rcp_z);
+
+            // The *nm variants return the non-NaN operand just like f32::min and f32::max, which
+            // keeps the mask identical to the one computed by intersections_generic
+            let mut tmin = t_start;
+            tmin = vminnmq_f32(vmaxnmq_f32(t0_x, tmin), vmaxnmq_f32(t1_x, tmin));
+            tmin = vminnmq_f32(vmaxnmq_f32(t0_y, tmin), vmaxnmq_f32(t1_y, tmin));
+            tmin = vminnmq_f32(vmaxnmq_f32(t0_z, tmin), vmaxnmq_f32(t1_z, tmin));
+
+            let mut tmax = t_end;
+            tmax = vmaxnmq_f32(vminnmq_f32(t0_x, tmax), vminnmq_f32(t1_x, tmax));
+            tmax = vmaxnmq_f32(vminnmq_f32(t0_y, tmax), vminnmq_f32(t1_y, tmax));
+            tmax = vmaxnmq_f32(vminnmq_f32(t0_z, tmax), vminnmq_f32(t1_z, tmax));
+
+            tmax = vmulq_f32(tmax, vdupq_n_f32(1.0 + 2.0 * gamma(3)));
+            let mask = vcleq_f32(tmin, tmax);
+
+            intersections |= (vaddvq_u32(vandq_u32(mask, lane_bits)) as u8) << off;
+        }
+
+        intersections
+    }
+}
+
 fn build(
     objects: &mut [ObjectInfo],
     offset: usize,
+    max_leaf_size: usize,
+    parallel_threshold: usize,
     branches: &mut Vec<Branch>,
 ) -> (Node, Aabb, usize) {
-    if objects.len() <= 1 {
+    if objects.len() <= max_leaf_size {
         build_leaf(objects, offset)
     } else {
-        build_branch(objects, offset, branches)
+        build_branch(objects, offset, max_leaf_size, parallel_threshold, branches)
     }
 }
 
@@ -339,11 +745,18 @@ fn build_leaf(objects: &mut [ObjectInfo], offset: usize) -> (Node, Aabb, usize)
     (child, aabb, 0)
 }
 
+// Nodes with at least this many objects build their children on separate threads, except on
+// wasm, which has no threads
+const PARALLEL_BUILD_THRESHOLD: usize = 1 << 16;
+
 fn build_branch(
     objects: &mut [ObjectInfo],
-    mut offset: usize,
+    offset: usize,
+    max_leaf_size: usize,
+    parallel_threshold: usize,
     branches: &mut Vec<Branch>,
 ) -> (Node, Aabb, usize) {
+    let parallel = cfg!(not(target_arch = "wasm32")) && objects.len() >= parallel_threshold;
     let splits = split8(objects);
 
     let own_idx = branches.len();
@@ -356,21 +769,64 @@ fn build_branch(
         }; 8],
     });
 
+    let mut children = [None; 8];
+    let mut child_offset = offset;
+    let splits = splits.into_iter().filter(|split| !split.is_empty());
+
+    if parallel {
+        thread::scope(|scope| {
+            let handles: Vec<_> = splits
+                .map(|split| {
+                    let split_offset = child_offset;
+                    child_offset += split.len();
+                    scope.spawn(move || {
+                        let mut branches = Vec::new();
+                        let child = build(
+                            split,
+                            split_offset,
+                            max_leaf_size,
+                            parallel_threshold,
+                            &mut branches,
+                        );
+                        (child, branches)
+                    })
+                })
+                .collect();
+
+            // Appending the subtrees in order yields exactly the layout of the serial build
+            for (i, handle) in handles.into_iter().enumerate() {
+                let ((child, child_aabb, child_max_depth), child_branches) = handle.join().unwrap();
+                let base = branches.len() as u32;
+                branches.extend(child_branches.into_iter().map(|mut branch| {
+                    for child in &mut branch.children {
+                        *child = child.offset_branch(base);
+                    }
+                    branch
+                }));
+                children[i] = Some((child.offset_branch(base), child_aabb, child_max_depth));
+            }
+        });
+    } else {
+        for (i, split) in splits.enumerate() {
+            children[i] = Some(build(
+                split,
+                child_offset,
+                max_leaf_size,
+                parallel_threshold,
+                branches,
+            ));
+            child_offset += split.len();
+        }
+    }
+
     let mut max_depth = 0;
     let mut aabb: Option<Aabb> = None;
-    for (i, split) in splits
-        .into_iter()
-        .filter(|split| !split.is_empty())
-        .enumerate()
-    {
-        let (child, child_aabb, child_max_depth) = build(split, offset, branches);
-
+    for (i, (child, child_aabb, child_max_depth)) in children.into_iter().flatten().enumerate() {
         let branch = &mut branches[own_idx];
         branch.aabb_min.set_vec(i, child_aabb.minimum.into());
         branch.aabb_max.set_vec(i, child_aabb.maximum.into());
         branch.children[i] = child;
 
-        offset += split.len();
         aabb = aabb
             .map(|aabb| aabb.merge(&child_aabb))
             .or(Some(child_aabb));
@@ -435,15 +891,15 @@ fn calc_sah(objects: &[ObjectInfo], pos: f32, axis: Dimension) -> f32 {
     let mut right_aabb: Option<Aabb> = None;
 
     for obj in objects {
-        let aabb = obj.bounds;
+        let bounds = obj.bounds;
         let centroid = obj.centroid[axis];
 
         if centroid < pos {
             left_count += 1;
-            left_aabb = left_aabb.map(|aabb| aabb.merge(&aabb)).or(Some(aabb));
+            left_aabb = left_aabb.map(|aabb| aabb.merge(&bounds)).or(Some(bounds));
         } else {
             right_count += 1;
-            right_aabb = right_aabb.map(|aabb| aabb.merge(&aabb)).or(Some(aabb));
+            right_aabb = right_aabb.map(|aabb| aabb.merge(&bounds)).or(Some(bounds));
         }
     }
 
@@ -503,3 +959,262 @@ fn partition<T>(data: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
         data.swap(l, r);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{color::Color, geometry::Sphere, material::Lambertian};
+
+    /// Runs the ray against the eight boxes with every intersection test the CPU supports and
+    /// returns the name and resulting mask of each, starting with the generic one
+    fn intersections_per_backend(
+        ray: Ray,
+        aabb_min: &Vector3x8,
+        aabb_max: &Vector3x8,
+        t_range: Range<f32>,
+    ) -> Vec<(&'static str, u8)> {
+        #[allow(unused_mut)]
+        let mut backends: Vec<(&'static str, IntersectionsTest)> =
+            vec![("generic", intersections_generic)];
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sse") {
+                backends.push(("sse", intersections_x86_sse));
+            }
+            if is_x86_feature_detected!("avx") {
+                backends.push(("avx", intersections_x86_avx));
+            }
+            if is_x86_feature_detected!("avx512f") {
+                backends.push(("avx512f", intersections_x86_avx512));
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            backends.push(("neon", intersections_aarch64_neon));
+        }
+
+        backends
+            .into_iter()
+            .map(|(name, func)| {
+                // Only the detected backends were added
+                let mask = unsafe { func(ray, aabb_min, aabb_max, t_range.clone()) };
+                (name, mask)
+            })
+            .collect()
+    }
+
+    /// Whether both BVHs have the same nodes, bounds and object order
+    fn same_layout<L, M>(a: &Bvh<L>, b: &Bvh<M>) -> bool {
+        fn bounds_bits(v: &Vector3x8) -> [[u32; 3]; 8] {
+            std::array::from_fn(|i| v.get_vec(i).map(f32::to_bits))
+        }
+        a.root == b.root
+            && a.max_depth == b.max_depth
+            && a.original_indices == b.original_indices
+            && a.bounding_box == b.bounding_box
+            && a.branches.len() == b.branches.len()
+            && a.branches.iter().zip(&b.branches).all(|(a, b)| {
+                a.children == b.children
+                    && bounds_bits(&a.aabb_min) == bounds_bits(&b.aabb_min)
+                    && bounds_bits(&a.aabb_max) == bounds_bits(&b.aabb_max)
+            })
+    }
+
+    /// Surface area heuristic cost of splitting objects with the given bounds in two the way
+    /// [`Bvh::new`] does, and of splitting them at the middle of their centroids along the
+    /// axis in which those spread the most instead
+    fn split_costs(bounds: &[Aabb]) -> (f32, f32) {
+        let infos = || -> Vec<_> {
+            bounds
+                .iter()
+                .enumerate()
+                .map(|(idx, &bounds)| ObjectInfo {
+                    centroid: (bounds.minimum + bounds.maximum) * 0.5,
+                    bounds,
+                    idx,
+                })
+                .collect()
+        };
+        // Computed from scratch, so that a broken `calc_sah` can't hide its own mistakes
+        let cost = |(left, right): (&mut [ObjectInfo], &mut [ObjectInfo])| {
+            [left, right]
+                .into_iter()
+                .map(|side| {
+                    let area = side
+                        .iter()
+                        .map(|obj| obj.bounds)
+                        .reduce(|a, b| a.merge(&b))
+                        .map_or(0.0, |aabb| aabb.surface_area());
+                    area * side.len() as f32
+                })
+                .sum::<f32>()
+        };
+
+        let mut objects = infos();
+        let sah = cost(split_sah(&mut objects));
+
+        let mut objects = infos();
+        let (min, max) = objects.iter().fold(
+            (objects[0].centroid, objects[0].centroid),
+            |(min, max), obj| (min.min(obj.centroid), max.max(obj.centroid)),
+        );
+        let axis = (max - min).largest_axis();
+        let middle = (min[axis] + max[axis]) * 0.5;
+        let naive = cost(split_middle(&mut objects, middle, axis));
+
+        (sah, naive)
+    }
+
+    /// A small and a large cluster of boxes, and a far away outlier that moves the middle of all
+    /// centroids into the large cluster
+    fn clustered_bounds() -> Vec<Aabb> {
+        let cube = |x: f32, y: f32| Aabb {
+            minimum: Vector::from_xyz(x - 0.1, y - 0.1, -0.1),
+            maximum: Vector::from_xyz(x + 0.1, y + 0.1, 0.1),
+        };
+        let small = (0..8).map(|i| cube(i as f32 * 0.05, (i % 4) as f32 * 0.05));
+        let large = (0..24).map(|i| cube(10.0 + i as f32 * 0.05, (i % 4) as f32 * 0.05));
+        small.chain(large).chain([cube(21.0, 0.0)]).collect()
+    }
+
+    #[test]
+    fn sah_split_beats_middle_split_on_clusters() {
+        let (sah, middle) = split_costs(&clustered_bounds());
+        assert!(
+            sah < 0.9 * middle,
+            "SAH cost {sah}, middle split cost {middle}"
+        );
+    }
+
+    /// Xorshift generator, so that failures can be reproduced without a dependency
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_f32(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 40) as f32 / (1 << 24) as f32
+        }
+
+        /// Mostly values in [-range, range), with exact zeros and integers mixed in, which put ray
+        /// origins on box planes and give directions infinite reciprocals
+        fn coordinate(&mut self, range: f32) -> f32 {
+            let value = (self.next_f32() * 2.0 - 1.0) * range;
+            match (self.next_f32() * 8.0) as u32 {
+                0 => 0.0,
+                1 => value.round(),
+                _ => value,
+            }
+        }
+
+        fn vector(&mut self, range: f32) -> Vector {
+            Vector::from_xyz(
+                self.coordinate(range),
+                self.coordinate(range),
+                self.coordinate(range),
+            )
+        }
+    }
+
+    #[test]
+    fn intersection_backends_agree() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut num_hitting_rays = 0;
+
+        for _ in 0..20_000 {
+            let (mut aabb_min, mut aabb_max) = (Vector3x8::ZERO, Vector3x8::ZERO);
+            for i in 0..8 {
+                // Unused children have zero bounds, which some nodes mimic
+                if rng.next_f32() < 0.1 {
+                    continue;
+                }
+                let corner = rng.vector(4.0);
+                let size = Vector::from_xyz(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 2.0;
+                aabb_min.set_vec(i, corner.into());
+                aabb_max.set_vec(i, (corner + size).into());
+            }
+
+            // Aimed near one of the boxes, as most random rays would miss them all
+            let target = (rng.next_f32() * 8.0) as usize;
+            let target = (Vector::from(aabb_min.get_vec(target))
+                + Vector::from(aabb_max.get_vec(target)))
+                * 0.5
+                + rng.vector(0.5);
+            let origin = rng.vector(6.0);
+            let mut direction = target - origin;
+            if rng.next_f32() < 0.2 {
+                direction.0[(rng.next_f32() * 3.0) as usize] = 0.0;
+            }
+            let ray = Ray::new(origin, direction);
+            let t_start = if rng.next_f32() < 0.5 { 0.0 } else { 1e-3 };
+            let t_end = if rng.next_f32() < 0.5 {
+                f32::INFINITY
+            } else {
+                rng.next_f32() * 8.0
+            };
+
+            let masks = intersections_per_backend(ray, &aabb_min, &aabb_max, t_start..t_end);
+            let (_, generic) = masks[0];
+            for (name, mask) in &masks[1..] {
+                assert_eq!(
+                    *mask, generic,
+                    "{name} disagrees with the generic test for {ray:?} within {t_start}..{t_end} \
+                     and bounds {aabb_min:?} to {aabb_max:?}"
+                );
+            }
+            num_hitting_rays += (generic != 0) as u32;
+        }
+
+        // Both hits and misses have to be common for the comparison to mean anything
+        assert!(
+            (5_000..15_000).contains(&num_hitting_rays),
+            "{num_hitting_rays} rays hit a box"
+        );
+    }
+
+    #[test]
+    fn parallel_build_matches_serial_build() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let spheres: Vec<_> = (0..1500)
+            .map(|_| (rng.vector(20.0), 0.05 + rng.next_f32() * 0.5))
+            .collect();
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::WHITE,
+            emission: Color::BLACK,
+        });
+        let objects = || -> Vec<_> {
+            spheres
+                .iter()
+                .map(|&(center, radius)| Sphere::new(center, radius, material.clone()))
+                .collect()
+        };
+
+        // Building enough objects to reach the default threshold takes minutes in debug builds, so
+        // the threshold is lowered to have most nodes build their children in parallel
+        let serial = Bvh::with_parallel_threshold(objects(), 4, usize::MAX);
+        let parallel = Bvh::with_parallel_threshold(objects(), 4, 16);
+        assert!(same_layout(&serial, &parallel));
+
+        let arena = Bump::new();
+        let mut num_hits = 0;
+        for _ in 0..1000 {
+            // Aimed near a random sphere, as most random rays would pass between them
+            let (target, _) = spheres[(rng.next_f32() * spheres.len() as f32) as usize];
+            let origin = rng.vector(30.0);
+            let ray = Ray::new(origin, target + rng.vector(0.5) - origin);
+
+            let [serial_hit, parallel_hit] = [&serial, &parallel].map(|bvh| {
+                bvh.hit_indexed(ray, 0.0..f32::INFINITY, &arena)
+                    .map(|(hit, idx)| (hit.t.to_bits(), idx))
+            });
+            assert_eq!(serial_hit, parallel_hit);
+            num_hits += serial_hit.is_some() as u32;
+        }
+        assert!(num_hits > 300, "{num_hits} hits");
+    }
+}
@@ -0,0 +1,216 @@
+use std::{ops::Range, sync::Arc};
+
+use bumpalo::Bump;
+
+use crate::{ray::Ray, vector::Vector};
+
+use super::{Aabb, Hit, Object, TraversalCounts};
+
+/// An affine transform, stored as the rows of its linear part and a translation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    rows: [Vector; 3],
+    translation: Vector,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        rows: [
+            Vector::from_xyz(1.0, 0.0, 0.0),
+            Vector::from_xyz(0.0, 1.0, 0.0),
+            Vector::from_xyz(0.0, 0.0, 1.0),
+        ],
+        translation: Vector::ZERO,
+    };
+
+    pub fn translate(offset: Vector) -> Self {
+        Self {
+            translation: offset,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn scale(factors: Vector) -> Self {
+        Self {
+            rows: [
+                Vector::from_xyz(factors.x(), 0.0, 0.0),
+                Vector::from_xyz(0.0, factors.y(), 0.0),
+                Vector::from_xyz(0.0, 0.0, factors.z()),
+            ],
+            translation: Vector::ZERO,
+        }
+    }
+
+    /// Rotates counterclockwise by `angle` radians around `axis`, which must not be zero
+    pub fn rotate(axis: Vector, angle: f32) -> Self {
+        let [x, y, z, _] = axis.normalize_unchecked().0;
+        let (sin, cos) = angle.sin_cos();
+        let c = 1.0 - cos;
+
+        Self {
+            rows: [
+                Vector::from_xyz(cos + x * x * c, x * y * c - z * sin, x * z * c + y * sin),
+                Vector::from_xyz(y * x * c + z * sin, cos + y * y * c, y * z * c - x * sin),
+                Vector::from_xyz(z * x * c - y * sin, z * y * c + x * sin, cos + z * z * c),
+            ],
+            translation: Vector::ZERO,
+        }
+    }
+
+    /// Returns the transform that applies `self` first and `next` afterwards
+    pub fn then(&self, next: &Self) -> Self {
+        let [a, b, c] = self.rows;
+        let rows = next.rows.map(|row| row.x() * a + row.y() * b + row.z() * c);
+
+        Self {
+            rows,
+            translation: next.transform_point(self.translation),
+        }
+    }
+
+    /// Returns `None` if the transform collapses space onto a plane, line or point
+    pub fn inverse(&self) -> Option<Self> {
+        let [a, b, c] = self.rows;
+        let det = a.dot(b.cross3(c));
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        // The columns of the inverse are the cross products of the rows
+        let columns = [b.cross3(c) / det, c.cross3(a) / det, a.cross3(b) / det];
+        let rows =
+            [0, 1, 2].map(|i| Vector::from_xyz(columns[0].0[i], columns[1].0[i], columns[2].0[i]));
+        let linear = Self {
+            rows,
+            translation: Vector::ZERO,
+        };
+
+        Some(Self {
+            translation: -linear.transform_vector(self.translation),
+            ..linear
+        })
+    }
+
+    pub fn transform_point(&self, point: Vector) -> Vector {
+        self.transform_vector(point) + self.translation
+    }
+
+    pub fn transform_vector(&self, vector: Vector) -> Vector {
+        let [a, b, c] = self.rows;
+        Vector::from_xyz(a.dot(vector), b.dot(vector), c.dot(vector))
+    }
+}
+
+/// Places a shared object into the scene with a transform. A BVH over instances forms the top
+/// level of a two-level hierarchy whose bottom levels, such as mesh BVHs, are built only once no
+/// matter how often they are instanced.
+pub struct Instance {
+    object: Arc<dyn Object>,
+    to_object: Transform,
+    bounding_box: Aabb,
+}
+
+impl Instance {
+    /// Returns `None` if `transform` is not invertible
+    pub fn new(object: Arc<dyn Object>, transform: Transform) -> Option<Self> {
+        let to_object = transform.inverse()?;
+
+        let Aabb { minimum, maximum } = object.bounding_box();
+        let bounding_box = (0..8)
+            .map(|corner| {
+                let pick =
+                    |bit: usize, min: f32, max: f32| if corner & bit == 0 { min } else { max };
+                let point = Vector::from_xyz(
+                    pick(1, minimum.x(), maximum.x()),
+                    pick(2, minimum.y(), maximum.y()),
+                    pick(4, minimum.z(), maximum.z()),
+                );
+                let point = transform.transform_point(point);
+                Aabb {
+                    minimum: point,
+                    maximum: point,
+                }
+            })
+            .reduce(|a, b| a.merge(&b))
+            .unwrap();
+
+        Some(Self {
+            object,
+            to_object,
+            bounding_box,
+        })
+    }
+
+    // Transforms the ray and its range into object space, returning the scale of distances
+    // along it
+    fn to_object_space(&self, ray: Ray, t_range: Range<f32>) -> (Ray, Range<f32>, f32) {
+        // Scaling changes the length of the direction, so distances along the object space ray
+        // differ from the world space ones by that factor
+        let direction = self.to_object.transform_vector(ray.direction);
+        let scale = direction.length();
+        let object_ray = Ray::new(self.to_object.transform_point(ray.origin), direction);
+        (
+            object_ray,
+            t_range.start * scale..t_range.end * scale,
+            scale,
+        )
+    }
+
+    // Transforms the ray into object space for `hit_object` and its hit back into world space
+    fn hit_with<'a>(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        hit_object: impl FnOnce(Ray, Range<f32>) -> Option<Hit<'a>>,
+    ) -> Option<Hit<'a>> {
+        let (object_ray, object_t_range, scale) = self.to_object_space(ray, t_range);
+        let hit = hit_object(object_ray, object_t_range)?;
+
+        // Normals transform with the inverse transpose, which keeps them on their side of the
+        // surface
+        let [a, b, c] = self.to_object.rows;
+        let transform_normal =
+            |n: Vector| (n.x() * a + n.y() * b + n.z() * c).normalize_unchecked();
+        let t = hit.t / scale;
+
+        Some(Hit {
+            point: ray.at(t),
+            normal: transform_normal(hit.normal),
+            geometric_normal: transform_normal(hit.geometric_normal),
+            ray,
+            front_face: hit.front_face,
+            t,
+            uv: hit.uv,
+            material: hit.material,
+        })
+    }
+}
+
+impl Object for Instance {
+    fn hit(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> Option<Hit<'_>> {
+        self.hit_with(ray, t_range, |ray, t_range| {
+            self.object.hit(ray, t_range, arena)
+        })
+    }
+
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        self.hit_with(ray, t_range, |ray, t_range| {
+            self.object.hit_counted(ray, t_range, arena, counts)
+        })
+    }
+
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> bool {
+        let (object_ray, object_t_range, _) = self.to_object_space(ray, t_range);
+        self.object.occludes(object_ray, object_t_range, arena)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+}
@@ -1,20 +1,49 @@
-use std::{ops::Range, sync::Arc};
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Range,
+    sync::Arc,
+};
 
-use aabb::Aabb;
+pub use aabb::Aabb;
 use bumpalo::Bump;
+pub use bvh::{Bvh, TraversalCounts};
+pub use instance::{Instance, Transform};
 pub use sphere::Sphere;
 pub use triangle::TriangleMesh;
+pub use world::World;
 
-use crate::{material::Material, ray::Ray, vector::Vector};
+use crate::{material::Material, ray::Ray, raybow::WorkerState, vector::Vector};
 
 mod aabb;
 pub mod bvh;
+mod instance;
 mod sphere;
 mod triangle;
+mod world;
 
 pub trait Object: Send + Sync {
     fn hit(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> Option<Hit<'_>>;
 
+    /// Like [`Object::hit`], but adds the nodes and primitives visited by any BVH inside the
+    /// object to `counts`. Objects that contain other objects need to forward it, and all others
+    /// can keep the default, which counts nothing.
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        let _ = counts;
+        self.hit(ray, t_range, arena)
+    }
+
+    /// Whether the ray hits anything within `t_range`. Objects that contain a BVH override
+    /// this to stop at the first hit they find instead of searching for the nearest one.
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> bool {
+        self.hit(ray, t_range, arena).is_some()
+    }
+
     fn bounding_box(&self) -> Aabb;
 
     fn centroid(&self) -> Vector {
@@ -23,11 +52,44 @@ pub trait Object: Send + Sync {
     }
 }
 
+/// An emitter that next event estimation can aim rays at. The light it emits is queried
+/// through [`Material::emitted`] on whatever the rays hit.
+pub trait LightSampleable: Send + Sync {
+    /// Picks a direction from `point` towards the light and returns it with its density over
+    /// solid angle. A density of zero means that the light cannot be sampled from `point`.
+    fn sample_toward(&self, point: Vector, state: &mut WorkerState) -> (Vector, f32);
+
+    /// Density over solid angle with which [`LightSampleable::sample_toward`] picks the
+    /// normalized `direction` from `point`
+    fn pdf_toward(&self, point: Vector, direction: Vector) -> f32;
+
+    /// Estimate of the total power the light emits. Next event estimation picks lights in
+    /// proportion to it, so only its ratio to the other lights matters. All lights are equally
+    /// likely by default.
+    fn power(&self) -> f32 {
+        1.0
+    }
+}
+
 impl Object for Box<dyn Object> {
     fn hit(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> Option<Hit<'_>> {
         self.as_ref().hit(ray, t_range, arena)
     }
 
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        self.as_ref().hit_counted(ray, t_range, arena, counts)
+    }
+
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> bool {
+        self.as_ref().occludes(ray, t_range, arena)
+    }
+
     fn bounding_box(&self) -> Aabb {
         self.as_ref().bounding_box()
     }
@@ -38,19 +100,83 @@ impl Object for Arc<dyn Object> {
         self.as_ref().hit(ray, t_range, arena)
     }
 
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        self.as_ref().hit_counted(ray, t_range, arena, counts)
+    }
+
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> bool {
+        self.as_ref().occludes(ray, t_range, arena)
+    }
+
     fn bounding_box(&self) -> Aabb {
         self.as_ref().bounding_box()
     }
 }
 
+/// Indexed storage for the objects of a [`Bvh`]. Implement this instead of [`Object`] when
+/// the objects are not separate values, like the triangles of a [`TriangleMesh`] which share
+/// one vertex buffer.
+///
+/// Indices range from 0 to [`ObjectList::len`]. [`Bvh::new`] reorders the objects through
+/// [`ObjectList::objects_mut`] so that every leaf covers a contiguous range of indices, so the
+/// index of an object must follow its position in that slice.
 #[allow(clippy::len_without_is_empty)]
 pub trait ObjectList {
     type Object;
 
+    /// Intersects the ray with the object at `index`, returning a hit only if it lies within
+    /// `t_range`
     fn hit(&self, ray: Ray, t_range: Range<f32>, index: usize, arena: &Bump) -> Option<Hit<'_>>;
 
+    /// Like [`ObjectList::hit`], but counts the BVH traversal inside the object like
+    /// [`Object::hit_counted`]
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        index: usize,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        let _ = counts;
+        self.hit(ray, t_range, index, arena)
+    }
+
+    /// Like [`ObjectList::hit`], but only tells whether the object is hit, like
+    /// [`Object::occludes`]
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, index: usize, arena: &Bump) -> bool {
+        self.hit(ray, t_range, index, arena).is_some()
+    }
+
+    /// Finds the nearest hit among the objects in `indices`, such as those of a BVH leaf, along
+    /// with the index of the hit object. Lists that can test several objects at once override
+    /// this, but have to return the same hit as testing them one after another.
+    fn hit_nearest(
+        &self,
+        ray: Ray,
+        mut t_range: Range<f32>,
+        indices: Range<usize>,
+        arena: &Bump,
+    ) -> Option<(Hit<'_>, usize)> {
+        let mut nearest_hit = None;
+        for index in indices {
+            if let Some(hit) = self.hit(ray, t_range.clone(), index, arena) {
+                t_range.end = hit.t;
+                nearest_hit = Some((hit, index));
+            }
+        }
+        nearest_hit
+    }
+
     fn bounding_box(&self, index: usize) -> Aabb;
 
+    /// Position used to sort the object into the BVH. Defaults to the center of its bounds.
     fn centroid(&self, index: usize) -> Vector {
         let bounds = self.bounding_box(index);
         (bounds.minimum + bounds.maximum) * 0.5
@@ -68,6 +194,21 @@ impl<O: Object> ObjectList for Vec<O> {
         self[index].hit(ray, t_range, arena)
     }
 
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        index: usize,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        self[index].hit_counted(ray, t_range, arena, counts)
+    }
+
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, index: usize, arena: &Bump) -> bool {
+        self[index].occludes(ray, t_range, arena)
+    }
+
     fn bounding_box(&self, index: usize) -> Aabb {
         self[index].bounding_box()
     }
@@ -87,10 +228,15 @@ impl<O: Object> ObjectList for Vec<O> {
 
 pub struct Hit<'m> {
     pub point: Vector,
+    /// Unit normal facing against the ray, which shading works with
     pub normal: Vector,
+    /// Unit normal as the object defines it, e.g. outward for spheres and counterclockwise
+    /// for triangles, which faces away from the ray on back faces
+    pub geometric_normal: Vector,
     pub ray: Ray,
     pub front_face: bool,
     pub t: f32,
+    pub uv: Uv,
     pub material: &'m dyn Material,
 }
 
@@ -102,6 +248,7 @@ impl<'m> Hit<'m> {
         t: f32,
         material: &'m dyn Material,
     ) -> Self {
+        let geometric_normal = normal;
         let (normal, front_face) = if ray.direction.dot(normal) < 0.0 {
             (normal, true)
         } else {
@@ -110,10 +257,37 @@ impl<'m> Hit<'m> {
         Self {
             point,
             normal,
+            geometric_normal,
             ray,
             front_face,
             t,
+            uv: Uv::Planar([0.0; 2]),
             material,
         }
     }
+
+    pub fn with_uv(self, uv: Uv) -> Self {
+        Self { uv, ..self }
+    }
+}
+
+/// Texture coordinates of a hit point
+#[derive(Debug, Clone, Copy)]
+pub enum Uv {
+    Planar([f32; 2]),
+    /// Longitude and latitude of the given unit direction from the center of a sphere, which are
+    /// only computed when looked up since most hits never are
+    Spherical(Vector),
+}
+
+impl Uv {
+    pub fn coordinates(self) -> [f32; 2] {
+        match self {
+            Self::Planar(uv) => uv,
+            Self::Spherical(direction) => [
+                0.5 + direction.z().atan2(direction.x()) / TAU,
+                direction.y().clamp(-1.0, 1.0).asin() / PI + 0.5,
+            ],
+        }
+    }
 }
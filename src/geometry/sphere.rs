@@ -1,10 +1,19 @@
-use std::{ops::Range, sync::Arc};
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Range,
+    sync::Arc,
+};
 
 use bumpalo::Bump;
 
-use crate::{material::Material, ray::Ray, vector::Vector};
+use crate::{
+    material::{Material, orthonormal_basis},
+    ray::Ray,
+    raybow::WorkerState,
+    vector::Vector,
+};
 
-use super::{Aabb, Hit, Object};
+use super::{Aabb, Hit, LightSampleable, Object, Uv};
 
 pub struct Sphere {
     center: Vector,
@@ -13,26 +22,98 @@ pub struct Sphere {
 }
 
 impl Sphere {
+    /// A negative radius turns the sphere inside out: its normals point towards the center, so
+    /// rays from outside hit a back face. Inside a glass sphere, this makes a hollow bubble
+    /// whose surface refracts like leaving the glass.
+    ///
+    /// Panics if `radius` is zero or not finite.
     pub fn new(center: Vector, radius: f32, material: Arc<dyn Material>) -> Self {
+        assert!(
+            radius != 0.0 && radius.is_finite(),
+            "sphere radius must be finite and non-zero"
+        );
         Self {
             center,
             radius,
             material,
         }
     }
+
+    /// Axis of the cone in which the sphere appears from `point`, along with the cosine of its
+    /// half-angle and one minus that, or `None` if `point` lies inside the sphere
+    fn visible_cone(&self, point: Vector) -> Option<(Vector, f32, f32)> {
+        let to_center = self.center - point;
+        let dist2 = to_center.length_squared();
+        let r2 = self.radius * self.radius;
+        if dist2 <= r2 {
+            return None;
+        }
+        let sin2_max = r2 / dist2;
+        let cos_max = (1.0 - sin2_max).sqrt();
+        // Rewritten to stay accurate for small and distant spheres
+        let one_minus_cos_max = sin2_max / (1.0 + cos_max);
+        Some((to_center / dist2.sqrt(), cos_max, one_minus_cos_max))
+    }
+}
+
+impl LightSampleable for Sphere {
+    // Sampling the visible cone rather than the surface wastes no samples on the far side,
+    // which matters most for small lights
+    fn sample_toward(&self, point: Vector, state: &mut WorkerState) -> (Vector, f32) {
+        let Some((axis, _, one_minus_cos_max)) = self.visible_cone(point) else {
+            return (Vector::ZERO, 0.0);
+        };
+        let [u1, u2, ..] = state.gen_random_floats();
+
+        let cos_theta = 1.0 - u1 * one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = u2 * TAU;
+
+        let (tangent, bitangent) = orthonormal_basis(axis);
+        let direction = tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + axis * cos_theta;
+        (direction, 1.0 / (TAU * one_minus_cos_max))
+    }
+
+    fn pdf_toward(&self, point: Vector, direction: Vector) -> f32 {
+        match self.visible_cone(point) {
+            Some((axis, cos_max, one_minus_cos_max)) if direction.dot(axis) >= cos_max => {
+                1.0 / (TAU * one_minus_cos_max)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // The emission seen when looking straight down onto the top of the sphere stands in for
+    // the whole surface, which emits with a cosine falloff over the hemisphere
+    fn power(&self) -> f32 {
+        let up = Vector::from_xyz(0.0, 1.0, 0.0);
+        let point = self.center + up * self.radius.abs();
+        let ray = Ray::new(point + up, -up);
+        // An inside-out sphere faces its emission inwards
+        let normal = up * self.radius.signum();
+        let hit = Hit::new(point, normal, ray, 1.0, &*self.material);
+        let area = 2.0 * TAU * self.radius * self.radius;
+        self.material.emitted(&hit).luminance() * PI * area
+    }
 }
 
 impl Object for Sphere {
     fn hit(&self, ray: Ray, t_range: Range<f32>, _: &Bump) -> Option<Hit<'_>> {
         let oc = self.center - ray.origin;
+        let oc2 = oc.length_squared();
         let tca = oc.dot(ray.direction);
-        if tca < 0.0 {
+
+        let r2 = self.radius * self.radius;
+
+        // A ray starting inside the sphere always has an exit intersection, even when it points
+        // away from the center
+        if tca < 0.0 && oc2 > r2 {
             return None;
         }
 
-        let d2 = oc.length_squared() - tca * tca;
-
-        let r2 = self.radius * self.radius;
+        let d2 = oc2 - tca * tca;
         if d2 > r2 {
             return None;
         }
@@ -50,19 +131,23 @@ impl Object for Sphere {
 
         let point = ray.at(t);
 
-        Some(Hit::new(
-            point,
-            (point - self.center) / self.radius,
-            ray,
-            t,
-            &*self.material,
-        ))
+        Some(
+            Hit::new(
+                point,
+                (point - self.center) / self.radius,
+                ray,
+                t,
+                &*self.material,
+            )
+            .with_uv(Uv::Spherical((point - self.center) / self.radius.abs())),
+        )
     }
 
     fn bounding_box(&self) -> Aabb {
+        let radius = self.radius.abs();
         Aabb {
-            minimum: self.center - Vector::from_xyz(self.radius, self.radius, self.radius),
-            maximum: self.center + Vector::from_xyz(self.radius, self.radius, self.radius),
+            minimum: self.center - Vector::from_xyz(radius, radius, radius),
+            maximum: self.center + Vector::from_xyz(radius, radius, radius),
         }
     }
 }
@@ -1,28 +1,199 @@
-use std::{ops::Range, sync::Arc};
+use std::{
+    ops::Range,
+    sync::{Arc, OnceLock},
+};
 
 use bumpalo::Bump;
 
 use crate::{material::Material, ray::Ray, vector::Vector};
 
-use super::{Hit, ObjectList, aabb::Aabb};
+use super::{Hit, ObjectList, Uv, aabb::Aabb};
 
 pub struct TriangleMesh {
     triangles: Box<[Triangle]>,
     vertices: Box<[f32]>,
-    material: Arc<dyn Material>,
+    // Texture coordinates as consecutive uv pairs, one per vertex
+    uvs: Option<Box<[f32]>>,
+    materials: Box<[Arc<dyn Material>]>,
+    cull_back_faces: bool,
+    num_degenerate: usize,
+    // Built on the first batched test, after the BVH has put the triangles in their final order
+    vertex_planes: OnceLock<VertexPlanes>,
 }
 
 impl TriangleMesh {
     pub fn new(vertices: Box<[f32]>, indices: Box<[u32]>, material: Arc<dyn Material>) -> Self {
+        Self::with_materials(vertices, indices, None, Box::new([material]))
+    }
+
+    /// Creates a mesh where the i-th triangle uses `materials[material_ids[i]]`. Without
+    /// `material_ids` every triangle uses the first material.
+    ///
+    /// Triangles without area, whose vertices coincide or lie on a line, can't be hit and have
+    /// no normal, so they are left out.
+    pub fn with_materials(
+        vertices: Box<[f32]>,
+        indices: Box<[u32]>,
+        material_ids: Option<Box<[u32]>>,
+        materials: Box<[Arc<dyn Material>]>,
+    ) -> Self {
         assert!(indices.len().is_multiple_of(3));
+        assert!(!materials.is_empty());
 
-        let triangles = bytemuck::cast_slice_box(indices);
+        let indices: &[[u32; 3]] = bytemuck::cast_slice(&indices);
 
-        Self {
-            triangles,
+        if let Some(material_ids) = &material_ids {
+            assert_eq!(material_ids.len(), indices.len());
+            assert!(
+                material_ids
+                    .iter()
+                    .all(|&id| (id as usize) < materials.len())
+            );
+        }
+
+        let mut mesh = Self {
+            triangles: Box::new([]),
             vertices,
-            material,
+            uvs: None,
+            materials,
+            cull_back_faces: false,
+            num_degenerate: 0,
+            vertex_planes: OnceLock::new(),
+        };
+
+        let triangles: Box<[_]> = indices
+            .iter()
+            .enumerate()
+            .map(|(i, &indices)| Triangle {
+                indices,
+                material: material_ids.as_ref().map_or(0, |ids| ids[i]),
+            })
+            .filter(|triangle| {
+                let [p1, p2, p3] = triangle.indices.map(|i| mesh.fetch_vertex(i));
+                let area = (p2 - p1).cross3(p3 - p1).length_squared();
+                // Also catches NaN coordinates and areas too small to be represented
+                area > 0.0 && area.is_finite()
+            })
+            .collect();
+
+        mesh.num_degenerate = indices.len() - triangles.len();
+        mesh.triangles = triangles;
+        mesh
+    }
+
+    /// Sets the texture coordinates as one uv pair per vertex. Without them, hits report the
+    /// barycentric coordinates of the second and third vertex of their triangle.
+    pub fn with_uvs(mut self, uvs: Box<[f32]>) -> Self {
+        assert_eq!(
+            uvs.len() * 3,
+            self.vertices.len() * 2,
+            "expected one uv pair per vertex"
+        );
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Lets rays pass through triangles from behind, where the winding of their vertices is
+    /// clockwise. This suits closed opaque meshes, which are only ever seen from outside.
+    pub fn with_back_face_culling(mut self) -> Self {
+        self.cull_back_faces = true;
+        self
+    }
+
+    /// Number of triangles left out because they have no area
+    pub fn num_degenerate(&self) -> usize {
+        self.num_degenerate
+    }
+
+    // `ObjectList::hit_nearest` with the given batched test, so that all of them can be tested
+    fn hit_nearest_with(
+        &self,
+        triangles_test: TrianglesTest,
+        ray: Ray,
+        mut t_range: Range<f32>,
+        indices: Range<usize>,
+        arena: &Bump,
+    ) -> Option<(Hit<'_>, usize)> {
+        assert!(
+            indices.end <= self.triangles.len(),
+            "triangle index out of bounds"
+        );
+
+        let projection = RayProjection::new(ray);
+        let planes = self.vertex_planes.get_or_init(|| VertexPlanes::new(self));
+
+        // The hit is only built for the nearest triangle, from the range it was found in
+        let mut nearest = None;
+
+        for start in indices.clone().step_by(8) {
+            let num_lanes = (indices.end - start).min(8);
+            // Every index is in bounds and the planes are padded, so the eight triangles from
+            // `start` on can always be loaded
+            let lanes = unsafe { triangles_test(&projection, planes, start) };
+
+            let mut candidates = lanes.candidates & (u8::MAX >> (8 - num_lanes));
+            while candidates != 0 {
+                let lane = candidates.trailing_zeros() as usize;
+                candidates ^= 1 << lane;
+
+                let e = lanes.edges.map(|e| e[lane]);
+                let t = if e.contains(&0.0) {
+                    // Needs the edge functions in double precision
+                    self.hit(ray, t_range.clone(), start + lane, arena)
+                        .map(|hit| hit.t)
+                } else {
+                    self.solve(e, lanes.t_scaled[lane], projection.shear[2], &t_range)
+                };
+
+                if let Some(t) = t {
+                    nearest = Some((start + lane, t_range.clone()));
+                    t_range.end = t;
+                }
+            }
         }
+
+        let (index, t_range) = nearest?;
+        let hit = self.hit(ray, t_range, index, arena)?;
+        Some((hit, index))
+    }
+
+    // Finishes the test of a triangle from its edge functions and the hit distance scaled by
+    // their sum, returning the distance if the triangle is hit within `t_range`
+    fn solve(
+        &self,
+        [e1, e2, e3]: [f32; 3],
+        t_scaled: f32,
+        sz: f32,
+        t_range: &Range<f32>,
+    ) -> Option<f32> {
+        if (e1 < 0.0 || e2 < 0.0 || e3 < 0.0) && (e1 > 0.0 || e2 > 0.0 || e3 > 0.0) {
+            return None;
+        }
+
+        let det = e1 + e2 + e3;
+        if det == 0.0 {
+            return None;
+        }
+
+        // The determinant is the dot product of the ray direction and the unnormalized normal
+        // divided by the direction's component along the projection axis, so together with
+        // that component's sign it tells which side is hit
+        if self.cull_back_faces && (det > 0.0) == (sz > 0.0) {
+            return None;
+        }
+
+        if (det < 0.0 && (t_scaled >= 0.0 || t_scaled < t_range.end * det))
+            || (det > 0.0 && (t_scaled <= 0.0 || t_scaled > t_range.end * det))
+        {
+            return None;
+        }
+
+        let t = t_scaled / det;
+        if t < t_range.start || t > t_range.end {
+            return None;
+        }
+
+        Some(t)
     }
 
     fn fetch_vertices(&self, triangle_index: usize) -> [Vector; 3] {
@@ -44,24 +215,10 @@ impl ObjectList for TriangleMesh {
     type Object = Triangle;
 
     fn hit(&self, ray: Ray, t_range: Range<f32>, index: usize, _: &Bump) -> Option<Hit<'_>> {
-        // https://jcgt.org/published/0002/01/05/paper.pdf
-
-        let ray_dir = ray.direction;
-
-        let kz = ray.direction.abs().largest_axis() as usize;
-        let kx = (kz + 1) % 3;
-        let ky = (kx + 1) % 3;
-
-        let sz = 1.0 / ray_dir[kz];
-        let sx = -ray_dir[kx] * sz;
-        let sy = -ray_dir[ky] * sz;
+        let projection = RayProjection::new(ray);
 
         let [p1, p2, p3] = self.fetch_vertices(index);
-
-        let [p1t, p2t, p3t] = [p1, p2, p3]
-            .map(|p| p - ray.origin)
-            .map(|p| Vector::from_xyz(p[kx], p[ky], p[kz]))
-            .map(|p| Vector::from_xyz(p.x() + sx * p.z(), p.y() + sy * p.z(), p.z() * sz));
+        let [p1t, p2t, p3t] = [p1, p2, p3].map(|p| projection.apply(p));
 
         let mut e1 = p2t.x() * p3t.y() - p3t.x() * p2t.y();
         let mut e2 = p3t.x() * p1t.y() - p1t.x() * p3t.y();
@@ -73,37 +230,43 @@ impl ObjectList for TriangleMesh {
             e3 = (p1t.x() as f64 * p2t.y() as f64 - p2t.x() as f64 * p1t.y() as f64) as f32;
         }
 
-        if (e1 < 0.0 || e2 < 0.0 || e3 < 0.0) && (e1 > 0.0 || e2 > 0.0 || e3 > 0.0) {
-            return None;
-        }
-
-        let det = e1 + e2 + e3;
-        if det == 0.0 {
-            return None;
-        }
-
         let t_scaled = e1 * p1t.z() + e2 * p2t.z() + e3 * p3t.z();
-        if (det < 0.0 && (t_scaled >= 0.0 || t_scaled < t_range.end * det))
-            || (det > 0.0 && (t_scaled <= 0.0 || t_scaled > t_range.end * det))
-        {
-            return None;
-        }
+        let t = self.solve([e1, e2, e3], t_scaled, projection.shear[2], &t_range)?;
 
-        let inv_det = 1.0 / det;
+        let inv_det = 1.0 / (e1 + e2 + e3);
         let b1 = e1 * inv_det;
         let b2 = e2 * inv_det;
         let b3 = e3 * inv_det;
 
         let point = b1 * p1 + b2 * p2 + b3 * p3;
 
-        let t = t_scaled / det;
-
-        if t < t_range.start || t > t_range.end {
-            return None;
-        }
+        let uv = match &self.uvs {
+            Some(uvs) => {
+                let [uv1, uv2, uv3] = self.triangles[index]
+                    .indices
+                    .map(|i| [uvs[i as usize * 2], uvs[i as usize * 2 + 1]]);
+                [
+                    b1 * uv1[0] + b2 * uv2[0] + b3 * uv3[0],
+                    b1 * uv1[1] + b2 * uv2[1] + b3 * uv3[1],
+                ]
+            }
+            None => [b2, b3],
+        };
 
         let normal = (p2 - p1).cross3(p3 - p1).normalize_unchecked();
-        Some(Hit::new(point, normal, ray, t, self.material.as_ref()))
+        let material = &self.materials[self.triangles[index].material as usize];
+        Some(Hit::new(point, normal, ray, t, material.as_ref()).with_uv(Uv::Planar(uv)))
+    }
+
+    // Tests eight triangles at a time, which gives the same hit as testing them one by one
+    fn hit_nearest(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        indices: Range<usize>,
+        arena: &Bump,
+    ) -> Option<(Hit<'_>, usize)> {
+        self.hit_nearest_with(triangles_test(), ray, t_range, indices, arena)
     }
 
     fn bounding_box(&self, index: usize) -> Aabb {
@@ -116,6 +279,8 @@ impl ObjectList for TriangleMesh {
     }
 
     fn objects_mut(&mut self) -> &mut [Self::Object] {
+        // The triangles may be reordered
+        self.vertex_planes = OnceLock::new();
         &mut self.triangles
     }
 
@@ -128,4 +293,342 @@ impl ObjectList for TriangleMesh {
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Triangle {
     indices: [u32; 3],
+    material: u32,
+}
+
+// The positions of the triangles' vertices, with one array per vertex and axis, so that those of
+// consecutive triangles can be loaded into a SIMD register at once
+struct VertexPlanes([[Box<[f32]>; 3]; 3]);
+
+impl VertexPlanes {
+    // Seven unused elements at the end allow loading eight triangles from any of them on
+    const PADDING: usize = 7;
+
+    fn new(mesh: &TriangleMesh) -> Self {
+        let len = mesh.triangles.len() + Self::PADDING;
+        let mut planes: [[Vec<f32>; 3]; 3] = Default::default();
+        for vertex in planes.iter_mut() {
+            for plane in vertex.iter_mut() {
+                plane.reserve_exact(len);
+            }
+        }
+
+        for index in 0..mesh.triangles.len() {
+            for (vertex, position) in planes.iter_mut().zip(mesh.fetch_vertices(index)) {
+                for (axis, plane) in vertex.iter_mut().enumerate() {
+                    plane.push(position[axis]);
+                }
+            }
+        }
+
+        Self(planes.map(|vertex| {
+            vertex.map(|mut plane| {
+                plane.resize(len, 0.0);
+                plane.into_boxed_slice()
+            })
+        }))
+    }
+
+    fn load(&self, vertex: usize, axis: usize, index: usize) -> f32 {
+        self.0[vertex][axis][index]
+    }
+}
+
+// Moves the ray origin to zero and shears space so that the ray points along the z axis, which
+// turns the intersection into a 2d test (https://jcgt.org/published/0002/01/05/paper.pdf)
+#[derive(Clone, Copy)]
+struct RayProjection {
+    origin: Vector,
+    // The axes that become x, y and z, with the ray direction's largest component along z
+    axes: [usize; 3],
+    shear: [f32; 3],
+}
+
+impl RayProjection {
+    fn new(ray: Ray) -> Self {
+        let ray_dir = ray.direction;
+
+        let kz = ray_dir.abs().largest_axis() as usize;
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+
+        let sz = 1.0 / ray_dir[kz];
+        let sx = -ray_dir[kx] * sz;
+        let sy = -ray_dir[ky] * sz;
+
+        Self {
+            origin: ray.origin,
+            axes: [kx, ky, kz],
+            shear: [sx, sy, sz],
+        }
+    }
+
+    fn apply(&self, p: Vector) -> Vector {
+        let [kx, ky, kz] = self.axes;
+        let [sx, sy, sz] = self.shear;
+        let p = p - self.origin;
+        let p = Vector::from_xyz(p[kx], p[ky], p[kz]);
+        Vector::from_xyz(p.x() + sx * p.z(), p.y() + sy * p.z(), p.z() * sz)
+    }
+}
+
+// Edge functions and scaled hit distances of eight consecutive triangles, computed like in
+// `TriangleMesh::hit`
+struct Lanes {
+    edges: [[f32; 8]; 3],
+    t_scaled: [f32; 8],
+    // Triangles that may be hit, because their edge functions don't have different signs or
+    // one of them is zero and has to be computed again in double precision
+    candidates: u8,
+}
+
+type TrianglesTest = unsafe fn(&RayProjection, &VertexPlanes, usize) -> Lanes;
+
+// Picks the fastest implementation the CPU supports on the first call
+fn triangles_test() -> TrianglesTest {
+    static FUNC: OnceLock<TrianglesTest> = OnceLock::new();
+    *FUNC.get_or_init(detect_triangles_test)
+}
+
+fn detect_triangles_test() -> TrianglesTest {
+    #[allow(unused_mut)]
+    let mut func: TrianglesTest = triangles_generic;
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx") {
+        func = triangles_x86_avx;
+    }
+
+    func
+}
+
+fn triangles_generic(projection: &RayProjection, planes: &VertexPlanes, start: usize) -> Lanes {
+    let mut lanes = Lanes {
+        edges: [[0.0; 8]; 3],
+        t_scaled: [0.0; 8],
+        candidates: 0,
+    };
+
+    for lane in 0..8 {
+        let [p1t, p2t, p3t] = [0, 1, 2].map(|vertex| {
+            let [x, y, z] = [0, 1, 2].map(|axis| planes.load(vertex, axis, start + lane));
+            projection.apply(Vector::from_xyz(x, y, z))
+        });
+
+        let e1 = p2t.x() * p3t.y() - p3t.x() * p2t.y();
+        let e2 = p3t.x() * p1t.y() - p1t.x() * p3t.y();
+        let e3 = p1t.x() * p2t.y() - p2t.x() * p1t.y();
+
+        lanes.edges[0][lane] = e1;
+        lanes.edges[1][lane] = e2;
+        lanes.edges[2][lane] = e3;
+        lanes.t_scaled[lane] = e1 * p1t.z() + e2 * p2t.z() + e3 * p3t.z();
+
+        let any_negative = e1 < 0.0 || e2 < 0.0 || e3 < 0.0;
+        let any_positive = e1 > 0.0 || e2 > 0.0 || e3 > 0.0;
+        let any_zero = e1 == 0.0 || e2 == 0.0 || e3 == 0.0;
+        if !(any_negative && any_positive) || any_zero {
+            lanes.candidates |= 1 << lane;
+        }
+    }
+
+    lanes
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn triangles_x86_avx(
+    projection: &RayProjection,
+    planes: &VertexPlanes,
+    start: usize,
+) -> Lanes {
+    unsafe {
+        use std::arch::x86_64::*;
+
+        let [kx, ky, kz] = projection.axes;
+        let sx = _mm256_set1_ps(projection.shear[0]);
+        let sy = _mm256_set1_ps(projection.shear[1]);
+        let sz = _mm256_set1_ps(projection.shear[2]);
+
+        let origin_x = _mm256_set1_ps(projection.origin[kx]);
+        let origin_y = _mm256_set1_ps(projection.origin[ky]);
+        let origin_z = _mm256_set1_ps(projection.origin[kz]);
+
+        let mut transformed = [[_mm256_setzero_ps(); 3]; 3];
+        for (vertex, planes) in transformed.iter_mut().zip(&planes.0) {
+            debug_assert!(start + 8 <= planes[0].len());
+            let x = _mm256_sub_ps(_mm256_loadu_ps(planes[kx].as_ptr().add(start)), origin_x);
+            let y = _mm256_sub_ps(_mm256_loadu_ps(planes[ky].as_ptr().add(start)), origin_y);
+            let z = _mm256_sub_ps(_mm256_loadu_ps(planes[kz].as_ptr().add(start)), origin_z);
+
+            *vertex = [
+                _mm256_add_ps(x, _mm256_mul_ps(sx, z)),
+                _mm256_add_ps(y, _mm256_mul_ps(sy, z)),
+                _mm256_mul_ps(z, sz),
+            ];
+        }
+        let [[p1x, p1y, p1z], [p2x, p2y, p2z], [p3x, p3y, p3z]] = transformed;
+
+        let e1 = _mm256_sub_ps(_mm256_mul_ps(p2x, p3y), _mm256_mul_ps(p3x, p2y));
+        let e2 = _mm256_sub_ps(_mm256_mul_ps(p3x, p1y), _mm256_mul_ps(p1x, p3y));
+        let e3 = _mm256_sub_ps(_mm256_mul_ps(p1x, p2y), _mm256_mul_ps(p2x, p1y));
+
+        let t_scaled = _mm256_add_ps(
+            _mm256_add_ps(_mm256_mul_ps(e1, p1z), _mm256_mul_ps(e2, p2z)),
+            _mm256_mul_ps(e3, p3z),
+        );
+
+        let zero = _mm256_setzero_ps();
+        let any_negative = _mm256_or_ps(
+            _mm256_or_ps(
+                _mm256_cmp_ps(e1, zero, _CMP_LT_OQ),
+                _mm256_cmp_ps(e2, zero, _CMP_LT_OQ),
+            ),
+            _mm256_cmp_ps(e3, zero, _CMP_LT_OQ),
+        );
+        let any_positive = _mm256_or_ps(
+            _mm256_or_ps(
+                _mm256_cmp_ps(e1, zero, _CMP_GT_OQ),
+                _mm256_cmp_ps(e2, zero, _CMP_GT_OQ),
+            ),
+            _mm256_cmp_ps(e3, zero, _CMP_GT_OQ),
+        );
+        let any_zero = _mm256_or_ps(
+            _mm256_or_ps(
+                _mm256_cmp_ps(e1, zero, _CMP_EQ_OQ),
+                _mm256_cmp_ps(e2, zero, _CMP_EQ_OQ),
+            ),
+            _mm256_cmp_ps(e3, zero, _CMP_EQ_OQ),
+        );
+        let missed = _mm256_movemask_ps(_mm256_and_ps(any_negative, any_positive)) as u8;
+
+        let mut lanes = Lanes {
+            edges: [[0.0; 8]; 3],
+            t_scaled: [0.0; 8],
+            candidates: !missed | _mm256_movemask_ps(any_zero) as u8,
+        };
+        _mm256_storeu_ps(lanes.edges[0].as_mut_ptr(), e1);
+        _mm256_storeu_ps(lanes.edges[1].as_mut_ptr(), e2);
+        _mm256_storeu_ps(lanes.edges[2].as_mut_ptr(), e3);
+        _mm256_storeu_ps(lanes.t_scaled.as_mut_ptr(), t_scaled);
+
+        lanes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian};
+
+    /// Finds the nearest hit among the triangles in `indices` with every batched test the CPU
+    /// supports and returns the name of each along with the bits of the distance and the index
+    /// of the hit triangle, starting with the generic one
+    fn nearest_hits_per_backend(
+        mesh: &TriangleMesh,
+        ray: Ray,
+        t_range: Range<f32>,
+        indices: Range<usize>,
+    ) -> Vec<(&'static str, Option<(u32, usize)>)> {
+        #[allow(unused_mut)]
+        let mut backends: Vec<(&'static str, TrianglesTest)> = vec![("generic", triangles_generic)];
+
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx") {
+            backends.push(("avx", triangles_x86_avx));
+        }
+
+        let arena = Bump::new();
+        backends
+            .into_iter()
+            .map(|(name, func)| {
+                let hit = mesh
+                    .hit_nearest_with(func, ray, t_range.clone(), indices.clone(), &arena)
+                    .map(|(hit, index)| (hit.t.to_bits(), index));
+                (name, hit)
+            })
+            .collect()
+    }
+
+    // A bumpy grid whose triangles share vertices and edges. Its coordinates are not exact in binary,
+    // so rays aimed at the vertices need the double precision fallback of the edge functions.
+    #[test]
+    fn batched_hits_match_single_triangle_tests() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::WHITE,
+            emission: Color::BLACK,
+        });
+        let size = 4;
+        let vertices: Box<[f32]> = (0..size * size)
+            .flat_map(|i| {
+                [
+                    (i % size) as f32 * 0.1,
+                    (i / size) as f32 * 0.1,
+                    ((i * 7) % 3) as f32 * 0.05,
+                ]
+            })
+            .collect();
+        let indices: Box<[u32]> = (0..size - 1)
+            .flat_map(|y| (0..size - 1).map(move |x| y * size + x))
+            .flat_map(|i| [i, i + 1, i + size, i + 1, i + size + 1, i + size])
+            .collect();
+
+        // xorshift, as the exact rays don't matter
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next_f32 = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1 << 24) as f32
+        };
+        let mut num_hits = 0;
+
+        for cull_back_faces in [false, true] {
+            let mut mesh = TriangleMesh::new(vertices.clone(), indices.clone(), material.clone());
+            if cull_back_faces {
+                mesh = mesh.with_back_face_culling();
+            }
+            let arena = Bump::new();
+
+            for _ in 0..5000 {
+                let mut coordinate = |scale: f32| (next_f32() * scale - 1.0).round() * 0.1;
+                let origin = Vector::from_xyz(coordinate(6.0), coordinate(6.0), 0.4);
+                let mut target = Vector::from_xyz(coordinate(4.0), coordinate(4.0), 0.05);
+                if next_f32() < 0.5 {
+                    target = target + Vector::from_xyz(next_f32(), next_f32(), 0.0) * 0.1;
+                }
+                let direction = if next_f32() < 0.8 {
+                    target - origin
+                } else {
+                    origin - target
+                };
+                let ray = Ray::new(origin, direction);
+                let t_range = 0.0..[f32::INFINITY, 1.0][(next_f32() * 2.0) as usize];
+
+                let start = (next_f32() * 4.0) as usize;
+                let end = (start + 1 + (next_f32() * 16.0) as usize).min(mesh.len());
+
+                let mut expected = None;
+                let mut t_end = t_range.end;
+                for index in start..end {
+                    if let Some(hit) = mesh.hit(ray, t_range.start..t_end, index, &arena) {
+                        t_end = hit.t;
+                        expected = Some((hit.t.to_bits(), index));
+                    }
+                }
+                num_hits += expected.is_some() as u32;
+
+                let hits = nearest_hits_per_backend(&mesh, ray, t_range.clone(), start..end);
+                for (name, hit) in hits {
+                    assert_eq!(
+                        hit, expected,
+                        "{name} disagrees with single triangle tests for {ray:?} within {t_range:?} \
+                         and triangles {start}..{end}"
+                    );
+                }
+            }
+        }
+
+        assert!(num_hits > 1000, "only {num_hits} rays hit");
+    }
 }
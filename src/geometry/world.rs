@@ -0,0 +1,75 @@
+use std::ops::Range;
+
+use bumpalo::Bump;
+
+use crate::ray::Ray;
+
+use super::{Hit, Object, ObjectList, TraversalCounts, aabb::Aabb};
+
+/// Tests every object for each ray. Slower than a [`Bvh`](super::bvh::Bvh) for all but tiny
+/// scenes, but needs no build step and serves as a reference for checking the BVH.
+pub struct World<L> {
+    object_list: L,
+    bounding_box: Aabb,
+}
+
+impl<L: ObjectList> World<L> {
+    pub fn new(object_list: L) -> Self {
+        let bounding_box = (0..object_list.len())
+            .map(|idx| object_list.bounding_box(idx))
+            .reduce(|a, b| a.merge(&b))
+            .unwrap_or(Aabb::ZERO);
+
+        Self {
+            object_list,
+            bounding_box,
+        }
+    }
+
+    fn nearest_hit<'a>(
+        &'a self,
+        mut t_range: Range<f32>,
+        mut hit_object: impl FnMut(usize, Range<f32>) -> Option<Hit<'a>>,
+    ) -> Option<Hit<'a>> {
+        let mut nearest_hit = None;
+
+        for idx in 0..self.object_list.len() {
+            if let Some(hit) = hit_object(idx, t_range.clone()) {
+                t_range.end = hit.t;
+                nearest_hit = Some(hit);
+            }
+        }
+
+        nearest_hit
+    }
+}
+
+impl<L: ObjectList + Send + Sync> Object for World<L> {
+    fn hit(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> Option<Hit<'_>> {
+        self.nearest_hit(t_range, |idx, t_range| {
+            self.object_list.hit(ray, t_range, idx, arena)
+        })
+    }
+
+    fn hit_counted(
+        &self,
+        ray: Ray,
+        t_range: Range<f32>,
+        arena: &Bump,
+        counts: &mut TraversalCounts,
+    ) -> Option<Hit<'_>> {
+        self.nearest_hit(t_range, |idx, t_range| {
+            self.object_list
+                .hit_counted(ray, t_range, idx, arena, counts)
+        })
+    }
+
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, arena: &Bump) -> bool {
+        (0..self.object_list.len())
+            .any(|idx| self.object_list.occludes(ray, t_range.clone(), idx, arena))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+}
@@ -1,9 +1,75 @@
-use crate::Color;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    iter,
+    ops::{Add, Mul},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{Color, color::TransferFunction};
+
+/// Rectangle of pixels whose top left corner is at (`x`, `y`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn fits_within(&self, width: u32, height: u32) -> bool {
+        self.x
+            .checked_add(self.width)
+            .is_some_and(|end| end <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|end| end <= height)
+    }
+
+    /// Row-major indices of the pixels inside the rectangle in an image `stride` pixels wide
+    pub fn indices(self, stride: u32) -> impl Iterator<Item = usize> + Clone {
+        let stride = stride as usize;
+        (self.y as usize..(self.y + self.height) as usize).flat_map(move |row| {
+            let start = row * stride + self.x as usize;
+            start..start + self.width as usize
+        })
+    }
+}
+
+impl FromStr for Rect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<u32> = s
+            .split(',')
+            .map(|value| value.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|err| format!("invalid rectangle {s}: {err}"))?;
+
+        match values[..] {
+            [x, y, width, height] => Ok(Self {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(format!("expected x,y,width,height but got {s}")),
+        }
+    }
+}
 
 pub struct Image {
     width: u32,
     height: u32,
     pub pixels: Box<[Color]>,
+    /// Optional linear coverage per pixel, written as an alpha channel by the encoders that
+    /// support one. The color is not divided by it, so it is premultiplied when rendered with
+    /// a black background.
+    pub alpha: Option<Box<[f32]>>,
 }
 
 impl Image {
@@ -15,6 +81,7 @@ impl Image {
             width,
             height,
             pixels: vec![Color::BLACK; num_pixels].into_boxed_slice(),
+            alpha: None,
         }
     }
 
@@ -35,17 +102,529 @@ impl Image {
         self.pixels.get(idx).copied()
     }
 
-    pub fn into_srgb_8bit(self) -> Box<[u8]> {
+    /// Copies the `width` by `height` pixels whose top left corner is at (`x`, `y`)
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image {
+        let region = Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert!(
+            region.fits_within(self.width, self.height),
+            "crop region out of bounds"
+        );
+
+        let indices = region.indices(self.width);
+
+        Image {
+            width,
+            height,
+            pixels: indices.clone().map(|idx| self.pixels[idx]).collect(),
+            alpha: self
+                .alpha
+                .as_ref()
+                .map(|alpha| indices.map(|idx| alpha[idx]).collect()),
+        }
+    }
+
+    /// Resamples the image to the given size with a box filter, so every new pixel is the
+    /// area-weighted average of the pixels it covers
+    pub fn resize(&self, width: u32, height: u32) -> Image {
+        assert!(width > 0 && height > 0, "target size must not be empty");
+        assert!(!self.pixels.is_empty(), "cannot resize an empty image");
+
+        let (src_width, src_height) = (self.width as usize, self.height as usize);
+        let x_weights = box_weights(src_width, width as usize);
+        let y_weights = box_weights(src_height, height as usize);
+
+        Image {
+            width,
+            height,
+            pixels: resample(&self.pixels, src_width, &x_weights, &y_weights),
+            alpha: self
+                .alpha
+                .as_ref()
+                .map(|alpha| resample(alpha, src_width, &x_weights, &y_weights)),
+        }
+    }
+
+    /// Spreads the light of pixels brighter than `threshold` in luminance into a glow around
+    /// them. The part above the threshold is blurred with a Gaussian reaching `radius` pixels and
+    /// scaled by `intensity`, so an intensity of 1 only moves light without changing its total.
+    /// Meant for the linear values before they are encoded.
+    pub fn bloom(&mut self, threshold: f32, radius: f32, intensity: f32) {
+        assert!(threshold >= 0.0, "bloom threshold must not be negative");
+        assert!(radius > 0.0, "bloom radius must be positive");
+
+        let bright: Vec<Color> = self
+            .pixels
+            .iter_mut()
+            .map(|color| {
+                let luminance = color.luminance();
+                if luminance <= threshold {
+                    return Color::BLACK;
+                }
+                let bright = *color * ((luminance - threshold) / luminance);
+                *color = *color - bright;
+                bright
+            })
+            .collect();
+        if bright.is_empty() {
+            return;
+        }
+
+        let width = self.width as usize;
+        let x_weights = gaussian_weights(width, radius);
+        let y_weights = gaussian_weights(self.height as usize, radius);
+        let glow = resample(&bright, width, &x_weights, &y_weights);
+        for (color, glow) in self.pixels.iter_mut().zip(glow) {
+            *color += glow * intensity;
+        }
+    }
+
+    /// Darkens the pixels towards the corners by `strength` times their squared distance from
+    /// the center relative to the corners, so the center keeps its color and a strength of 1
+    /// turns the corners black
+    pub fn vignette(&mut self, strength: f32) {
+        assert!(
+            (0.0..=1.0).contains(&strength),
+            "vignette strength must be within [0, 1]"
+        );
+        if strength == 0.0 {
+            return;
+        }
+
+        let half_width = 0.5 * self.width as f32;
+        let half_height = 0.5 * self.height as f32;
+        let corner2 = half_width * half_width + half_height * half_height;
+        let width = self.width as usize;
+        for (idx, color) in self.pixels.iter_mut().enumerate() {
+            let dx = (idx % width) as f32 + 0.5 - half_width;
+            let dy = (idx / width) as f32 + 0.5 - half_height;
+            *color *= 1.0 - strength * (dx * dx + dy * dy) / corner2;
+        }
+    }
+
+    /// 64-bit FNV-1a hash over the raw bits of all pixel values
+    pub fn content_hash(&self) -> u64 {
+        self.pixels
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b])
+            .flat_map(f32::to_le_bytes)
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    pub fn to_rgb_8bit(&self, transfer: TransferFunction) -> Box<[u8]> {
+        self.pixels
+            .iter()
+            .flat_map(|color| color.encode(transfer).to_rgb_bytes_8bit())
+            .collect()
+    }
+
+    pub fn to_rgb_16bit(&self, transfer: TransferFunction) -> Box<[u8]> {
+        self.pixels
+            .iter()
+            .flat_map(|color| color.encode(transfer).to_rgb_bytes_16bit())
+            .collect()
+    }
+
+    /// Like [`Image::to_rgb_8bit`] followed by the linear alpha of every pixel, which is
+    /// opaque if the image has no alpha
+    pub fn to_rgba_8bit(&self, transfer: TransferFunction) -> Box<[u8]> {
         self.pixels
             .iter()
-            .flat_map(|color| color.apply_gamma().to_rgb_bytes_8bit())
+            .zip(self.alpha_values())
+            .flat_map(|(color, alpha)| {
+                let [r, g, b] = color.encode(transfer).to_rgb_bytes_8bit();
+                [r, g, b, (alpha.clamp(0.0, 1.0) * 255.0).round() as u8]
+            })
             .collect()
     }
 
-    pub fn into_srgb_16bit(self) -> Box<[u8]> {
+    /// Like [`Image::to_rgb_16bit`] followed by the linear alpha of every pixel, which is
+    /// opaque if the image has no alpha
+    pub fn to_rgba_16bit(&self, transfer: TransferFunction) -> Box<[u8]> {
         self.pixels
             .iter()
-            .flat_map(|color| color.apply_gamma().to_rgb_bytes_16bit())
+            .zip(self.alpha_values())
+            .flat_map(|(color, alpha)| {
+                let [rh, rl, gh, gl, bh, bl] = color.encode(transfer).to_rgb_bytes_16bit();
+                let [ah, al] = ((alpha.clamp(0.0, 1.0) * 65535.0).round() as u16).to_be_bytes();
+                [rh, rl, gh, gl, bh, bl, ah, al]
+            })
             .collect()
     }
+
+    fn alpha_values(&self) -> impl Iterator<Item = f32> + '_ {
+        let alpha = self.alpha.as_deref().unwrap_or(&[]);
+        assert!(alpha.is_empty() || alpha.len() == self.pixels.len());
+        alpha.iter().copied().chain(iter::repeat(1.0))
+    }
+
+    /// Reads the RGB channels of the first layer of an OpenEXR file
+    #[cfg(feature = "exr")]
+    pub fn load_exr(path: &Path) -> Result<Image, exr::error::Error> {
+        use exr::prelude::read_first_rgba_layer_from_file;
+
+        let exr_image = read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| Image::new(resolution.width() as u32, resolution.height() as u32),
+            |image: &mut Image, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                let idx = position.y() * image.width() as usize + position.x();
+                image.pixels[idx] = Color::from_rgb(r, g, b);
+            },
+        )?;
+
+        Ok(exr_image.layer_data.channel_data.pixels)
+    }
+
+    /// Writes the linear pixel values as a 32-bit float RGB OpenEXR file
+    #[cfg(feature = "exr")]
+    pub fn save_exr(&self, path: &Path) -> Result<(), SaveError> {
+        use exr::image::{Image as ExrImage, SpecificChannels, write::WritableImage};
+
+        let size = (self.width() as usize, self.height() as usize);
+
+        if let Some(alpha) = &self.alpha {
+            let pixels = SpecificChannels::rgba(|position: exr::prelude::Vec2<usize>| {
+                let idx = position.y() * size.0 + position.x();
+                let Color { r, g, b } = self.pixels[idx];
+                (r, g, b, alpha[idx])
+            });
+            ExrImage::from_channels(size, pixels)
+                .write()
+                .to_file(path)?;
+        } else {
+            let pixels = SpecificChannels::rgb(ImageGetPixelWrapper(self));
+            ExrImage::from_channels(size, pixels)
+                .write()
+                .to_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the color channels like [`Image::save_exr`] along with `extra` channels of one
+    /// value per pixel each in row-major order. Readers group channels named with a common
+    /// prefix, such as `normal.X`, `normal.Y` and `normal.Z`, into a layer.
+    #[cfg(feature = "exr")]
+    pub fn save_exr_with_channels(
+        &self,
+        extra: Vec<(&str, Vec<f32>)>,
+        path: &Path,
+    ) -> Result<(), SaveError> {
+        use exr::prelude::{
+            AnyChannel, AnyChannels, FlatSamples, Image as ExrImage, SmallVec, WritableImage,
+        };
+
+        let component =
+            |f: fn(&Color) -> f32| FlatSamples::F32(self.pixels.iter().map(f).collect());
+        let mut channels: SmallVec<[_; 4]> = SmallVec::new();
+        channels.push(AnyChannel::new("R", component(|color| color.r)));
+        channels.push(AnyChannel::new("G", component(|color| color.g)));
+        channels.push(AnyChannel::new("B", component(|color| color.b)));
+        if let Some(alpha) = &self.alpha {
+            channels.push(AnyChannel::new("A", FlatSamples::F32(alpha.to_vec())));
+        }
+        for (name, values) in extra {
+            assert_eq!(values.len(), self.pixels.len());
+            channels.push(AnyChannel::new(name, FlatSamples::F32(values)));
+        }
+
+        let size = (self.width() as usize, self.height() as usize);
+        ExrImage::from_channels(size, AnyChannels::sort(channels))
+            .write()
+            .to_file(path)?;
+
+        Ok(())
+    }
+
+    /// Writes the pixels as a 16-bit PNG file, tagged with the transfer function
+    #[cfg(feature = "png")]
+    pub fn save_png(&self, path: &Path, transfer: TransferFunction) -> Result<(), SaveError> {
+        use png::{BitDepth, ColorType, Compression, ScaledFloat, SrgbRenderingIntent};
+
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            self.width(),
+            self.height(),
+        );
+        encoder.set_color(if self.alpha.is_some() {
+            ColorType::Rgba
+        } else {
+            ColorType::Rgb
+        });
+        encoder.set_depth(BitDepth::Sixteen);
+        match transfer {
+            TransferFunction::Srgb => encoder.set_source_srgb(SrgbRenderingIntent::Perceptual),
+            TransferFunction::Gamma(gamma) => {
+                encoder.set_source_gamma(ScaledFloat::new(1.0 / gamma))
+            }
+            TransferFunction::Linear => encoder.set_source_gamma(ScaledFloat::new(1.0)),
+        }
+        encoder.set_compression(Compression::High);
+
+        encoder.add_text_chunk(String::from("software"), String::from("raybow"))?;
+
+        let mut writer = encoder.write_header()?;
+        if self.alpha.is_some() {
+            writer.write_image_data(&self.to_rgba_16bit(transfer))?;
+        } else {
+            writer.write_image_data(&self.to_rgb_16bit(transfer))?;
+        }
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Writes the pixels as an 8-bit QOI file. QOI can only tell linear from sRGB data, so
+    /// custom gamma curves are tagged as sRGB.
+    #[cfg(feature = "qoi")]
+    pub fn save_qoi(&self, path: &Path, transfer: TransferFunction) -> Result<(), SaveError> {
+        use rapid_qoi::{Colors, Qoi};
+
+        let linear = transfer == TransferFunction::Linear;
+        let (colors, data) = match (self.alpha.is_some(), linear) {
+            (true, false) => (Colors::SrgbLinA, self.to_rgba_8bit(transfer)),
+            (false, false) => (Colors::Srgb, self.to_rgb_8bit(transfer)),
+            (true, true) => (Colors::Rgba, self.to_rgba_8bit(transfer)),
+            (false, true) => (Colors::Rgb, self.to_rgb_8bit(transfer)),
+        };
+        let qoi = Qoi {
+            width: self.width(),
+            height: self.height(),
+            colors,
+        };
+
+        let encoded = qoi.encode_alloc(&data)?;
+        std::fs::write(path, encoded)?;
+
+        Ok(())
+    }
+
+    /// Writes the pixels as an 8-bit binary PPM file. PPM has no alpha channel, so the alpha
+    /// is dropped, and no way to tag the transfer function.
+    pub fn save_ppm(&self, path: &Path, transfer: TransferFunction) -> Result<(), SaveError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        write!(writer, "P6\n{} {}\n255\n", self.width(), self.height())?;
+        writer.write_all(&self.to_rgb_8bit(transfer))?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Writes a single named channel of `width * height` values in row-major order as an OpenEXR
+/// file, e.g. for depth or object ID buffers
+#[cfg(feature = "exr")]
+pub fn save_exr_channel(
+    name: &str,
+    values: &[f32],
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> Result<(), SaveError> {
+    use exr::image::{Image as ExrImage, SpecificChannels, write::WritableImage};
+
+    let pixels = SpecificChannels::build()
+        .with_channel::<f32>(name)
+        .with_pixel_fn(|position: exr::prelude::Vec2<usize>| {
+            (values[position.y() * width as usize + position.x()],)
+        });
+
+    let exr_image = ExrImage::from_channels((width as usize, height as usize), pixels);
+
+    exr_image.write().to_file(path)?;
+
+    Ok(())
+}
+
+/// Writes `width * height` values in row-major order as a linear 16-bit grayscale PNG file, where
+/// 0 is black and 1 is white. Values outside of that range are clamped.
+#[cfg(feature = "png")]
+pub fn save_png_channel(
+    values: &[f32],
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> Result<(), SaveError> {
+    use png::{BitDepth, ColorType, Compression, ScaledFloat};
+
+    assert_eq!(values.len(), width as usize * height as usize);
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(BitDepth::Sixteen);
+    encoder.set_source_gamma(ScaledFloat::new(1.0));
+    encoder.set_compression(Compression::High);
+
+    encoder.add_text_chunk(String::from("software"), String::from("raybow"))?;
+
+    let data: Vec<_> = values
+        .iter()
+        .flat_map(|value| ((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_be_bytes())
+        .collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+// For every target pixel along an axis, the source pixels it overlaps and their share of it
+fn box_weights(src_len: usize, dst_len: usize) -> Vec<Vec<(usize, f32)>> {
+    let scale = src_len as f64 / dst_len as f64;
+
+    (0..dst_len)
+        .map(|i| {
+            let start = i as f64 * scale;
+            let end = (i + 1) as f64 * scale;
+            (start.floor() as usize..(end.ceil() as usize).min(src_len))
+                .map(|j| {
+                    let overlap = end.min((j + 1) as f64) - start.max(j as f64);
+                    (j, (overlap / scale) as f32)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Normalized Gaussian weights whose standard deviation is half the radius. Taps beyond the ends
+// of the axis fall onto the pixel at the end.
+fn gaussian_weights(len: usize, radius: f32) -> Vec<Vec<(usize, f32)>> {
+    let reach = radius.ceil() as isize;
+    let sigma = 0.5 * radius;
+    let kernel: Vec<f32> = (-reach..=reach)
+        .map(|d| (-0.5 * (d as f32 / sigma).powi(2)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+
+    (0..len as isize)
+        .map(|i| {
+            (-reach..=reach)
+                .zip(&kernel)
+                .map(|(d, &weight)| ((i + d).clamp(0, len as isize - 1) as usize, weight / total))
+                .collect()
+        })
+        .collect()
+}
+
+fn resample<T>(
+    values: &[T],
+    src_width: usize,
+    x_weights: &[Vec<(usize, f32)>],
+    y_weights: &[Vec<(usize, f32)>],
+) -> Box<[T]>
+where
+    T: Copy + Default + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let dst_width = x_weights.len();
+    let weighted_sum = |weights: &[(usize, f32)], value: &dyn Fn(usize) -> T| {
+        weights.iter().fold(T::default(), |sum, &(idx, weight)| {
+            sum + value(idx) * weight
+        })
+    };
+
+    let rows: Vec<T> = values
+        .chunks_exact(src_width)
+        .flat_map(|row| {
+            x_weights
+                .iter()
+                .map(|weights| weighted_sum(weights, &|x| row[x]))
+        })
+        .collect();
+
+    y_weights
+        .iter()
+        .flat_map(|weights| {
+            let rows = &rows;
+            (0..dst_width).map(move |x| weighted_sum(weights, &|y| rows[y * dst_width + x]))
+        })
+        .collect()
+}
+
+#[cfg(feature = "exr")]
+struct ImageGetPixelWrapper<'a>(&'a Image);
+
+#[cfg(feature = "exr")]
+impl<'a> exr::image::write::channels::GetPixel for ImageGetPixelWrapper<'a> {
+    type Pixel = (f32, f32, f32);
+
+    fn get_pixel(&self, position: exr::prelude::Vec2<usize>) -> Self::Pixel {
+        let x = position.x() as u32;
+        let y = position.y() as u32;
+        let Color { r, g, b } = self.0.pixel(x, y).unwrap();
+        (r, g, b)
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    #[cfg(feature = "exr")]
+    Exr(exr::error::Error),
+    #[cfg(feature = "png")]
+    Png(png::EncodingError),
+    #[cfg(feature = "qoi")]
+    Qoi(rapid_qoi::EncodeError),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => err.fmt(f),
+            #[cfg(feature = "exr")]
+            Self::Exr(err) => err.fmt(f),
+            #[cfg(feature = "png")]
+            Self::Png(err) => err.fmt(f),
+            #[cfg(feature = "qoi")]
+            Self::Qoi(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            #[cfg(feature = "exr")]
+            Self::Exr(err) => Some(err),
+            #[cfg(feature = "png")]
+            Self::Png(err) => Some(err),
+            #[cfg(feature = "qoi")]
+            Self::Qoi(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[cfg(feature = "exr")]
+impl From<exr::error::Error> for SaveError {
+    fn from(err: exr::error::Error) -> Self {
+        Self::Exr(err)
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<png::EncodingError> for SaveError {
+    fn from(err: png::EncodingError) -> Self {
+        Self::Png(err)
+    }
+}
+
+#[cfg(feature = "qoi")]
+impl From<rapid_qoi::EncodeError> for SaveError {
+    fn from(err: rapid_qoi::EncodeError) -> Self {
+        Self::Qoi(err)
+    }
 }
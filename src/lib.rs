@@ -0,0 +1,29 @@
+pub use bumpalo;
+pub use camera::Camera;
+pub use color::Color;
+pub use raybow::{
+    Aovs, ColorModel, Filter, MAX_RENDER_PIXELS, RenderJob, RenderJobBuilder, RenderJobError,
+    RenderMode, RenderScratch, RenderStats, Sampler, Sun, WorkerState, debug_pixel, render,
+    render_reusing, render_sequence, render_to_vec, render_views,
+};
+#[cfg(feature = "rayon")]
+pub use raybow::{render_in_pool, render_in_pool_reusing};
+
+pub mod background;
+pub mod camera;
+pub mod color;
+pub mod compare;
+pub mod geometry;
+pub mod image;
+pub mod material;
+pub mod obj;
+mod philox;
+pub mod ray;
+mod raybow;
+pub mod scene;
+mod sobol;
+#[cfg(feature = "spectral")]
+pub mod spectrum;
+mod sync_unsafe_cell;
+pub mod texture;
+pub mod vector;
@@ -1,38 +1,27 @@
 use std::{
-    fs::File,
-    io::BufWriter,
+    io::Write,
+    ops::Range,
     path::{Path, PathBuf},
+    process::ExitCode,
     str::FromStr,
     sync::Arc,
 };
 
 use argh::FromArgs;
-use camera::Camera;
-use color::Color;
-use geometry::{Object, Sphere};
-use image::Image;
-use material::{DiffuseLight, Lambertian, Material, Metal};
-use rapid_qoi::{Colors, Qoi};
-use raybow::RenderJob;
-use scene::Scene;
-use vector::Vector;
-
-mod camera;
-mod color;
-mod geometry;
-mod image;
-mod material;
-mod philox;
-mod ray;
-mod raybow;
-mod scene;
-mod sync_unsafe_cell;
-mod vector;
+use raybow::{
+    Aovs, ColorModel, Filter, MAX_RENDER_PIXELS, RenderJob, RenderJobBuilder, RenderMode,
+    RenderStats, Sampler,
+    background::Background,
+    color::TransferFunction,
+    image::{self, Image, Rect, SaveError},
+    scene::{self, Scene},
+};
 
 enum OutputFormat {
     Exr,
     Qoi,
     Png,
+    Ppm,
 }
 
 impl OutputFormat {
@@ -41,6 +30,7 @@ impl OutputFormat {
             Self::Exr => "exr",
             Self::Qoi => "qoi",
             Self::Png => "png",
+            Self::Ppm => "ppm",
         }
     }
 }
@@ -55,12 +45,53 @@ impl FromStr for OutputFormat {
             Ok(Self::Qoi)
         } else if s.eq_ignore_ascii_case("png") {
             Ok(Self::Png)
+        } else if s.eq_ignore_ascii_case("ppm") {
+            Ok(Self::Ppm)
         } else {
             Err(format!("unsupported output format: {s}"))
         }
     }
 }
 
+#[derive(Default)]
+struct AovSelection {
+    normal: bool,
+    albedo: bool,
+    depth: bool,
+    object_id: bool,
+    sample_count: bool,
+}
+
+impl AovSelection {
+    fn any(&self) -> bool {
+        self.normal || self.albedo || self.depth || self.object_id || self.sample_count
+    }
+}
+
+impl FromStr for AovSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut selection = Self::default();
+        for aov in s.split(',') {
+            if aov.eq_ignore_ascii_case("normal") {
+                selection.normal = true;
+            } else if aov.eq_ignore_ascii_case("albedo") {
+                selection.albedo = true;
+            } else if aov.eq_ignore_ascii_case("depth") {
+                selection.depth = true;
+            } else if aov.eq_ignore_ascii_case("id") {
+                selection.object_id = true;
+            } else if aov.eq_ignore_ascii_case("samples") {
+                selection.sample_count = true;
+            } else {
+                return Err(format!("unsupported AOV: {aov}"));
+            }
+        }
+        Ok(selection)
+    }
+}
+
 /// A blazingly slow toy CPU Raytracer
 #[derive(FromArgs)]
 struct Options {
@@ -80,10 +111,69 @@ struct Options {
     #[argh(option, short = 's', default = "500")]
     num_samples: u32,
 
+    /// index of the first sample, so that renders of disjoint sample ranges can be averaged
+    #[argh(option, default = "0")]
+    first_sample: u32,
+
+    /// maximum number of times a ray may bounce off a surface
+    #[argh(option, default = "50")]
+    max_bounces: u32,
+
+    /// end paths with low throughput at random, after the bounces given by --min-bounces
+    #[argh(switch)]
+    russian_roulette: bool,
+
+    /// number of bounces every path takes before Russian roulette may end it
+    #[argh(option, default = "3")]
+    min_bounces: u32,
+
+    /// minimum hit distance of rays, to be scaled with the size of the scene
+    #[argh(option, default = "0.0001")]
+    ray_epsilon: f32,
+
+    /// source of random numbers, either philox or sobol
+    #[argh(option, default = "Sampler::Philox")]
+    sampler: Sampler,
+
+    /// what to render: pathtrace, normals to show the outward surface normal at the first hit,
+    /// or cost to show the number of BVH nodes and primitives visited by the camera rays
+    #[argh(option, default = "RenderMode::PathTrace")]
+    mode: RenderMode,
+
+    /// how path tracing represents light: rgb, or spectral for hero wavelength sampling when
+    /// built with the spectral feature
+    #[argh(option, default = "ColorModel::Rgb")]
+    color_model: ColorModel,
+
+    /// pixel reconstruction filter: box, tent, or gaussian with an optional radius in pixels
+    /// such as gaussian:2
+    #[argh(option, default = "Filter::Box")]
+    filter: Filter,
+
+    /// stop sampling a pixel once its estimated relative error drops below this value, using
+    /// the sample count as an upper bound
+    #[argh(option)]
+    adaptive: Option<f32>,
+
+    /// stop rendering once a sample pass changes the pixels by less than this mean relative
+    /// amount, using the sample count as an upper bound
+    #[argh(option)]
+    target_convergence: Option<f32>,
+
+    /// do not sample spherical lights directly, leaving them to be found by scattered rays
+    /// alone
+    #[argh(switch)]
+    no_light_sampling: bool,
+
     /// the seed
     #[argh(option, default = "0")]
     seed: u64,
 
+    /// derive the random numbers of every pixel from its own key, as a safeguard; the default
+    /// counters already keep the noise of adjacent pixels uncorrelated
+    #[argh(switch)]
+    scramble_pixels: bool,
+
     /// number of workers to use (default is number of available CPUs)
     #[argh(option, short = 'p', default = "num_cpus::get()")]
     num_workers: usize,
@@ -95,162 +185,466 @@ struct Options {
     /// data format in which to encode the output
     #[argh(option, short = 'f', default = "OutputFormat::Exr")]
     output_format: OutputFormat,
+
+    /// transfer function of PNG, QOI and PPM output: srgb, linear, or gamma with an exponent
+    /// such as gamma:2.2. EXR output is always linear.
+    #[argh(option, default = "TransferFunction::Srgb")]
+    transfer: TransferFunction,
+
+    /// comma separated list of auxiliary buffers (normal, albedo, depth, id, samples) to write
+    /// along with the image. EXR output stores them as layers of the output file. Otherwise
+    /// they are written as additional files next to it, where the per-pixel sample counts are
+    /// a grayscale PNG with white as the full sample count and all others are EXR files.
+    #[argh(option, default = "AovSelection::default()")]
+    aov: AovSelection,
+
+    /// only render the pixels inside x,y,width,height and leave the rest black
+    #[argh(option)]
+    region: Option<Rect>,
+
+    /// darken the image towards its corners, from 0 for no change to 1 for black corners
+    #[argh(option)]
+    vignette: Option<f32>,
+
+    /// add an alpha channel holding the fraction of camera rays that hit an object
+    #[argh(switch)]
+    alpha: bool,
+
+    /// write a JSON file describing the render next to the output
+    #[argh(switch)]
+    metadata: bool,
+
+    /// render this many frames while orbiting the camera once around its look-at point and
+    /// write them to numbered files next to the output
+    #[argh(option, default = "1")]
+    frames: u32,
+
+    /// do not print progress, for example when the output is not a terminal
+    #[argh(switch, short = 'q')]
+    quiet: bool,
+
+    /// print BVH and tracing statistics after rendering
+    #[argh(switch, short = 'v')]
+    verbose: bool,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> ExitCode {
     let options: Options = argh::from_env();
 
-    let (camera, objects, background) = match options.scene.as_str() {
-        "builtin:spheres" => gen_scene_spheres(options.width as f32 / options.height as f32),
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    if options.quiet && options.verbose {
+        return Err("--quiet and --verbose cannot be combined".into());
+    }
+    checked_image_size(&options)?;
+    if let Some(strength) = options.vignette
+        && !(0.0..=1.0).contains(&strength)
+    {
+        return Err(format!("vignette strength {strength} is not within [0, 1]").into());
+    }
+    if options.frames > 1 {
+        return run_sequence(options);
+    }
+
+    let (camera, objects, lights, background, sun) = match options.scene.as_str() {
+        "builtin:spheres" => {
+            let (camera, objects, background) =
+                scene::builtin_spheres(options.width as f32 / options.height as f32);
+            (
+                camera,
+                objects,
+                Vec::new(),
+                Arc::new(background) as Arc<dyn Background>,
+                None,
+            )
+        }
         _ => {
             let scene = Scene::from_file(Path::new(&options.scene))?;
             let camera = scene.construct_camera(options.width as f32 / options.height as f32);
-            let objects = scene.construct_world();
-            (camera, objects, scene.background)
+            let objects = scene.construct_world()?;
+            let lights = if options.no_light_sampling {
+                Vec::new()
+            } else {
+                scene.construct_lights()?
+            };
+            let background = scene.construct_background()?;
+            (camera, objects, lights, background, scene.sun())
         }
     };
 
-    let job = RenderJob {
-        camera: &camera,
-        objects,
-        background,
-        num_samples: options.num_samples,
-        seed: options.seed,
-        num_workers: options.num_workers,
-    };
+    let mut builder = RenderJob::builder(&camera)
+        .objects(objects)
+        .lights(lights)
+        .background(background);
+    if let Some(sun) = sun {
+        builder = builder.sun(sun);
+    }
+    let job = configure_job(builder, &options)?.build()?;
 
     let mut image = Image::new(options.width, options.height);
+    let num_pixels = image.pixels.len();
+    if options.alpha {
+        image.alpha = Some(vec![0.0; num_pixels].into_boxed_slice());
+    }
+    let mut aovs = Aovs {
+        normal: options
+            .aov
+            .normal
+            .then(|| Image::new(options.width, options.height)),
+        albedo: options
+            .aov
+            .albedo
+            .then(|| Image::new(options.width, options.height)),
+        depth: options
+            .aov
+            .depth
+            .then(|| vec![f32::INFINITY; num_pixels].into_boxed_slice()),
+        object_id: options
+            .aov
+            .object_id
+            .then(|| vec![-1; num_pixels].into_boxed_slice()),
+        sample_count: options
+            .aov
+            .sample_count
+            .then(|| vec![0; num_pixels].into_boxed_slice()),
+    };
 
-    raybow::render(job, &mut image);
-
-    let output_path = options.output.unwrap_or_else(|| {
-        PathBuf::new()
-            .with_file_name("output")
-            .with_extension(options.output_format.default_file_extension())
+    let num_samples = options.num_samples;
+    let stats = raybow::render(job, &mut image, &mut aovs, |pass, _| {
+        if options.quiet {
+            return;
+        }
+        let mut stdout = std::io::stdout().lock();
+        let progress = pass as f32 / num_samples as f32 * 100.0;
+        write!(stdout, "\x1B[G\x1B[K{pass}/{num_samples} ({progress:.0}%)").unwrap();
+        stdout.flush().unwrap();
     });
+    print_summary(&options, &stats);
+    if let Some(strength) = options.vignette {
+        image.vignette(strength);
+    }
 
-    match options.output_format {
-        OutputFormat::Exr => write_exr(image, &output_path)?,
-        OutputFormat::Qoi => write_qoi(image, &output_path)?,
-        OutputFormat::Png => write_png(image, &output_path)?,
+    let output_path = output_path(&options);
+    if matches!(options.output_format, OutputFormat::Exr) && options.aov.any() {
+        image.save_exr_with_channels(aov_channels(&aovs), &output_path)?;
+    } else {
+        write_image(&options, &image, &output_path)?;
+        write_aov_files(&options, &aovs, &output_path)?;
+    }
+
+    if options.metadata {
+        let metadata = render_metadata(&options, &image, &stats);
+        std::fs::write(output_path.with_extension("json"), metadata)?;
     }
 
     Ok(())
 }
 
-struct ImageGetPixelWrapper<'a>(&'a Image);
-
-impl<'a> exr::image::write::channels::GetPixel for ImageGetPixelWrapper<'a> {
-    type Pixel = (f32, f32, f32);
-
-    fn get_pixel(&self, position: exr::prelude::Vec2<usize>) -> Self::Pixel {
-        let x = position.x() as u32;
-        let y = position.y() as u32;
-        let Color { r, g, b } = self.0.pixel(x, y).unwrap();
-        (r, g, b)
+/// Applies the render settings from the command line, leaving the scene to the caller
+fn configure_job<'a>(
+    builder: RenderJobBuilder<'a>,
+    options: &Options,
+) -> Result<RenderJobBuilder<'a>, String> {
+    let mut builder = builder
+        .sample_range(checked_sample_range(options)?)
+        .seed(options.seed)
+        .workers(options.num_workers)
+        .max_bounces(options.max_bounces)
+        .ray_epsilon(options.ray_epsilon)
+        .sampler(options.sampler)
+        .mode(options.mode)
+        .color_model(options.color_model)
+        .filter(options.filter);
+    if let Some(target_error) = options.adaptive {
+        builder = builder.adaptive(target_error);
+    }
+    if options.russian_roulette {
+        builder = builder.russian_roulette(options.min_bounces);
     }
+    if options.scramble_pixels {
+        builder = builder.scramble_pixels();
+    }
+    if let Some(target_convergence) = options.target_convergence {
+        builder = builder.target_convergence(target_convergence);
+    }
+    if let Some(region) = checked_region(options)? {
+        builder = builder.region(region);
+    }
+    Ok(builder)
 }
 
-fn write_exr(image: Image, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    use exr::image::{Image as ExrImage, SpecificChannels, write::WritableImage};
-
-    let pixels = SpecificChannels::rgb(ImageGetPixelWrapper(&image));
-
-    let exr_image =
-        ExrImage::from_channels((image.width() as usize, image.height() as usize), pixels);
-
-    exr_image.write().to_file(path)?;
-
-    Ok(())
+/// Names and values of the EXR channels holding the AOVs
+fn aov_channels(aovs: &Aovs) -> Vec<(&'static str, Vec<f32>)> {
+    let mut channels = Vec::new();
+    if let Some(normal) = &aovs.normal {
+        channels.push(("normal.X", normal.pixels.iter().map(|n| n.r).collect()));
+        channels.push(("normal.Y", normal.pixels.iter().map(|n| n.g).collect()));
+        channels.push(("normal.Z", normal.pixels.iter().map(|n| n.b).collect()));
+    }
+    if let Some(albedo) = &aovs.albedo {
+        channels.push(("albedo.R", albedo.pixels.iter().map(|c| c.r).collect()));
+        channels.push(("albedo.G", albedo.pixels.iter().map(|c| c.g).collect()));
+        channels.push(("albedo.B", albedo.pixels.iter().map(|c| c.b).collect()));
+    }
+    if let Some(depth) = &aovs.depth {
+        channels.push(("Z", depth.to_vec()));
+    }
+    if let Some(object_id) = &aovs.object_id {
+        // Stored as float so that misses can keep their -1
+        channels.push(("id", object_id.iter().map(|&id| id as f32).collect()));
+    }
+    if let Some(sample_count) = &aovs.sample_count {
+        channels.push(("samples", sample_count.iter().map(|&n| n as f32).collect()));
+    }
+    channels
 }
 
-fn write_qoi(image: Image, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let qoi = Qoi {
-        width: image.width(),
-        height: image.height(),
-        colors: Colors::Srgb,
-    };
-
-    let encoded = qoi.encode_alloc(&image.into_srgb_8bit())?;
-    std::fs::write(path, encoded)?;
+fn write_aov_files(
+    options: &Options,
+    aovs: &Aovs,
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (name, aov) in [("normal", &aovs.normal), ("albedo", &aovs.albedo)] {
+        if let Some(aov) = aov {
+            aov.save_exr(&output_path.with_extension(format!("{name}.exr")))?;
+        }
+    }
+    if let Some(depth) = &aovs.depth {
+        let path = output_path.with_extension("depth.exr");
+        image::save_exr_channel("Z", depth, options.width, options.height, &path)?;
+    }
+    if let Some(object_id) = &aovs.object_id {
+        // Stored as float so that misses can keep their -1
+        let object_id: Vec<_> = object_id.iter().map(|&id| id as f32).collect();
+        let path = output_path.with_extension("id.exr");
+        image::save_exr_channel("id", &object_id, options.width, options.height, &path)?;
+    }
+    if let Some(sample_count) = &aovs.sample_count {
+        let max_samples = options.num_samples as f32;
+        let heatmap: Vec<_> = sample_count
+            .iter()
+            .map(|&count| count as f32 / max_samples)
+            .collect();
+        let path = output_path.with_extension("samples.png");
+        image::save_png_channel(&heatmap, options.width, options.height, &path)?;
+    }
 
     Ok(())
 }
 
-fn write_png(image: Image, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    use png::{BitDepth, ColorType, Compression, SrgbRenderingIntent};
+fn run_sequence(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    if options.scene == "builtin:spheres" {
+        return Err("--frames requires a scene file".into());
+    }
+    let AovSelection {
+        normal,
+        albedo,
+        depth,
+        object_id,
+        sample_count,
+    } = options.aov;
+    if normal || albedo || depth || object_id || sample_count || options.alpha {
+        return Err("AOVs and alpha can not be combined with --frames".into());
+    }
 
-    let mut encoder = png::Encoder::new(
-        BufWriter::new(File::create(path)?),
-        image.width(),
-        image.height(),
+    let scene = Scene::from_file(Path::new(&options.scene))?;
+    let aspect_ratio = options.width as f32 / options.height as f32;
+    let objects = scene.construct_world()?;
+    let lights = if options.no_light_sampling {
+        Vec::new()
+    } else {
+        scene.construct_lights()?
+    };
+    let background = scene.construct_background()?;
+
+    let num_frames = options.frames;
+    let cameras: Vec<_> = (0..num_frames)
+        .map(|frame| {
+            let angle = frame as f32 / num_frames as f32 * std::f32::consts::TAU;
+            scene.construct_orbit_camera(aspect_ratio, angle)
+        })
+        .collect();
+    let mut jobs = cameras
+        .iter()
+        .map(|camera| {
+            let mut builder = RenderJob::builder(camera)
+                .objects(objects.clone())
+                .lights(lights.clone())
+                .background(Arc::clone(&background));
+            if let Some(sun) = scene.sun() {
+                builder = builder.sun(sun);
+            }
+            Ok(configure_job(builder, &options)?.build()?)
+        })
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?
+        .into_iter();
+
+    let output_path = output_path(&options);
+    let extension = output_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or(options.output_format.default_file_extension())
+        .to_owned();
+
+    let num_samples = options.num_samples;
+    let total_passes = num_frames as f32 * num_samples as f32;
+    // Only the first error is kept, the remaining frames are still rendered
+    let mut result = Ok(());
+    let stats = raybow::render_sequence(
+        num_frames,
+        options.width,
+        options.height,
+        |_| jobs.next().unwrap(),
+        |frame, pass, _| {
+            if options.quiet {
+                return;
+            }
+            let mut stdout = std::io::stdout().lock();
+            let progress = (frame * num_samples + pass) as f32 / total_passes * 100.0;
+            write!(
+                stdout,
+                "\x1B[G\x1B[Kframe {}/{num_frames} {pass}/{num_samples} ({progress:.0}%)",
+                frame + 1
+            )
+            .unwrap();
+            stdout.flush().unwrap();
+        },
+        |frame, mut image, stats| {
+            if result.is_err() {
+                return;
+            }
+            if let Some(strength) = options.vignette {
+                image.vignette(strength);
+            }
+            let frame_path = output_path.with_extension(format!("{frame:04}.{extension}"));
+            result = write_image(&options, &image, &frame_path).and_then(|()| {
+                if options.metadata {
+                    let metadata = render_metadata(&options, &image, stats);
+                    std::fs::write(frame_path.with_extension("json"), metadata)?;
+                }
+                Ok(())
+            });
+        },
     );
-    encoder.set_color(ColorType::Rgb);
-    encoder.set_depth(BitDepth::Sixteen);
-    encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
-    encoder.set_compression(Compression::High);
+    print_summary(&options, &stats);
 
-    encoder.add_text_chunk(String::from("software"), String::from("raybow"))?;
+    Ok(result?)
+}
+
+fn print_summary(options: &Options, stats: &RenderStats) {
+    if options.quiet {
+        return;
+    }
+    println!("\x1B[G\x1B[KDone in {:.3?}", stats.elapsed);
+
+    if options.adaptive.is_some() {
+        let (width, height) = match options.region {
+            Some(region) => (region.width, region.height),
+            None => (options.width, options.height),
+        };
+        let num_pixels = width as u64 * height as u64 * options.frames as u64;
+        let average = stats.samples as f64 / num_pixels as f64;
+        println!("Average samples per pixel: {average:.1}");
+    }
+    if options.adaptive.is_some() || options.target_convergence.is_some() {
+        let max_passes = options.num_samples as u64 * options.frames as u64;
+        println!("Passes: {}/{max_passes}", stats.passes);
+    }
 
-    let mut writer = encoder.write_header()?;
-    writer.write_image_data(&image.into_srgb_16bit())?;
-    writer.finish()?;
+    if options.verbose {
+        println!("BVH built in {:.3?}", stats.bvh_build_time);
+        println!("BVH branches: {}", stats.bvh_branches);
+        println!("Rays: {}", stats.rays);
+        println!("Bounces: {}", stats.bounces);
+        println!("Hits: {}", stats.hits);
+        println!("Rejected samples: {}", stats.rejected_samples);
+    }
+}
 
+fn checked_image_size(options: &Options) -> Result<(), String> {
+    if options.width as u64 * options.height as u64 > MAX_RENDER_PIXELS {
+        return Err(format!(
+            "a {}x{} image exceeds the limit of {MAX_RENDER_PIXELS} pixels",
+            options.width, options.height
+        ));
+    }
     Ok(())
 }
 
-fn gen_scene_spheres(aspect_ratio: f32) -> (Camera, Vec<Arc<dyn Object>>, Color) {
-    let lookfrom = Vector::from_xyz(13.0, 2.0, 3.0);
-    let lookat = Vector::from_xyz(0.0, 0.0, 0.0);
-    let vup = Vector::from_xyz(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        50.0,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-    );
-
-    let mut objects = Vec::<Arc<dyn Object>>::new();
+fn checked_region(options: &Options) -> Result<Option<Rect>, String> {
+    match options.region {
+        Some(region) if !region.fits_within(options.width, options.height) => Err(format!(
+            "region {},{},{},{} does not fit into the {}x{} image",
+            region.x, region.y, region.width, region.height, options.width, options.height
+        )),
+        region => Ok(region),
+    }
+}
 
-    let ground: Arc<dyn Material> = Arc::new(Lambertian {
-        albedo: Color::from_rgb(0.5, 0.5, 0.5),
-    });
-    let floor_radius = 1000.0f32;
-    objects.push(Arc::new(Sphere::new(
-        Vector::from_xyz(0.0, -floor_radius, 0.0),
-        floor_radius,
-        Arc::clone(&ground),
-    )));
-
-    let num_spheres = 11;
-
-    for a in -num_spheres..num_spheres {
-        for b in -num_spheres..num_spheres {
-            let x = a as f32;
-            let z = b as f32;
-            let y = (-x * x - z * z + floor_radius * floor_radius).sqrt() - floor_radius;
-            let center = Vector::from_xyz(x, y + 0.2, z);
-
-            let albedo = Color::from_rgb(0.3, 0.7, 0.9);
-            let material = Arc::new(Metal { albedo, fuzz: 0.1 });
-            objects.push(Arc::new(Sphere::new(center, 0.2, material)));
-        }
+fn checked_sample_range(options: &Options) -> Result<Range<u32>, String> {
+    match options.first_sample.checked_add(options.num_samples) {
+        Some(end) => Ok(options.first_sample..end),
+        None => Err(format!(
+            "{} samples starting at {} exceed the largest sample index",
+            options.num_samples, options.first_sample
+        )),
     }
+}
 
-    let light: Arc<dyn Material> = Arc::new(DiffuseLight {
-        emit: Color::WHITE * 4.0,
-    });
-    objects.push(Arc::new(Sphere::new(
-        Vector::from_xyz(0.0, 3.0, 0.0),
-        0.5,
-        Arc::clone(&light),
-    )));
+fn output_path(options: &Options) -> PathBuf {
+    options.output.clone().unwrap_or_else(|| {
+        PathBuf::new()
+            .with_file_name("output")
+            .with_extension(options.output_format.default_file_extension())
+    })
+}
 
-    (camera, objects, Color::BLACK)
+fn write_image(options: &Options, image: &Image, path: &Path) -> Result<(), SaveError> {
+    match options.output_format {
+        OutputFormat::Exr => image.save_exr(path),
+        OutputFormat::Qoi => image.save_qoi(path, options.transfer),
+        OutputFormat::Png => image.save_png(path, options.transfer),
+        OutputFormat::Ppm => image.save_ppm(path, options.transfer),
+    }
+}
+
+fn render_metadata(options: &Options, image: &Image, stats: &RenderStats) -> String {
+    format!(
+        concat!(
+            "{{\n",
+            "  \"width\": {},\n",
+            "  \"height\": {},\n",
+            "  \"num_samples\": {},\n",
+            "  \"passes\": {},\n",
+            "  \"seed\": {},\n",
+            "  \"num_workers\": {},\n",
+            "  \"elapsed_seconds\": {},\n",
+            "  \"rays\": {},\n",
+            "  \"bounces\": {},\n",
+            "  \"hits\": {},\n",
+            "  \"rejected_samples\": {},\n",
+            "  \"pixel_hash\": \"fnv1a64:{:016x}\"\n",
+            "}}\n",
+        ),
+        image.width(),
+        image.height(),
+        options.num_samples,
+        stats.passes,
+        options.seed,
+        options.num_workers,
+        stats.elapsed.as_secs_f64(),
+        stats.rays,
+        stats.bounces,
+        stats.hits,
+        stats.rejected_samples,
+        image.content_hash(),
+    )
 }
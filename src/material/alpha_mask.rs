@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use crate::{color::Color, geometry::Hit, raybow::WorkerState, texture::Texture, vector::Vector};
+
+use super::{Material, MaterialHitResult};
+
+/// Cuts out the parts of `material` where the alpha of `mask` is below `threshold`. Rays pass
+/// through them as if the surface wasn't there, e.g. around the leaves drawn on a flat card.
+pub struct AlphaMask {
+    pub material: Arc<dyn Material>,
+    pub mask: Arc<dyn Texture>,
+    pub threshold: f32,
+}
+
+impl Material for AlphaMask {
+    fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
+        self.material.hit(hit, state)
+    }
+
+    fn albedo(&self) -> Color {
+        self.material.albedo()
+    }
+
+    fn emitted(&self, hit: &Hit) -> Color {
+        self.material.emitted(hit)
+    }
+
+    fn scattering(&self, hit: &Hit, direction: Vector) -> Option<(Color, f32)> {
+        self.material.scattering(hit, direction)
+    }
+
+    fn passes_through(&self, hit: &Hit) -> bool {
+        self.mask.alpha(hit.uv) < self.threshold
+    }
+}
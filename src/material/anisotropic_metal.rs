@@ -0,0 +1,81 @@
+use std::f32::consts::TAU;
+
+use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState, vector::Vector};
+
+use super::{Material, MaterialHitResult, orthonormal_basis};
+
+/// Metal with an anisotropic GGX lobe, e.g. for brushed surfaces
+///
+/// Roughness is specified separately along the tangent and the bitangent of the hit. Objects
+/// have no UVs, so the tangent is `tangent` projected onto the plane perpendicular to the
+/// shading normal. Where no tangent is given, or the normal is parallel to it, an arbitrary
+/// but continuous basis is derived from the normal alone.
+pub struct AnisotropicMetal {
+    pub albedo: Color,
+    /// Roughness along the tangent
+    pub roughness_u: f32,
+    /// Roughness along the bitangent
+    pub roughness_v: f32,
+    /// World space direction the brushing runs along
+    pub tangent: Option<Vector>,
+}
+
+impl AnisotropicMetal {
+    fn tangent_frame(&self, n: Vector) -> (Vector, Vector) {
+        if let Some(tangent) = self.tangent {
+            let projected = tangent - n * n.dot(tangent);
+            if !projected.is_almost_zero() {
+                let t = projected.normalize_unchecked();
+                return (t, n.cross3(t));
+            }
+        }
+        orthonormal_basis(n)
+    }
+}
+
+impl Material for AnisotropicMetal {
+    fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
+        let n = hit.normal;
+        let (t, b) = self.tangent_frame(n);
+        let alpha_u = (self.roughness_u * self.roughness_u).max(1e-3);
+        let alpha_v = (self.roughness_v * self.roughness_v).max(1e-3);
+
+        let [u1, u2, ..] = state.gen_random_floats();
+
+        // Sample a microfacet normal proportional to D(h) * cos(theta_h)
+        let phi = u2 * TAU;
+        let (sin_phi, cos_phi) = (alpha_v * phi.sin()).atan2(alpha_u * phi.cos()).sin_cos();
+        let alpha2 = 1.0
+            / (cos_phi * cos_phi / (alpha_u * alpha_u) + sin_phi * sin_phi / (alpha_v * alpha_v));
+        let tan2_theta = alpha2 * u1 / (1.0 - u1);
+        let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let h = t * (sin_theta * cos_phi) + b * (sin_theta * sin_phi) + n * cos_theta;
+
+        let v = -hit.ray.direction.normalize_unchecked();
+        let l = (-v).reflect(h);
+
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 {
+            return MaterialHitResult::emitting(Color::BLACK);
+        }
+
+        let n_dot_v = n.dot(v).max(1e-4);
+        let n_dot_h = n.dot(h).max(1e-4);
+        let v_dot_h = v.dot(h).max(0.0);
+
+        let lambda = |w: Vector, w_n: f32| {
+            let (w_t, w_b) = (w.dot(t) * alpha_u, w.dot(b) * alpha_v);
+            (-1.0 + (1.0 + (w_t * w_t + w_b * w_b) / (w_n * w_n)).sqrt()) * 0.5
+        };
+        let g = 1.0 / ((1.0 + lambda(v, n_dot_v)) * (1.0 + lambda(l, n_dot_l)));
+
+        // BRDF * cos / pdf with the pdf of the reflected direction being D * n.h / (4 v.h)
+        let weight = g * v_dot_h / (n_dot_v * n_dot_h);
+        MaterialHitResult::reflecting(Ray::new(hit.point, l), self.albedo * weight)
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+}
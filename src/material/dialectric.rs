@@ -1,40 +1,89 @@
 use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState};
 
-use super::{Material, MaterialHitResult, reflect, refract};
+use super::{Material, MaterialHitResult, sample_ggx_normal};
 
 pub struct Dialectric {
+    /// Refraction index at the yellow helium d line of 587.6 nm
     pub index: f32,
+    /// Abbe number of the glass, which makes the index depend on the wavelength following
+    /// Cauchy's equation. Lower values disperse more, typical glasses range from about 20 to 90.
+    /// Without it, all wavelengths refract alike.
+    pub abbe: Option<f32>,
+    /// Absorption coefficient per unit distance travelled inside the medium. Paths are absorbed
+    /// from where they refract into the medium until they refract out of it again, including
+    /// any segments between objects inside it. Paths starting inside, like those of a camera
+    /// placed in the glass, are not.
+    pub absorption: Color,
+    /// Microfacet roughness, where 0 is perfectly smooth glass
+    pub roughness: f32,
 }
 
 impl Material for Dialectric {
     fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
-        let attenuation = Color::WHITE;
-        let refraction_ratio = if hit.front_face {
-            1.0 / self.index
+        let unit_vel = hit.ray.direction.normalize_unchecked();
+
+        let [reflect_threshold, u1, u2, u3] = state.gen_random_floats();
+
+        // Each wavelength refracts differently, so the path continues with only one of them
+        let index = match self.abbe {
+            Some(abbe) => cauchy_index(self.index, abbe, state.select_wavelength(u3)),
+            None => self.index,
+        };
+        let refraction_ratio = if hit.front_face { 1.0 / index } else { index };
+
+        let normal = if self.roughness > 0.0 {
+            let alpha = self.roughness * self.roughness;
+            sample_ggx_normal(hit.normal, alpha, u1, u2)
         } else {
-            self.index
+            hit.normal
         };
 
-        let unit_vel = hit.ray.direction.normalize_unchecked();
+        let cos_theta = (-unit_vel).dot(normal).min(1.0);
 
-        let cos_theta = (-unit_vel).dot(hit.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        // No refracted direction means total internal reflection
+        let refracted = unit_vel
+            .refract(normal, refraction_ratio)
+            .filter(|_| reflectance(cos_theta, refraction_ratio) <= reflect_threshold);
+        let cannot_refract = refracted.is_none();
+        let dir = refracted.unwrap_or_else(|| unit_vel.reflect(normal));
 
-        let [reflect_threshold, ..] = state.gen_random_floats();
+        // Sampled microfacets can face away from the ray, or reflect it into the surface and
+        // refract it out of it again, so those paths end here
+        if self.roughness > 0.0 {
+            let reflected = dir.dot(hit.normal) > 0.0;
+            if cos_theta <= 0.0 || reflected != cannot_refract {
+                return MaterialHitResult::emitting(Color::BLACK);
+            }
+        }
 
-        let cannot_refract = refraction_ratio * sin_theta > 1.0
-            || reflectance(cos_theta, refraction_ratio) > reflect_threshold;
+        if !cannot_refract {
+            if hit.front_face && self.absorption != Color::BLACK {
+                state.enter_medium(self.absorption);
+            } else {
+                state.leave_medium();
+            }
+        }
 
-        let dir = if cannot_refract {
-            reflect(unit_vel, hit.normal)
-        } else {
-            refract(unit_vel, hit.normal, cos_theta, refraction_ratio)
-        };
+        MaterialHitResult::reflecting(Ray::new(hit.point, dir), Color::WHITE)
+    }
 
-        MaterialHitResult::reflecting(Ray::new(hit.point, dir), attenuation)
+    fn albedo(&self) -> Color {
+        Color::WHITE
     }
 }
 
+/// Evaluates Cauchy's equation `A + B / λ²` fitted to the index at the helium d line and the
+/// Abbe number, which relates it to the indices at the hydrogen F and C lines
+fn cauchy_index(index_d: f32, abbe: f32, wavelength: f32) -> f32 {
+    const D: f32 = 587.6;
+    const F: f32 = 486.1;
+    const C: f32 = 656.3;
+
+    let b = (index_d - 1.0) / (abbe * (1.0 / (F * F) - 1.0 / (C * C)));
+    let a = index_d - b / (D * D);
+    a + b / (wavelength * wavelength)
+}
+
 fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     let r0 = r0 * r0;
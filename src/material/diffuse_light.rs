@@ -1,13 +1,37 @@
-use crate::{Color, geometry::Hit, raybow::WorkerState};
+use std::sync::Arc;
+
+use crate::{
+    Color,
+    geometry::{Hit, Uv},
+    raybow::WorkerState,
+    texture::Texture,
+};
 
 use super::{Material, MaterialHitResult};
 
 pub struct DiffuseLight {
-    pub emit: Color,
+    /// Emitted radiance over the surface, which may be a plain [`Color`]
+    pub emit: Arc<dyn Texture>,
+    /// Whether the back face emits as well. One-sided lights only emit from the side the
+    /// surface normal points to.
+    pub two_sided: bool,
 }
 
 impl Material for DiffuseLight {
-    fn hit(&self, _hit: &Hit, _state: &mut WorkerState) -> MaterialHitResult {
-        MaterialHitResult::emitting(self.emit)
+    fn hit(&self, hit: &Hit, _state: &mut WorkerState) -> MaterialHitResult {
+        MaterialHitResult::emitting(self.emitted(hit))
+    }
+
+    // The emission at the center of the texture
+    fn albedo(&self) -> Color {
+        self.emit.color(Uv::Planar([0.5; 2]))
+    }
+
+    fn emitted(&self, hit: &Hit) -> Color {
+        if self.two_sided || hit.front_face {
+            self.emit.color(hit.uv)
+        } else {
+            Color::BLACK
+        }
     }
 }
@@ -0,0 +1,28 @@
+use std::f32::consts::PI;
+
+use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState, vector::Vector};
+
+use super::{Material, MaterialHitResult, random_unit_vector};
+
+/// Scatters into a uniformly random direction regardless of the surface normal, like the
+/// particles inside a participating medium
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Material for Isotropic {
+    fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
+        let scattered = Ray::new(hit.point, random_unit_vector(state));
+        MaterialHitResult::reflecting(scattered, self.albedo)
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    // The phase function is as uniform as the sampling, so the two cancel out
+    fn scattering(&self, _hit: &Hit, _direction: Vector) -> Option<(Color, f32)> {
+        let pdf = 1.0 / (4.0 * PI);
+        Some((self.albedo * pdf, pdf))
+    }
+}
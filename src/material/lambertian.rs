@@ -1,9 +1,13 @@
-use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState};
+use std::f32::consts::FRAC_1_PI;
+
+use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState, vector::Vector};
 
 use super::{Material, MaterialHitResult, random_unit_vector};
 
 pub struct Lambertian {
     pub albedo: Color,
+    /// Light emitted by the surface in addition to the light it reflects
+    pub emission: Color,
 }
 
 impl Material for Lambertian {
@@ -15,6 +19,20 @@ impl Material for Lambertian {
         }
 
         let scattered = Ray::new(hit.point, scatter_dir);
-        MaterialHitResult::reflecting(scattered, self.albedo)
+        MaterialHitResult::reflecting(scattered, self.albedo).with_emission(self.emission)
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn emitted(&self, _hit: &Hit) -> Color {
+        self.emission
+    }
+
+    // Scattering around the normal by a random unit vector is cosine-weighted
+    fn scattering(&self, hit: &Hit, direction: Vector) -> Option<(Color, f32)> {
+        let pdf = hit.normal.dot(direction).max(0.0) * FRAC_1_PI;
+        Some((self.albedo * pdf, pdf))
     }
 }
@@ -1,18 +1,28 @@
 use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState};
 
-use super::{Material, MaterialHitResult, random_in_unit_sphere, reflect};
+use super::{Material, MaterialHitResult, random_in_unit_sphere};
 
 pub struct Metal {
     pub albedo: Color,
     pub fuzz: f32,
+    /// Light emitted by the surface in addition to the light it reflects
+    pub emission: Color,
 }
 
 impl Material for Metal {
     fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
         let fuzz_dir = random_in_unit_sphere(state);
 
-        let reflected = reflect(hit.ray.direction.normalize_unchecked(), hit.normal);
+        let reflected = hit.ray.direction.normalize_unchecked().reflect(hit.normal);
         let scattered = Ray::new(hit.point, reflected + self.fuzz * fuzz_dir);
-        MaterialHitResult::reflecting(scattered, self.albedo)
+        MaterialHitResult::reflecting(scattered, self.albedo).with_emission(self.emission)
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn emitted(&self, _hit: &Hit) -> Color {
+        self.emission
     }
 }
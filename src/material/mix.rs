@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use crate::{color::Color, geometry::Hit, raybow::WorkerState};
+
+use super::{Material, MaterialHitResult};
+
+/// Stochastically blends two materials by delegating each hit to one of them
+pub struct MixMaterial {
+    pub first: Arc<dyn Material>,
+    pub second: Arc<dyn Material>,
+    /// Probability of choosing `second`, where 0 behaves like `first` only
+    pub factor: f32,
+}
+
+impl Material for MixMaterial {
+    fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
+        let [choice, ..] = state.gen_random_floats();
+        if choice < self.factor {
+            self.second.hit(hit, state)
+        } else {
+            self.first.hit(hit, state)
+        }
+    }
+
+    fn albedo(&self) -> Color {
+        self.first.albedo().lerp(self.second.albedo(), self.factor)
+    }
+
+    fn emitted(&self, hit: &Hit) -> Color {
+        self.first
+            .emitted(hit)
+            .lerp(self.second.emitted(hit), self.factor)
+    }
+}
@@ -2,18 +2,57 @@ use std::f32::consts::TAU;
 
 use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState, vector::Vector};
 
+pub use alpha_mask::AlphaMask;
+pub use anisotropic_metal::AnisotropicMetal;
 pub use dialectric::Dialectric;
 pub use diffuse_light::DiffuseLight;
+pub use isotropic::Isotropic;
 pub use lambertian::Lambertian;
 pub use metal::Metal;
+pub use mix::MixMaterial;
+pub use oren_nayar::OrenNayar;
+pub use pbr::Pbr;
+pub use spotlight::Spotlight;
 
+mod alpha_mask;
+mod anisotropic_metal;
 mod dialectric;
 mod diffuse_light;
+mod isotropic;
 mod lambertian;
 mod metal;
+mod mix;
+mod oren_nayar;
+mod pbr;
+mod spotlight;
 
 pub trait Material: Send + Sync {
     fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult;
+
+    /// Base color of the surface, used for the albedo AOV
+    fn albedo(&self) -> Color;
+
+    /// Light emitted back along `hit.ray`, on average over the random choices of
+    /// [`Material::hit`]. Next event estimation uses it for the surfaces its rays hit.
+    fn emitted(&self, _hit: &Hit) -> Color {
+        Color::BLACK
+    }
+
+    /// Scattering from the normalized `direction` back along `hit.ray`, as the BSDF times the
+    /// cosine with the normal together with the density over solid angle with which
+    /// [`Material::hit`] picks that direction. Their ratio is the attenuation `hit` returns.
+    ///
+    /// Next event estimation only samples lights from surfaces whose material returns `Some`,
+    /// so materials that only reflect into a narrow lobe, like mirrors, keep the default.
+    fn scattering(&self, _hit: &Hit, _direction: Vector) -> Option<(Color, f32)> {
+        None
+    }
+
+    /// Whether rays continue through `hit` as if the surface wasn't there, like through the
+    /// cut-out parts of an [`AlphaMask`]
+    fn passes_through(&self, _hit: &Hit) -> bool {
+        false
+    }
 }
 
 pub struct Reflection {
@@ -40,6 +79,10 @@ impl MaterialHitResult {
             emission,
         }
     }
+
+    fn with_emission(self, emission: Color) -> Self {
+        Self { emission, ..self }
+    }
 }
 
 // https://math.stackexchange.com/a/44691
@@ -63,12 +106,26 @@ fn random_in_unit_sphere(state: &mut WorkerState) -> Vector {
     unit_vector_from_cylinder(angle, -1.0 + z * 2.0) * len
 }
 
-fn reflect(v: Vector, n: Vector) -> Vector {
-    v - 2.0 * v.dot(n) * n
+// https://graphics.pixar.com/library/OrthonormalB/paper.pdf
+pub(crate) fn orthonormal_basis(n: Vector) -> (Vector, Vector) {
+    let sign = 1.0f32.copysign(n.z());
+    let a = -1.0 / (sign + n.z());
+    let b = n.x() * n.y() * a;
+
+    let tangent = Vector::from_xyz(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x());
+    let bitangent = Vector::from_xyz(b, sign + n.y() * n.y() * a, -n.y());
+
+    (tangent, bitangent)
 }
 
-fn refract(uv: Vector, n: Vector, cos_theta: f32, etai_over_etat: f32) -> Vector {
-    let r_out_perp = etai_over_etat * (uv + cos_theta * n);
-    let r_out_parallel = (-(1.0 - r_out_perp.length_squared()).abs().sqrt()) * n;
-    r_out_perp + r_out_parallel
+/// Samples a microfacet normal around `n` from the GGX distribution with roughness `alpha`
+fn sample_ggx_normal(n: Vector, alpha: f32, u1: f32, u2: f32) -> Vector {
+    let (tangent, bitangent) = orthonormal_basis(n);
+
+    let tan2_theta = alpha * alpha * u1 / (1.0 - u1);
+    let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = u2 * TAU;
+
+    tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + n * cos_theta
 }
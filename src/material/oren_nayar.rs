@@ -0,0 +1,58 @@
+use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState};
+
+use super::{Material, MaterialHitResult, random_unit_vector};
+
+/// Rough diffuse material following the Oren-Nayar model. A roughness of 0 is identical to
+/// [`Lambertian`](super::Lambertian).
+pub struct OrenNayar {
+    pub albedo: Color,
+    /// Standard deviation of the microfacet slope angle in radians
+    pub roughness: f32,
+}
+
+impl Material for OrenNayar {
+    fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
+        let n = hit.normal;
+        let mut scatter_dir = n + random_unit_vector(state);
+
+        if scatter_dir.is_almost_zero() {
+            scatter_dir = n;
+        }
+
+        let sigma2 = self.roughness * self.roughness;
+        let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+        // The sampled direction is cosine-distributed, so only the Oren-Nayar factor remains
+        // of the BRDF after dividing by the pdf
+        let mut factor = a;
+        if b > 0.0 {
+            let v = -hit.ray.direction.normalize_unchecked();
+            let l = scatter_dir.normalize_unchecked();
+            let cos_v = n.dot(v).clamp(0.0, 1.0);
+            let cos_l = n.dot(l).clamp(0.0, 1.0);
+
+            let v_perp = v - n * cos_v;
+            let l_perp = l - n * cos_l;
+            let perp_len2 = v_perp.length_squared() * l_perp.length_squared();
+            let cos_phi = if perp_len2 > 0.0 {
+                (v_perp.dot(l_perp) / perp_len2.sqrt()).max(0.0)
+            } else {
+                0.0
+            };
+
+            let (cos_alpha, cos_beta) = (cos_v.min(cos_l), cos_v.max(cos_l));
+            let sin_alpha = (1.0 - cos_alpha * cos_alpha).sqrt();
+            let tan_beta = (1.0 - cos_beta * cos_beta).sqrt() / cos_beta.max(1e-4);
+
+            factor += b * cos_phi * sin_alpha * tan_beta;
+        }
+
+        let scattered = Ray::new(hit.point, scatter_dir);
+        MaterialHitResult::reflecting(scattered, self.albedo * factor)
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+}
@@ -0,0 +1,85 @@
+use std::f32::consts::{FRAC_1_PI, PI};
+
+use crate::{color::Color, geometry::Hit, ray::Ray, raybow::WorkerState};
+
+use super::{Material, MaterialHitResult, sample_ggx_normal, unit_vector_from_cylinder};
+
+/// Metallic-roughness material using a Cook-Torrance GGX specular lobe over a Lambertian base
+pub struct Pbr {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Light emitted by the surface in addition to the light it reflects
+    pub emission: Color,
+}
+
+impl Material for Pbr {
+    fn hit(&self, hit: &Hit, state: &mut WorkerState) -> MaterialHitResult {
+        let n = hit.normal;
+        let v = -hit.ray.direction;
+        let alpha = (self.roughness * self.roughness).max(1e-3);
+
+        let [lobe, u1, u2, _] = state.gen_random_floats();
+
+        // Metals have no diffuse lobe, so the specular lobe is sampled more often the more
+        // metallic the surface is
+        let specular_probability = 0.5 + 0.5 * self.metallic;
+
+        let l = if lobe < specular_probability {
+            (-v).reflect(sample_ggx_normal(n, alpha, u1, u2))
+        } else {
+            let dir = n + unit_vector_from_cylinder(u1, -1.0 + u2 * 2.0);
+            if dir.is_almost_zero() {
+                n
+            } else {
+                dir.normalize_unchecked()
+            }
+        };
+
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 {
+            return MaterialHitResult::emitting(self.emission);
+        }
+
+        let h = (v + l).normalize_unchecked();
+        let n_dot_v = n.dot(v).max(1e-4);
+        let n_dot_h = n.dot(h).max(0.0);
+        let v_dot_h = v.dot(h).max(1e-4);
+
+        let d = ggx_distribution(n_dot_h, alpha);
+        let g = smith_g1(n_dot_v, alpha) * smith_g1(n_dot_l, alpha);
+
+        let f0 = Color::from_rgb(0.04, 0.04, 0.04).lerp(self.base_color, self.metallic);
+        let fresnel = f0 + (Color::WHITE - f0) * (1.0 - v_dot_h).powi(5);
+
+        let specular = fresnel * (d * g / (4.0 * n_dot_v * n_dot_l));
+        let diffuse =
+            (Color::WHITE - fresnel) * self.base_color * ((1.0 - self.metallic) * FRAC_1_PI);
+
+        let pdf = specular_probability * d * n_dot_h / (4.0 * v_dot_h)
+            + (1.0 - specular_probability) * n_dot_l * FRAC_1_PI;
+
+        let attenuation = (specular + diffuse) * (n_dot_l / pdf);
+        MaterialHitResult::reflecting(Ray::new(hit.point, l), attenuation)
+            .with_emission(self.emission)
+    }
+
+    fn albedo(&self) -> Color {
+        self.base_color
+    }
+
+    fn emitted(&self, _hit: &Hit) -> Color {
+        self.emission
+    }
+}
+
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let denom = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denom * denom)
+}
+
+fn smith_g1(n_dot_x: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    2.0 * n_dot_x / (n_dot_x + (alpha2 + (1.0 - alpha2) * n_dot_x * n_dot_x).sqrt())
+}
@@ -0,0 +1,45 @@
+use crate::{Color, geometry::Hit, raybow::WorkerState, vector::Vector};
+
+use super::{Material, MaterialHitResult};
+
+/// An emitter that only shines into a cone around `direction`
+pub struct Spotlight {
+    pub emit: Color,
+    pub direction: Vector,
+    /// Half-angle in radians of the cone that receives the full emission
+    pub inner_angle: f32,
+    /// Half-angle in radians beyond which nothing is emitted. Emission fades out smoothly
+    /// between the inner and the outer angle.
+    pub outer_angle: f32,
+}
+
+impl Material for Spotlight {
+    fn hit(&self, hit: &Hit, _state: &mut WorkerState) -> MaterialHitResult {
+        MaterialHitResult::emitting(self.emitted(hit))
+    }
+
+    fn albedo(&self) -> Color {
+        self.emit
+    }
+
+    fn emitted(&self, hit: &Hit) -> Color {
+        // The light leaving the surface travels back along the incoming ray
+        let cos_angle = (-hit.ray.direction)
+            .normalize_unchecked()
+            .dot(self.direction.normalize_unchecked());
+
+        let cos_inner = self.inner_angle.cos();
+        let cos_outer = self.outer_angle.cos();
+        let falloff = smoothstep(cos_outer, cos_inner, cos_angle);
+
+        self.emit * falloff
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge0 >= edge1 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+pub struct ObjMesh {
+    /// Vertex positions as consecutive xyz triples
+    pub vertices: Vec<f32>,
+    /// Three vertex indices per triangle. Polygons are split into triangle fans.
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    InvalidVertex { line: usize },
+    InvalidFace { line: usize },
+    IndexOutOfRange { line: usize, index: i64 },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidVertex { line } => write!(f, "line {line}: invalid vertex"),
+            Self::InvalidFace { line } => write!(f, "line {line}: invalid face"),
+            Self::IndexOutOfRange { line, index } => {
+                write!(f, "line {line}: vertex index {index} out of range")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Reads the triangle geometry of a Wavefront OBJ file. Texture coordinates, normals, groups and
+/// materials are ignored.
+pub fn parse(source: &str) -> Result<ObjMesh, ObjError> {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut face = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_number = line_idx + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("v") => {
+                let mut coords = tokens.map(str::parse::<f32>);
+                for _ in 0..3 {
+                    match coords.next() {
+                        Some(Ok(coord)) => vertices.push(coord),
+                        _ => return Err(ObjError::InvalidVertex { line: line_number }),
+                    }
+                }
+            }
+            Some("f") => {
+                let num_vertices = (vertices.len() / 3) as i64;
+
+                face.clear();
+                for token in tokens {
+                    // Only the position index matters in `v`, `v/vt`, `v//vn` and `v/vt/vn`
+                    let index = token
+                        .split('/')
+                        .next()
+                        .and_then(|index| index.parse::<i64>().ok())
+                        .ok_or(ObjError::InvalidFace { line: line_number })?;
+
+                    // Indices are 1-based, negative ones count back from the latest vertex
+                    let resolved = if index < 0 {
+                        num_vertices + index
+                    } else {
+                        index - 1
+                    };
+                    if !(0..num_vertices).contains(&resolved) {
+                        return Err(ObjError::IndexOutOfRange {
+                            line: line_number,
+                            index,
+                        });
+                    }
+                    face.push(resolved as u32);
+                }
+
+                if face.len() < 3 {
+                    return Err(ObjError::InvalidFace { line: line_number });
+                }
+                for i in 1..face.len() - 1 {
+                    indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(ObjMesh { vertices, indices })
+}
@@ -1,9 +1,11 @@
 use std::{
-    io::Write,
-    iter,
+    fmt, mem,
+    ops::{AddAssign, Mul, MulAssign, Range},
+    panic::{self, AssertUnwindSafe},
+    str::FromStr,
     sync::{
-        Arc,
-        atomic::{AtomicU32, Ordering},
+        Arc, Barrier, Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     },
     thread,
     time::{Duration, SystemTime},
@@ -11,19 +13,165 @@ use std::{
 
 use bumpalo::Bump;
 
+#[cfg(feature = "spectral")]
+use crate::spectrum::{self, SampledSpectrum};
 use crate::{
+    background::Background,
     camera::Camera,
     color::Color,
-    geometry::{Object, bvh::Bvh},
-    image::Image,
+    geometry::{
+        Hit, LightSampleable, Object,
+        bvh::{self, Bvh},
+    },
+    image::{Image, Rect},
     material::Reflection,
     philox::Philox4x32_10,
     ray::Ray,
+    sobol,
     sync_unsafe_cell::SyncUnsafeCell,
+    vector::Vector,
 };
 
+/// Source of the random numbers used for pixel offsets, lens positions and scattering
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Sampler {
+    /// Independent pseudo-random numbers
+    #[default]
+    Philox,
+    /// Owen-scrambled Sobol points, which converge faster than independent samples and work
+    /// best with a power of two number of samples
+    Sobol,
+}
+
+impl FromStr for Sampler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "philox" => Ok(Self::Philox),
+            "sobol" => Ok(Self::Sobol),
+            _ => Err(format!("unsupported sampler: {s}")),
+        }
+    }
+}
+
+/// Reconstruction filter that weights each sample by its offset from the pixel center
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Equal weights for samples within the pixel
+    #[default]
+    Box,
+    /// Weights falling off linearly to zero one pixel away from the center
+    Tent,
+    /// Gaussian weights truncated at the given radius in pixels, which reduces aliasing at edges
+    /// at the cost of a slightly softer image
+    Gaussian(f32),
+}
+
+impl Filter {
+    /// Maximum offset of a sample from the pixel center along each axis
+    fn radius(self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian(radius) => radius,
+        }
+    }
+
+    fn weight(self, dx: f32, dy: f32) -> f32 {
+        match self {
+            Self::Box => 1.0,
+            Self::Tent => (1.0 - dx.abs()).max(0.0) * (1.0 - dy.abs()).max(0.0),
+            Self::Gaussian(radius) => {
+                // The standard deviation is half the radius. Subtracting the value at the radius
+                // makes the weights reach zero there instead of cutting off abruptly.
+                let gaussian = |d: f32| (-2.0 * d * d / (radius * radius)).exp();
+                let edge = gaussian(radius);
+                (gaussian(dx) - edge).max(0.0) * (gaussian(dy) - edge).max(0.0)
+            }
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "box" => Ok(Self::Box),
+            None if s == "tent" => Ok(Self::Tent),
+            None if s == "gaussian" => Ok(Self::Gaussian(1.5)),
+            Some(("gaussian", radius)) => radius
+                .parse()
+                .map(Self::Gaussian)
+                .map_err(|_| format!("invalid filter radius: {radius}")),
+            _ => Err(format!("unsupported filter: {s}")),
+        }
+    }
+}
+
+/// How light is represented along the paths of [`RenderMode::PathTrace`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorModel {
+    /// Red, green and blue are transported as three independent channels
+    #[default]
+    Rgb,
+    /// Each path carries a hero wavelength and three more spaced evenly across the visible
+    /// range. RGB reflectances and emissions are turned into spectra along the way and the
+    /// sensor converts the result back through the CIE color-matching functions, so colored
+    /// lights and dispersion mix like they physically do.
+    #[cfg(feature = "spectral")]
+    Spectral,
+}
+
+impl FromStr for ColorModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(Self::Rgb),
+            #[cfg(feature = "spectral")]
+            "spectral" => Ok(Self::Spectral),
+            #[cfg(not(feature = "spectral"))]
+            "spectral" => Err("spectral rendering requires the spectral feature".to_string()),
+            _ => Err(format!("unsupported color model: {s}")),
+        }
+    }
+}
+
+/// What the color of a sample shows
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Light transported along full paths
+    #[default]
+    PathTrace,
+    /// The outward normal at the first hit mapped to RGB as `0.5 * (normal + 1)`, or black for
+    /// misses. Unlike the normal AOV it is not flipped towards the ray, so back faces and
+    /// inverted meshes stand out.
+    Normals,
+    /// The number of BVH nodes and primitives visited by the camera ray, mapped through a color
+    /// ramp from black over blue, green and yellow to red at `max_visits`
+    TraversalCost { max_visits: u32 },
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pathtrace" => Ok(Self::PathTrace),
+            "normals" => Ok(Self::Normals),
+            "cost" => Ok(Self::TraversalCost { max_visits: 64 }),
+            _ => Err(format!("unsupported render mode: {s}")),
+        }
+    }
+}
+
 pub struct WorkerState {
     philox: Philox4x32_10,
+    // The key derived from the seed, which `philox` differs from while pixels are scrambled
+    seed_key: [u32; 2],
+    sampler: Sampler,
     // The current pixel number
     pixel_number: u32,
     // The sample number of the current pixel
@@ -31,18 +179,50 @@ pub struct WorkerState {
     // The ray number of the current sample
     ray_number: u32,
     rng_cnt: u32,
+    // The view of a multi-view render, which shares the last counter word with `rng_cnt`
+    view: u32,
+    // The wavelengths of the current sample in a spectral render
+    wavelengths: Option<[f32; 4]>,
+    // The color channel or index into `wavelengths` the current sample was restricted to by a
+    // wavelength dependent material
+    channel: Option<usize>,
+    // Absorption coefficient of the medium the current path has entered, set and cleared by
+    // the materials bounding it
+    medium_absorption: Option<Color>,
     arena: Bump,
+    stats: RenderStats,
 }
 
 impl WorkerState {
-    fn new(seed: u64) -> Self {
+    fn new(seed: u64, sampler: Sampler, view: u32, arena_capacity: usize) -> Self {
         Self {
-            philox: Philox4x32_10([(seed >> 32) as u32, seed as u32]),
+            philox: Philox4x32_10(seed_key(seed)),
+            seed_key: seed_key(seed),
+            sampler,
+            view,
             pixel_number: 0,
             sample_number: 0,
             ray_number: 0,
             rng_cnt: 0,
-            arena: Bump::new(),
+            wavelengths: None,
+            channel: None,
+            medium_absorption: None,
+            arena: Bump::with_capacity(arena_capacity),
+            stats: RenderStats::default(),
+        }
+    }
+
+    // Prepares a state from an earlier render for a new one, keeping the arena's memory unless
+    // it is smaller than `arena_capacity`
+    fn reset(&mut self, seed: u64, sampler: Sampler, view: u32, arena_capacity: usize) {
+        self.philox = Philox4x32_10(seed_key(seed));
+        self.seed_key = seed_key(seed);
+        self.sampler = sampler;
+        self.view = view;
+        self.stats = RenderStats::default();
+        self.arena.reset();
+        if self.arena.chunk_capacity() < arena_capacity {
+            self.arena = Bump::with_capacity(arena_capacity);
         }
     }
 
@@ -55,158 +235,1668 @@ impl WorkerState {
         self.sample_number = sample_number;
         self.ray_number = 0;
         self.rng_cnt = 0;
+        self.wavelengths = None;
+        self.channel = None;
+        self.medium_absorption = None;
+    }
+
+    /// Folds the pixel coordinates into the key of the random number generator, so that the
+    /// numbers of adjacent pixels share no structure through their counters. Lasts until the
+    /// next call.
+    fn scramble_pixel(&mut self, x: u32, y: u32) {
+        let [k0, k1, ..] = Philox4x32_10(self.seed_key).generate([x, y, u32::MAX, u32::MAX]);
+        self.philox = Philox4x32_10([k0, k1]);
+    }
+
+    /// Restricts the rest of the sample to a single wavelength in nanometers, for materials
+    /// that behave differently for each like dispersive glass. The first call of an RGB sample
+    /// picks one of the channels from the uniform random number `u`, while a spectral sample
+    /// keeps its hero wavelength. Later calls return the same one. The renderer weighs the
+    /// sample by the wavelengths it drops, so its expected color stays the same.
+    pub fn select_wavelength(&mut self, u: f32) -> f32 {
+        let channel = *self.channel.get_or_insert_with(|| match self.wavelengths {
+            Some(_) => 0,
+            None => ((u * 3.0) as usize).min(2),
+        });
+        match self.wavelengths {
+            Some(wavelengths) => wavelengths[channel],
+            None => CHANNEL_WAVELENGTHS[channel],
+        }
+    }
+
+    /// Lets the renderer absorb light along the rest of the path following Beer's law, with
+    /// `absorption` per unit distance travelled, until [`WorkerState::leave_medium`] is called.
+    /// For materials whose surface bounds an absorbing volume, like tinted glass.
+    pub fn enter_medium(&mut self, absorption: Color) {
+        self.medium_absorption = Some(absorption);
+    }
+
+    /// Stops the absorption started by [`WorkerState::enter_medium`]
+    pub fn leave_medium(&mut self) {
+        self.medium_absorption = None;
     }
 
+    // Fraction of the light the medium the path has entered lets through over `distance`, or
+    // `None` outside of any
+    fn medium_transmittance(&self, distance: f32) -> Option<Color> {
+        let Color { r, g, b } = self.medium_absorption? * -distance;
+        Some(Color::from_rgb(r.exp(), g.exp(), b.exp()))
+    }
+
+    /// Starts the random numbers of the next ray along the path. The counter restarts, so the
+    /// numbers a ray gets don't depend on how many the previous one drew, e.g. for the lens.
+    fn next_ray(&mut self) {
+        self.ray_number += 1;
+        self.rng_cnt = 0;
+    }
+
+    /// Returns four uniform random numbers in `[0, 1)`. They are derived from the counter
+    /// `[pixel, sample, ray, view << 16 | draw]`, which no other call of the render repeats,
+    /// so the numbers of different pixels, samples and views are independent of each other and
+    /// of the worker computing them.
     pub fn gen_random_floats(&mut self) -> [f32; 4] {
         let ctr = [
             self.pixel_number,
             self.sample_number,
             self.ray_number,
-            self.rng_cnt,
+            self.view << 16 | self.rng_cnt,
         ];
         self.rng_cnt += 1;
-        self.philox.gen_f32s(ctr)
+
+        match self.sampler {
+            Sampler::Philox => self.philox.gen_f32s(ctr),
+            Sampler::Sobol => {
+                // Every pixel and dimension gets its own scrambling, which must stay the same
+                // for all samples of the pixel, so the sample number is replaced by a marker
+                let [pixel, sample, ray, cnt] = ctr;
+                let [s0, s1, s2, s3] = self.philox.generate([pixel, u32::MAX, ray, cnt]);
+                let [s4, s5, ..] = self.philox.generate([pixel, u32::MAX - 1, ray, cnt]);
+
+                let [a, b] = sobol::sample_2d(sample, [s0, s1, s2]);
+                let [c, d] = sobol::sample_2d(sample, [s3, s4, s5]);
+                [a, b, c, d].map(|x| (x >> 8) as f32 * (f32::EPSILON / 2.))
+            }
+        }
+    }
+
+    /// Generates `N` random floats from as many calls to [`WorkerState::gen_random_floats`]
+    /// as needed, discarding the unused rest of the last block
+    pub fn gen_n_floats<const N: usize>(&mut self) -> [f32; N] {
+        let mut floats = [0.0; N];
+        for chunk in floats.chunks_mut(4) {
+            chunk.copy_from_slice(&self.gen_random_floats()[..chunk.len()]);
+        }
+        floats
+    }
+}
+
+fn seed_key(seed: u64) -> [u32; 2] {
+    [(seed >> 32) as u32, seed as u32]
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenderStats {
+    /// Number of rays traced through the scene, including scattered rays
+    pub rays: u64,
+    /// Number of times a ray scattered off a surface
+    pub bounces: u64,
+    /// Number of rays that hit an object
+    pub hits: u64,
+    /// Number of samples whose color had non-finite channels that were replaced by zero
+    pub rejected_samples: u64,
+    /// Number of camera samples taken. With adaptive sampling this is below the number of
+    /// pixels times the sample count.
+    pub samples: u64,
+    /// Time spent building the BVH over the job's objects
+    pub bvh_build_time: Duration,
+    /// Number of interior nodes of that BVH
+    pub bvh_branches: u64,
+    /// Number of sample passes run, which ends below the sample count when the render converges
+    /// early
+    pub passes: u32,
+    pub elapsed: Duration,
+}
+
+impl RenderStats {
+    fn merge(&mut self, other: &Self) {
+        self.rays += other.rays;
+        self.bounces += other.bounces;
+        self.hits += other.hits;
+        self.rejected_samples += other.rejected_samples;
+        self.samples += other.samples;
+    }
+}
+
+/// A light infinitely far away, which rays leaving the scene hit when they point into its disc
+#[derive(Debug, Clone, Copy)]
+pub struct Sun {
+    /// Direction towards the sun
+    pub direction: Vector,
+    pub emission: Color,
+    /// Angular radius of the sun's disc in radians. Without light sampling, small suns are only
+    /// found by few paths and converge slowly.
+    pub angular_radius: f32,
+}
+
+impl Sun {
+    fn emission_towards(&self, direction: Vector) -> Color {
+        let cos_angle = direction.dot(self.direction.normalize_unchecked());
+        if cos_angle >= self.angular_radius.cos() {
+            self.emission
+        } else {
+            Color::BLACK
+        }
     }
 }
 
-pub struct RenderJob<'a> {
+/// Objects default to `Arc<dyn Object>` so they can be shared with other jobs, but any
+/// [`Object`] works, e.g. `Box<dyn Object>` for geometry owned by a single job.
+pub struct RenderJob<'a, O = Arc<dyn Object>> {
     pub camera: &'a Camera,
-    pub objects: Vec<Arc<dyn Object>>,
-    pub background: Color,
+    pub objects: Vec<O>,
+    pub background: Arc<dyn Background>,
+    pub sun: Option<Sun>,
+    /// Emitters among `objects` whose light is sampled directly at diffuse surfaces and
+    /// combined with the scattered rays through multiple importance sampling. Listing a light
+    /// only reduces noise, it does not change what the image converges to.
+    pub lights: Vec<Arc<dyn LightSampleable>>,
     pub num_samples: u32,
+    /// Index of the first sample to take per pixel. Sample `i` of a pixel always gets the same
+    /// random numbers, so renders of disjoint sample ranges can be split across machines and
+    /// averaged, weighted by their sample counts, to match a single render of all samples.
+    pub first_sample: u32,
+    /// Key of the random number generator. Philox is counter based: each block of four random
+    /// numbers is a keyed bijection of the counter `[pixel, sample, ray, view << 16 | draw]`,
+    /// where the view of a multi-view render and the draw index share the last word. Distinct
+    /// seeds are distinct keys, whose streams are statistically independent but not disjoint:
+    /// they can produce the same numbers, just no more often than unrelated random sources.
+    /// Distributed renders therefore only need a distinct seed each, such as their job index.
     pub seed: u64,
+    /// Derives a key for every pixel from its coordinates and the seed. Philox already gives
+    /// adjacent pixels uncorrelated noise through their counters, and no tested sampler or
+    /// scene shows a measurable difference, so this only guards against structure a weaker
+    /// generator could leave. Renders stay deterministic, but differ from those without it.
+    pub scramble_pixels: bool,
     pub num_workers: usize,
+    pub max_bounces: u32,
+    /// Ends paths at random once their throughput gets low, weighting the surviving ones up so
+    /// the image converges to the same result. Paths still end at `max_bounces`.
+    pub russian_roulette: bool,
+    /// Bounces every path takes before Russian roulette may end it, which keeps light arriving
+    /// after a few bounces, e.g. through glass onto a diffuse surface, from being cut off early
+    pub min_bounces: u32,
+    /// Minimum distance along a ray for hits to count, which keeps scattered rays from hitting
+    /// the surface they start on. Should be scaled along with the scene.
+    pub ray_epsilon: f32,
+    pub sampler: Sampler,
+    pub mode: RenderMode,
+    pub color_model: ColorModel,
+    pub filter: Filter,
+    /// Pixels to render, where `None` renders the whole image. Pixels outside of it are left
+    /// untouched in the image and the AOVs.
+    pub region: Option<Rect>,
+    /// Enables adaptive sampling. A pixel stops receiving samples once the 95% confidence
+    /// interval of its luminance is within this fraction of its mean, so `num_samples` becomes
+    /// an upper bound.
+    pub target_error: Option<f32>,
+    /// Ends the render once a pass changes the image by less than this mean relative amount per
+    /// pixel, so `num_samples` becomes an upper bound
+    pub target_convergence: Option<f32>,
+    /// Bytes each worker preallocates for the per-ray scratch memory, where `None` sizes it for
+    /// a traversal of the scene's BVH. Larger values spare scenes with nested BVHs the
+    /// reallocations of their first rays.
+    pub arena_capacity: Option<usize>,
 }
 
-pub fn render(job: RenderJob<'_>, image: &mut Image) {
-    let start_time = SystemTime::now();
+impl<'a, O: Object> RenderJob<'a, O> {
+    pub fn new(camera: &'a Camera, objects: Vec<O>) -> Self {
+        Self {
+            camera,
+            objects,
+            background: Arc::new(Color::BLACK),
+            sun: None,
+            lights: Vec::new(),
+            num_samples: 500,
+            first_sample: 0,
+            seed: 0,
+            scramble_pixels: false,
+            num_workers: num_cpus::get(),
+            max_bounces: 50,
+            russian_roulette: false,
+            min_bounces: 3,
+            ray_epsilon: 0.0001,
+            sampler: Sampler::Philox,
+            mode: RenderMode::PathTrace,
+            color_model: ColorModel::Rgb,
+            filter: Filter::Box,
+            region: None,
+            target_error: None,
+            target_convergence: None,
+            arena_capacity: None,
+        }
+    }
 
-    let image_width = image.width();
-    let image_height = image.height();
+    pub fn builder(camera: &'a Camera) -> RenderJobBuilder<'a, O> {
+        RenderJobBuilder {
+            job: Self::new(camera, Vec::new()),
+        }
+    }
+}
 
-    let bvh = Bvh::new(job.objects);
+/// Builds a [`RenderJob`] starting from the defaults of [`RenderJob::new`]
+pub struct RenderJobBuilder<'a, O = Arc<dyn Object>> {
+    job: RenderJob<'a, O>,
+}
 
-    let next_pixel = AtomicU32::new(0);
-    let num_pixels = image_width as usize * image_height as usize;
-    let output: Vec<_> = iter::repeat_with(|| SyncUnsafeCell::new(Color::BLACK))
-        .take(num_pixels)
-        .collect();
+impl<'a, O: Object> RenderJobBuilder<'a, O> {
+    pub fn objects(mut self, objects: Vec<O>) -> Self {
+        self.job.objects = objects;
+        self
+    }
+
+    pub fn background(mut self, background: impl Background + 'static) -> Self {
+        self.job.background = Arc::new(background);
+        self
+    }
+
+    pub fn sun(mut self, sun: Sun) -> Self {
+        self.job.sun = Some(sun);
+        self
+    }
+
+    pub fn lights(mut self, lights: Vec<Arc<dyn LightSampleable>>) -> Self {
+        self.job.lights = lights;
+        self
+    }
+
+    pub fn samples(mut self, num_samples: u32) -> Self {
+        self.job.num_samples = num_samples;
+        self
+    }
+
+    /// Takes only the samples in `range` instead of the first [`RenderJob::num_samples`]
+    pub fn sample_range(mut self, range: Range<u32>) -> Self {
+        self.job.first_sample = range.start;
+        self.job.num_samples = range.end.saturating_sub(range.start);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.job.seed = seed;
+        self
+    }
+
+    pub fn scramble_pixels(mut self) -> Self {
+        self.job.scramble_pixels = true;
+        self
+    }
+
+    pub fn workers(mut self, num_workers: usize) -> Self {
+        self.job.num_workers = num_workers;
+        self
+    }
+
+    pub fn max_bounces(mut self, max_bounces: u32) -> Self {
+        self.job.max_bounces = max_bounces;
+        self
+    }
+
+    pub fn russian_roulette(mut self, min_bounces: u32) -> Self {
+        self.job.russian_roulette = true;
+        self.job.min_bounces = min_bounces;
+        self
+    }
+
+    pub fn ray_epsilon(mut self, ray_epsilon: f32) -> Self {
+        self.job.ray_epsilon = ray_epsilon;
+        self
+    }
+
+    pub fn sampler(mut self, sampler: Sampler) -> Self {
+        self.job.sampler = sampler;
+        self
+    }
+
+    pub fn mode(mut self, mode: RenderMode) -> Self {
+        self.job.mode = mode;
+        self
+    }
+
+    pub fn color_model(mut self, color_model: ColorModel) -> Self {
+        self.job.color_model = color_model;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.job.filter = filter;
+        self
+    }
+
+    pub fn region(mut self, region: Rect) -> Self {
+        self.job.region = Some(region);
+        self
+    }
+
+    pub fn adaptive(mut self, target_error: f32) -> Self {
+        self.job.target_error = Some(target_error);
+        self
+    }
+
+    pub fn target_convergence(mut self, target_convergence: f32) -> Self {
+        self.job.target_convergence = Some(target_convergence);
+        self
+    }
+
+    pub fn arena_capacity(mut self, arena_capacity: usize) -> Self {
+        self.job.arena_capacity = Some(arena_capacity);
+        self
+    }
+
+    pub fn build(self) -> Result<RenderJob<'a, O>, RenderJobError> {
+        if self.job.num_samples == 0 {
+            return Err(RenderJobError::NoSamples);
+        }
+        if self
+            .job
+            .first_sample
+            .checked_add(self.job.num_samples)
+            .is_none()
+        {
+            return Err(RenderJobError::SampleRangeOverflow);
+        }
+        if self.job.num_workers == 0 {
+            return Err(RenderJobError::NoWorkers);
+        }
+        if !(self.job.ray_epsilon >= 0.0 && self.job.ray_epsilon.is_finite()) {
+            return Err(RenderJobError::InvalidRayEpsilon);
+        }
+        if let Some(target_error) = self.job.target_error
+            && !(target_error > 0.0 && target_error.is_finite())
+        {
+            return Err(RenderJobError::InvalidTargetError);
+        }
+        if let Some(target_convergence) = self.job.target_convergence
+            && !(target_convergence > 0.0 && target_convergence.is_finite())
+        {
+            return Err(RenderJobError::InvalidTargetConvergence);
+        }
+        if let Filter::Gaussian(radius) = self.job.filter
+            && !(radius > 0.0 && radius.is_finite())
+        {
+            return Err(RenderJobError::InvalidFilterRadius);
+        }
+        Ok(self.job)
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderJobError {
+    NoSamples,
+    SampleRangeOverflow,
+    NoWorkers,
+    InvalidRayEpsilon,
+    InvalidTargetError,
+    InvalidTargetConvergence,
+    InvalidFilterRadius,
+}
+
+impl fmt::Display for RenderJobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSamples => write!(f, "the number of samples must be at least 1"),
+            Self::SampleRangeOverflow => write!(f, "the sample indices must fit into 32 bits"),
+            Self::NoWorkers => write!(f, "the number of workers must be at least 1"),
+            Self::InvalidRayEpsilon => write!(f, "the ray epsilon must be finite and not negative"),
+            Self::InvalidTargetError => write!(f, "the target error must be finite and positive"),
+            Self::InvalidTargetConvergence => {
+                write!(f, "the target convergence must be finite and positive")
+            }
+            Self::InvalidFilterRadius => write!(f, "the filter radius must be finite and positive"),
+        }
+    }
+}
+
+impl std::error::Error for RenderJobError {}
+
+/// Auxiliary buffers filled alongside the beauty image. Each buffer that is `Some` must have
+/// the same dimensions as the image and receives the average of the first hit's data, weighted
+/// by the job's filter like the image.
+#[derive(Default)]
+pub struct Aovs {
+    /// Shading normal of the first hit, stored unmodified in the RGB channels
+    pub normal: Option<Image>,
+    /// Base color of the material at the first hit, or the background for misses
+    pub albedo: Option<Image>,
+    /// Distance from the camera to the nearest first hit of all samples, with one value per
+    /// pixel in row-major order. Pixels where every sample missed hold `f32::INFINITY`.
+    pub depth: Option<Box<[f32]>>,
+    /// Index into [`RenderJob::objects`] of the object belonging to the depth AOV's hit, or -1
+    /// where every sample missed
+    pub object_id: Option<Box<[i32]>>,
+    /// Number of samples each pixel received, with one value per pixel in row-major order. Only
+    /// differs from the job's sample count with adaptive sampling.
+    pub sample_count: Option<Box<[u32]>>,
+}
+
+#[derive(Clone, Copy)]
+struct PathSample {
+    color: Color,
+    normal: Vector,
+    albedo: Color,
+    depth: f32,
+    object_id: i32,
+    coverage: f32,
+}
+
+impl PathSample {
+    const EMPTY: Self = Self {
+        color: Color::BLACK,
+        normal: Vector::ZERO,
+        albedo: Color::BLACK,
+        depth: f32::INFINITY,
+        object_id: -1,
+        coverage: 0.0,
+    };
+
+    fn accumulate(&mut self, other: &Self, weight: f32) {
+        self.color += other.color * weight;
+        self.normal = self.normal + other.normal * weight;
+        self.albedo += other.albedo * weight;
+        self.coverage += other.coverage * weight;
+        if other.depth < self.depth {
+            self.depth = other.depth;
+            self.object_id = other.object_id;
+        }
+    }
+}
+
+// Adaptive sampling only judges pixels after this many samples, as fewer give unreliable
+// variance estimates
+const ADAPTIVE_MIN_SAMPLES: u32 = 16;
+
+// Pixels darker than this are judged against it instead of their mean, so nearly black noise
+// need not reach the relative target
+const ADAPTIVE_MIN_MEAN: f32 = 0.01;
+
+// The change between the first few passes says little about how far the image is from
+// converging, so the convergence target is only checked after this many
+const CONVERGENCE_MIN_PASSES: u32 = 4;
+
+// Rays pass through at most this many cut-out surfaces in a row and are lost after that, which
+// bounds the work for rays grazing a dense stack of them
+const MAX_PASS_THROUGHS: u32 = 64;
+
+// Wavelengths in nanometers representing the red, green and blue channels
+const CHANNEL_WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+
+#[derive(Clone, Copy)]
+struct PixelAccumulator {
+    // Filter-weighted sum of the samples
+    sum: PathSample,
+    weight_sum: f32,
+    num_samples: u32,
+    // Running mean and sum of squared deviations of the sample luminance, following Welford
+    mean: f32,
+    m2: f32,
+    converged: bool,
+}
+
+impl PixelAccumulator {
+    const EMPTY: Self = Self {
+        sum: PathSample::EMPTY,
+        weight_sum: 0.0,
+        num_samples: 0,
+        mean: 0.0,
+        m2: 0.0,
+        converged: false,
+    };
+
+    // The convergence test ignores the weight, as luminance noise is what it has to judge
+    fn add(&mut self, sample: &PathSample, weight: f32) {
+        self.sum.accumulate(sample, weight);
+        self.weight_sum += weight;
+        self.num_samples += 1;
+
+        let luminance = sample.color.luminance();
+        let delta = luminance - self.mean;
+        self.mean += delta / self.num_samples as f32;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    /// Divisor turning the sums into averages. Samples right at the edge of the filter have no
+    /// weight, and a pixel made up only of those stays zero rather than becoming NaN.
+    fn total_weight(&self) -> f32 {
+        if self.weight_sum > 0.0 {
+            self.weight_sum
+        } else {
+            1.0
+        }
+    }
+
+    fn has_converged(&self, target_error: f32) -> bool {
+        if self.num_samples < ADAPTIVE_MIN_SAMPLES {
+            return false;
+        }
+        let n = self.num_samples as f32;
+        let variance = self.m2 / (n - 1.0);
+        let confidence_interval = 1.96 * (variance / n).sqrt();
+        confidence_interval <= target_error * self.mean.max(ADAPTIVE_MIN_MEAN)
+    }
+}
+
+/// Largest number of pixels in a rendered image. Pixels are numbered with 32 bits, which also
+/// key their random numbers.
+pub const MAX_RENDER_PIXELS: u64 = u32::MAX as u64;
+
+/// Renders the job into `image`, calling `on_pass` after every completed pass with the number
+/// of passes so far and the current average. If `image.alpha` is `Some`, it receives the
+/// fraction of camera rays per pixel that hit an object once all passes are done.
+///
+/// The output only depends on the job's scene, seed and sample count. Random numbers are keyed
+/// by pixel, sample and bounce rather than by worker, and every pixel accumulates its samples
+/// in pass order, so the image and AOVs are bit-identical for any number of workers.
+///
+/// With [`RenderJob::target_error`] set, converged pixels are skipped in later passes, and the
+/// render ends early once every pixel has converged. With [`RenderJob::target_convergence`] set,
+/// it ends early once a pass barely changes the image. [`RenderStats::passes`] tells how many
+/// passes were run in either case.
+///
+/// Panics if the image has more than [`MAX_RENDER_PIXELS`] pixels.
+pub fn render<O: Object>(
+    job: RenderJob<'_, O>,
+    image: &mut Image,
+    aovs: &mut Aovs,
+    on_pass: impl FnMut(u32, &Image),
+) -> RenderStats {
+    render_reusing(job, image, aovs, &mut RenderScratch::default(), on_pass)
+}
+
+/// Like [`render`], but into a new image of the given size whose linear RGB pixels are
+/// returned in row-major order, for callers that have no use for [`Image`]
+pub fn render_to_vec<O: Object>(
+    job: RenderJob<'_, O>,
+    image_width: u32,
+    image_height: u32,
+) -> (Vec<[f32; 3]>, RenderStats) {
+    let mut image = Image::new(image_width, image_height);
+    let stats = render(job, &mut image, &mut Aovs::default(), |_, _| {});
+    let pixels = image.pixels.iter().map(|color| [color.r, color.g, color.b]);
+    (pixels.collect(), stats)
+}
+
+/// Traces sample `sample_number` of the pixel at `x`, `y` in an image of the given size and
+/// returns its color, e.g. to reproduce a firefly or NaN pixel without rendering the whole
+/// frame. The sample gets the same random numbers as in a [`render`] of the job, but its color
+/// is returned unfiltered, and NaN or infinite colors are kept instead of being rejected.
+///
+/// Panics if the pixel lies outside of the image.
+pub fn debug_pixel<O: Object>(
+    mut job: RenderJob<'_, O>,
+    image_width: u32,
+    image_height: u32,
+    x: u32,
+    y: u32,
+    sample_number: u32,
+) -> Color {
+    assert!(x < image_width && y < image_height, "pixel out of bounds");
+
+    let (bvh, _) = build_bvh(mem::take(&mut job.objects));
+    let lights = LightSelection::new(&job.lights);
+    let arena_capacity = job
+        .arena_capacity
+        .unwrap_or_else(|| bvh.traversal_stack_size());
+    let mut state = WorkerState::new(job.seed, job.sampler, 0, arena_capacity);
+
+    let (sample, _) = trace_sample(
+        &mut state,
+        x,
+        y,
+        sample_number,
+        image_width,
+        image_height,
+        job.camera,
+        &bvh,
+        &*job.background,
+        job.sun.as_ref(),
+        &lights,
+        job.mode,
+        job.color_model,
+        job.filter,
+        job.scramble_pixels,
+        job.max_bounces,
+        job.russian_roulette.then_some(job.min_bounces),
+        job.ray_epsilon,
+    );
+    sample.color
+}
+
+/// Memory used by a render besides the image and AOVs, which can be kept for later renders
+/// to avoid allocating it again, e.g. for previews that render many frames in a row
+#[derive(Default)]
+pub struct RenderScratch {
+    accumulated: Vec<SyncUnsafeCell<PixelAccumulator>>,
+    states: Vec<WorkerState>,
+}
+
+/// Like [`render`], but takes the per-pixel accumulators and worker states from `scratch`
+/// and leaves them there for the next call. The result is the same as with a fresh scratch.
+pub fn render_reusing<O: Object>(
+    job: RenderJob<'_, O>,
+    image: &mut Image,
+    aovs: &mut Aovs,
+    scratch: &mut RenderScratch,
+    on_pass: impl FnMut(u32, &Image),
+) -> RenderStats {
+    let num_workers = job.num_workers;
+    render_with(
+        job,
+        image,
+        aovs,
+        on_pass,
+        scratch,
+        num_workers,
+        run_on_threads,
+    )
+}
+
+/// Measures the durations reported in [`RenderStats`]. The standard library has no clock on
+/// wasm, so they are zero there.
+#[derive(Clone, Copy)]
+struct Stopwatch(Option<SystemTime>);
+
+impl Stopwatch {
+    fn start() -> Self {
+        Self(cfg!(not(target_arch = "wasm32")).then(SystemTime::now))
+    }
+
+    fn elapsed(self) -> Duration {
+        self.0
+            .and_then(|start| start.elapsed().ok())
+            .unwrap_or_default()
+    }
+}
+
+// Spawns a thread for every worker, which then runs all passes. Between two passes, the
+// workers wait at a barrier while the calling thread runs `after_pass`. On wasm, which has no
+// threads, the workers run one after another on the calling thread instead, which yields the
+// same image.
+fn run_on_threads(
+    states: &mut [WorkerState],
+    work: &(dyn Fn(&mut WorkerState) + Sync),
+    after_pass: &mut dyn FnMut() -> bool,
+) {
+    if cfg!(target_arch = "wasm32") {
+        loop {
+            states.iter_mut().for_each(work);
+            if !after_pass() {
+                return;
+            }
+        }
+    }
+
+    // Every pass starts and ends with all workers and the calling thread at the barrier
+    let barrier = Barrier::new(states.len() + 1);
+    let finished = AtomicBool::new(false);
+    // A panic would leave the others waiting at the barrier forever, so it is caught and raised
+    // again once every thread has left
+    let panic_payload = Mutex::new(None);
 
     thread::scope(|scope| {
-        for _ in 0..job.num_workers {
-            scope.spawn(|| unsafe {
-                compute_pixels(
-                    image_width,
-                    image_height,
-                    job.num_samples,
-                    job.camera,
-                    &bvh,
-                    job.background,
-                    job.seed,
-                    &next_pixel,
-                    &output,
-                );
+        for state in states {
+            let (barrier, finished, panic_payload) = (&barrier, &finished, &panic_payload);
+            scope.spawn(move || {
+                loop {
+                    barrier.wait();
+                    if finished.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| work(state))) {
+                        *panic_payload.lock().unwrap() = Some(payload);
+                    }
+                    barrier.wait();
+                }
             });
         }
+
+        loop {
+            barrier.wait();
+            barrier.wait();
+
+            let mut panic_payload = panic_payload.lock().unwrap();
+            let next_pass = panic_payload.is_none()
+                && panic::catch_unwind(AssertUnwindSafe(&mut *after_pass)).unwrap_or_else(
+                    |payload| {
+                        *panic_payload = Some(payload);
+                        false
+                    },
+                );
+            if !next_pass {
+                finished.store(true, Ordering::Relaxed);
+                barrier.wait();
+                break;
+            }
+        }
+    });
+
+    if let Some(payload) = panic_payload.into_inner().unwrap() {
+        panic::resume_unwind(payload);
+    }
+}
+
+/// Renders `num_frames` images of the given size, one for each job returned by `make_job` for
+/// the frame index. Each frame builds its own BVH from the job's objects, so objects may move
+/// between frames. `on_pass` receives the frame index alongside the pass number and
+/// `on_frame` receives each finished image. The returned stats are summed over all frames.
+pub fn render_sequence<'a, O: Object>(
+    num_frames: u32,
+    image_width: u32,
+    image_height: u32,
+    mut make_job: impl FnMut(u32) -> RenderJob<'a, O>,
+    mut on_pass: impl FnMut(u32, u32, &Image),
+    mut on_frame: impl FnMut(u32, Image, &RenderStats),
+) -> RenderStats {
+    let mut total = RenderStats::default();
+    let mut scratch = RenderScratch::default();
+
+    for frame in 0..num_frames {
+        let mut image = Image::new(image_width, image_height);
+        let stats = render_reusing(
+            make_job(frame),
+            &mut image,
+            &mut Aovs::default(),
+            &mut scratch,
+            |pass, image| on_pass(frame, pass, image),
+        );
+
+        total.merge(&stats);
+        total.bvh_build_time += stats.bvh_build_time;
+        total.bvh_branches = total.bvh_branches.max(stats.bvh_branches);
+        total.passes += stats.passes;
+        total.elapsed += stats.elapsed;
+        on_frame(frame, image, &stats);
+    }
+
+    total
+}
+
+/// Renders an image of the given size from each of `cameras`, e.g. for stereo pairs, in place
+/// of the job's own camera. Unlike [`render_sequence`], the BVH is only built once for all
+/// views. Every view gets its own random numbers, and the first one matches a [`render`] of
+/// the job from that camera. `on_pass` receives the view index alongside the pass number. The
+/// returned stats are summed over all views.
+///
+/// Panics if there are more than 65536 cameras.
+pub fn render_views<'a, O: Object>(
+    mut job: RenderJob<'a, O>,
+    cameras: &'a [Camera],
+    image_width: u32,
+    image_height: u32,
+    mut on_pass: impl FnMut(u32, u32, &Image),
+) -> (Vec<Image>, RenderStats) {
+    assert!(cameras.len() <= 1 << 16, "too many views");
+    let start_time = Stopwatch::start();
+
+    let (bvh, bvh_build_time) = build_bvh(mem::take(&mut job.objects));
+    let mut scratch = RenderScratch::default();
+    let mut images = Vec::with_capacity(cameras.len());
+    let mut total = RenderStats::default();
+    let num_workers = job.num_workers;
+
+    for (view, camera) in (0..).zip(cameras) {
+        job.camera = camera;
+        let mut image = Image::new(image_width, image_height);
+        let stats = render_view(
+            &job,
+            &bvh,
+            view,
+            &mut image,
+            &mut Aovs::default(),
+            |pass, image| on_pass(view, pass, image),
+            &mut scratch,
+            num_workers,
+            run_on_threads,
+        );
+
+        total.merge(&stats);
+        total.bvh_branches = stats.bvh_branches;
+        total.passes += stats.passes;
+        images.push(image);
+    }
+    total.bvh_build_time = bvh_build_time;
+    total.elapsed = start_time.elapsed();
+
+    (images, total)
+}
+
+/// Like [`render`], but runs the workers on an existing thread pool instead of spawning new
+/// threads for the render. `rayon::ThreadPool` is the same type, so a pool built with rayon
+/// can be passed directly. One worker is used per pool thread and
+/// [`RenderJob::num_workers`] is ignored.
+#[cfg(feature = "rayon")]
+pub fn render_in_pool<O: Object>(
+    job: RenderJob<'_, O>,
+    pool: &rayon_core::ThreadPool,
+    image: &mut Image,
+    aovs: &mut Aovs,
+    on_pass: impl FnMut(u32, &Image),
+) -> RenderStats {
+    render_in_pool_reusing(
+        job,
+        pool,
+        image,
+        aovs,
+        &mut RenderScratch::default(),
+        on_pass,
+    )
+}
+
+/// Combines [`render_in_pool`] and [`render_reusing`]
+#[cfg(feature = "rayon")]
+pub fn render_in_pool_reusing<O: Object>(
+    job: RenderJob<'_, O>,
+    pool: &rayon_core::ThreadPool,
+    image: &mut Image,
+    aovs: &mut Aovs,
+    scratch: &mut RenderScratch,
+    on_pass: impl FnMut(u32, &Image),
+) -> RenderStats {
+    let num_workers = pool.current_num_threads();
+    render_with(
+        job,
+        image,
+        aovs,
+        on_pass,
+        scratch,
+        num_workers,
+        |states, work, after_pass| {
+            loop {
+                pool.scope(|scope| {
+                    for state in &mut *states {
+                        scope.spawn(move |_| work(state));
+                    }
+                });
+                if !after_pass() {
+                    break;
+                }
+            }
+        },
+    )
+}
+
+// `run_passes` must call the given function once for every worker state, then call the pass
+// callback on its own thread once all of those calls have finished, and repeat both for as long
+// as the callback returns true
+fn render_with<O: Object>(
+    mut job: RenderJob<'_, O>,
+    image: &mut Image,
+    aovs: &mut Aovs,
+    on_pass: impl FnMut(u32, &Image),
+    scratch: &mut RenderScratch,
+    num_workers: usize,
+    run_passes: impl FnOnce(
+        &mut [WorkerState],
+        &(dyn Fn(&mut WorkerState) + Sync),
+        &mut dyn FnMut() -> bool,
+    ),
+) -> RenderStats {
+    let start_time = Stopwatch::start();
+
+    let (bvh, bvh_build_time) = build_bvh(mem::take(&mut job.objects));
+    let mut stats = render_view(
+        &job,
+        &bvh,
+        0,
+        image,
+        aovs,
+        on_pass,
+        scratch,
+        num_workers,
+        run_passes,
+    );
+    stats.bvh_build_time = bvh_build_time;
+    stats.elapsed = start_time.elapsed();
+
+    stats
+}
+
+fn build_bvh<O: Object>(objects: Vec<O>) -> (Bvh<Vec<O>>, Duration) {
+    let start_time = Stopwatch::start();
+    let bvh = Bvh::new(objects, bvh::DEFAULT_MAX_LEAF_SIZE);
+    (bvh, start_time.elapsed())
+}
+
+// Renders the job from its camera without the BVH build, which the caller accounts for in the
+// returned stats. `view` is mixed into the random numbers.
+#[allow(clippy::too_many_arguments)]
+fn render_view<O: Object>(
+    job: &RenderJob<'_, O>,
+    bvh: &Bvh<Vec<O>>,
+    view: u32,
+    image: &mut Image,
+    aovs: &mut Aovs,
+    mut on_pass: impl FnMut(u32, &Image),
+    scratch: &mut RenderScratch,
+    num_workers: usize,
+    run_passes: impl FnOnce(
+        &mut [WorkerState],
+        &(dyn Fn(&mut WorkerState) + Sync),
+        &mut dyn FnMut() -> bool,
+    ),
+) -> RenderStats {
+    let image_width = image.width();
+    let image_height = image.height();
+    assert!(
+        image_width as u64 * image_height as u64 <= MAX_RENDER_PIXELS,
+        "image has more than {MAX_RENDER_PIXELS} pixels"
+    );
+
+    let region = job.region.unwrap_or(Rect {
+        x: 0,
+        y: 0,
+        width: image_width,
+        height: image_height,
+    });
+    assert!(
+        region.fits_within(image_width, image_height),
+        "render region out of bounds"
+    );
+
+    let lights = LightSelection::new(&job.lights);
+
+    let num_pixels = image_width as usize * image_height as usize;
+    let accumulated = &mut scratch.accumulated;
+    accumulated.clear();
+    accumulated.resize_with(num_pixels, || SyncUnsafeCell::new(PixelAccumulator::EMPTY));
+    let num_converged = AtomicU32::new(0);
+
+    let arena_capacity = job
+        .arena_capacity
+        .unwrap_or_else(|| bvh.traversal_stack_size());
+    let states = &mut scratch.states;
+    states.truncate(num_workers);
+    for state in states.iter_mut() {
+        state.reset(job.seed, job.sampler, view, arena_capacity);
+    }
+    states.resize_with(num_workers, || {
+        WorkerState::new(job.seed, job.sampler, view, arena_capacity)
     });
 
-    let duration = start_time.elapsed().unwrap_or(Duration::from_secs(0));
-    println!("\x1B[G\x1B[KDone in {duration:.3?}",);
+    let mut num_passes = 0;
+    let pass = AtomicU32::new(0);
+    let next_pixel = AtomicU64::new(0);
+
+    let work = |state: &mut WorkerState| unsafe {
+        compute_pass(
+            state,
+            job.first_sample + pass.load(Ordering::Relaxed),
+            image_width,
+            image_height,
+            region,
+            job.camera,
+            bvh,
+            &*job.background,
+            job.sun.as_ref(),
+            &lights,
+            job.mode,
+            job.color_model,
+            job.filter,
+            job.scramble_pixels,
+            job.max_bounces,
+            job.russian_roulette.then_some(job.min_bounces),
+            job.ray_epsilon,
+            job.target_error,
+            &next_pixel,
+            &accumulated[..],
+            &num_converged,
+        );
+    };
+
+    let mut after_pass = || {
+        // Sum of the luminance changes relative to the new luminance, as a cheap estimate of
+        // how much further passes would still change the image
+        let mut relative_change = 0.0;
+        for idx in region.indices(image_width) {
+            // SAFETY: The workers are waiting for the next pass, so nothing writes to the pixels
+            let pixel = unsafe { &*accumulated[idx].get() };
+            let color = pixel.sum.color / pixel.total_weight();
+            let luminance = color.luminance();
+            let change = (luminance - image.pixels[idx].luminance()).abs();
+            relative_change += (change / luminance.max(ADAPTIVE_MIN_MEAN)) as f64;
+            image.pixels[idx] = color;
+        }
+        num_passes += 1;
+
+        on_pass(num_passes, image);
+
+        if num_passes == job.num_samples
+            || num_converged.load(Ordering::Relaxed) == region.width * region.height
+        {
+            return false;
+        }
+        if let Some(target_convergence) = job.target_convergence
+            && num_passes >= CONVERGENCE_MIN_PASSES
+        {
+            let num_region_pixels = region.width as f64 * region.height as f64;
+            if relative_change / num_region_pixels < target_convergence as f64 {
+                return false;
+            }
+        }
+
+        pass.store(num_passes, Ordering::Relaxed);
+        next_pixel.store(0, Ordering::Relaxed);
+        true
+    };
+
+    if job.num_samples > 0 {
+        run_passes(states, &work, &mut after_pass);
+    }
+
+    if let Some(alpha) = &mut image.alpha {
+        assert_eq!(alpha.len(), num_pixels);
+        for idx in region.indices(image_width) {
+            let pixel = accumulated[idx].get_mut();
+            alpha[idx] = pixel.sum.coverage / pixel.total_weight();
+        }
+    }
+    if let Some(normal) = &mut aovs.normal {
+        for idx in region.indices(image_width) {
+            let pixel = accumulated[idx].get_mut();
+            let [r, g, b] = (pixel.sum.normal / pixel.total_weight()).into();
+            normal.pixels[idx] = Color::from_rgb(r, g, b);
+        }
+    }
+    if let Some(albedo) = &mut aovs.albedo {
+        for idx in region.indices(image_width) {
+            let pixel = accumulated[idx].get_mut();
+            albedo.pixels[idx] = pixel.sum.albedo / pixel.total_weight();
+        }
+    }
+    if let Some(depth) = &mut aovs.depth {
+        assert_eq!(depth.len(), num_pixels);
+        for idx in region.indices(image_width) {
+            depth[idx] = accumulated[idx].get_mut().sum.depth;
+        }
+    }
+    if let Some(object_id) = &mut aovs.object_id {
+        assert_eq!(object_id.len(), num_pixels);
+        for idx in region.indices(image_width) {
+            object_id[idx] = accumulated[idx].get_mut().sum.object_id;
+        }
+    }
+    if let Some(sample_count) = &mut aovs.sample_count {
+        assert_eq!(sample_count.len(), num_pixels);
+        for idx in region.indices(image_width) {
+            sample_count[idx] = accumulated[idx].get_mut().num_samples;
+        }
+    }
 
-    unsafe {
-        let output: Vec<Color> = std::mem::transmute(output);
-        image.pixels.copy_from_slice(&output);
+    let mut stats = RenderStats::default();
+    for state in states.iter() {
+        stats.merge(&state.stats);
     }
+    stats.bvh_branches = bvh.num_branches() as u64;
+    stats.passes = num_passes;
+
+    stats
 }
 
 #[allow(clippy::too_many_arguments)]
-unsafe fn compute_pixels(
+unsafe fn compute_pass(
+    state: &mut WorkerState,
+    sample_number: u32,
     image_width: u32,
     image_height: u32,
-    num_samples: u32,
+    region: Rect,
     camera: &Camera,
-    bvh: &Bvh<Vec<Arc<dyn Object>>>,
-    background: Color,
-    seed: u64,
-    next_pixel: &AtomicU32,
-    output: &[SyncUnsafeCell<Color>],
+    bvh: &Bvh<Vec<impl Object>>,
+    background: &dyn Background,
+    sun: Option<&Sun>,
+    lights: &LightSelection,
+    mode: RenderMode,
+    color_model: ColorModel,
+    filter: Filter,
+    scramble_pixels: bool,
+    max_bounces: u32,
+    roulette_after: Option<u32>,
+    ray_epsilon: f32,
+    target_error: Option<f32>,
+    next_pixel: &AtomicU64,
+    accumulated: &[SyncUnsafeCell<PixelAccumulator>],
+    num_converged: &AtomicU32,
 ) {
-    let mut state = WorkerState::new(seed);
-
     loop {
-        let pixel_number = next_pixel.fetch_add(1, Ordering::Relaxed);
-        if pixel_number >= image_width * image_height {
+        // Every worker overshoots once at the end, so the counter is wider than the pixels
+        let region_pixel = next_pixel.fetch_add(1, Ordering::Relaxed);
+        if region_pixel >= region.width as u64 * region.height as u64 {
             break;
         }
+        let region_pixel = region_pixel as u32;
 
-        let x = pixel_number % image_width;
-        let y = pixel_number / image_width;
+        // RNG counters are keyed by the absolute pixel, so a region matches the full render
+        let x = region.x + region_pixel % region.width;
+        let y = region.y + region_pixel / region.width;
+        let pixel_number = y * image_width + x;
 
-        if x == 0 {
-            let mut stdout = std::io::stdout().lock();
-            let progress = y as f32 / image_height as f32 * 100.0;
-            write!(stdout, "\x1B[G\x1B[K{y}/{image_height} ({progress:.0}%)",).unwrap();
-            stdout.flush().unwrap();
+        // Every pixel is handled by a single worker per pass, so no other reference exists
+        let pixel = unsafe { &mut *accumulated[pixel_number as usize].get() };
+        if pixel.converged {
+            continue;
         }
 
-        let mut color = Color::BLACK;
+        let (mut sample, weight) = trace_sample(
+            state,
+            x,
+            y,
+            sample_number,
+            image_width,
+            image_height,
+            camera,
+            bvh,
+            background,
+            sun,
+            lights,
+            mode,
+            color_model,
+            filter,
+            scramble_pixels,
+            max_bounces,
+            roulette_after,
+            ray_epsilon,
+        );
+
+        // A single NaN or infinite sample would otherwise poison the whole pixel
+        if !sample.color.is_finite() {
+            let Color { r, g, b } = sample.color;
+            let [r, g, b] = [r, g, b].map(|v| if v.is_finite() { v } else { 0.0 });
+            sample.color = Color::from_rgb(r, g, b);
+            state.stats.rejected_samples += 1;
+        }
 
-        for i in 0..num_samples {
-            state.init_trace(pixel_number, i);
+        state.arena().reset();
 
-            let [x_off, y_off, ..] = state.gen_random_floats();
+        pixel.add(&sample, weight);
+        if let Some(target_error) = target_error
+            && pixel.has_converged(target_error)
+        {
+            pixel.converged = true;
+            num_converged.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
 
-            let u = (x as f32 + x_off) / image_width as f32;
-            let v = (y as f32 + y_off) / image_height as f32;
-            let ray = camera.get_ray(1.0 - u, 1.0 - v, &mut state);
+// Traces sample `sample_number` of the pixel at `x`, `y` and returns it unfiltered, along with
+// its filter weight
+#[allow(clippy::too_many_arguments)]
+fn trace_sample(
+    state: &mut WorkerState,
+    x: u32,
+    y: u32,
+    sample_number: u32,
+    image_width: u32,
+    image_height: u32,
+    camera: &Camera,
+    bvh: &Bvh<Vec<impl Object>>,
+    background: &dyn Background,
+    sun: Option<&Sun>,
+    lights: &LightSelection,
+    mode: RenderMode,
+    color_model: ColorModel,
+    filter: Filter,
+    scramble_pixels: bool,
+    max_bounces: u32,
+    roulette_after: Option<u32>,
+    ray_epsilon: f32,
+) -> (PathSample, f32) {
+    if scramble_pixels {
+        state.scramble_pixel(x, y);
+    }
+    state.init_trace(y * image_width + x, sample_number);
+    state.stats.samples += 1;
 
-            color += ray_color(ray, bvh, 50, &mut state, background);
+    let [x_off, y_off, ..] = state.gen_random_floats();
 
-            state.arena().reset();
+    // Samples spread over the whole filter footprint, which may reach into neighbouring
+    // pixels, while the box filter keeps them within the pixel
+    let (x_off, y_off, weight) = match filter {
+        Filter::Box => (x_off, y_off, 1.0),
+        _ => {
+            let radius = filter.radius();
+            let dx = (2.0 * x_off - 1.0) * radius;
+            let dy = (2.0 * y_off - 1.0) * radius;
+            (0.5 + dx, 0.5 + dy, filter.weight(dx, dy))
         }
+    };
 
-        color /= num_samples as f32;
+    let u = (x as f32 + x_off) / image_width as f32;
+    let v = (y as f32 + y_off) / image_height as f32;
+    let ray = camera.get_ray(1.0 - u, 1.0 - v, state);
 
-        unsafe {
-            *output[pixel_number as usize].get() = color;
+    let sample = match (mode, color_model) {
+        (RenderMode::PathTrace, ColorModel::Rgb) => ray_color::<Color>(
+            ray,
+            bvh,
+            max_bounces,
+            roulette_after,
+            ray_epsilon,
+            state,
+            background,
+            sun,
+            lights,
+        ),
+        #[cfg(feature = "spectral")]
+        (RenderMode::PathTrace, ColorModel::Spectral) => {
+            // The camera ray's remaining numbers pick the wavelengths, which leaves those of
+            // the path the same as in an RGB render
+            let [u, ..] = state.gen_random_floats();
+            state.wavelengths = Some(spectrum::sample_wavelengths(u));
+            ray_color::<SampledSpectrum>(
+                ray,
+                bvh,
+                max_bounces,
+                roulette_after,
+                ray_epsilon,
+                state,
+                background,
+                sun,
+                lights,
+            )
+        }
+        (RenderMode::Normals, _) => normal_color(ray, bvh, ray_epsilon, state, background),
+        (RenderMode::TraversalCost { max_visits }, _) => {
+            cost_color(ray, bvh, ray_epsilon, state, background, max_visits)
         }
+    };
+
+    (sample, weight)
+}
+
+/// Light or throughput along a path, either in RGB or at the wavelengths of a spectral sample
+trait PathRadiance:
+    Copy + Mul<Self, Output = Self> + Mul<f32, Output = Self> + MulAssign<Self> + AddAssign<Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn from_reflectance(color: Color, state: &WorkerState) -> Self;
+
+    fn from_emission(color: Color, state: &WorkerState) -> Self;
+
+    /// Weight of a sample restricted to `channel` by [`WorkerState::select_wavelength`]
+    fn channel_weight(channel: usize) -> Self;
+
+    fn max_component(self) -> f32;
+
+    fn to_rgb(self, state: &WorkerState) -> Color;
+}
+
+impl PathRadiance for Color {
+    const ZERO: Self = Color::BLACK;
+    const ONE: Self = Color::WHITE;
+
+    fn from_reflectance(color: Color, _state: &WorkerState) -> Self {
+        color
+    }
+
+    fn from_emission(color: Color, _state: &WorkerState) -> Self {
+        color
+    }
+
+    fn channel_weight(channel: usize) -> Self {
+        let mut weight = [0.0; 3];
+        weight[channel] = 3.0;
+        Color::from_rgb(weight[0], weight[1], weight[2])
+    }
+
+    fn max_component(self) -> f32 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    fn to_rgb(self, _state: &WorkerState) -> Color {
+        self
+    }
+}
+
+#[cfg(feature = "spectral")]
+impl PathRadiance for SampledSpectrum {
+    const ZERO: Self = SampledSpectrum::ZERO;
+    const ONE: Self = SampledSpectrum::ONE;
+
+    fn from_reflectance(color: Color, state: &WorkerState) -> Self {
+        SampledSpectrum::from_reflectance(color, &state.wavelengths.unwrap())
+    }
+
+    fn from_emission(color: Color, state: &WorkerState) -> Self {
+        SampledSpectrum::from_illuminant(color, &state.wavelengths.unwrap())
+    }
+
+    fn channel_weight(channel: usize) -> Self {
+        debug_assert_eq!(channel, 0);
+        SampledSpectrum::hero_only()
+    }
+
+    fn max_component(self) -> f32 {
+        self.0.into_iter().fold(0.0, f32::max)
+    }
+
+    fn to_rgb(self, state: &WorkerState) -> Color {
+        SampledSpectrum::to_rgb(self, &state.wavelengths.unwrap())
     }
 }
 
-fn ray_color(
+#[allow(clippy::too_many_arguments)]
+fn ray_color<C: PathRadiance>(
     mut ray: Ray,
-    bvh: &Bvh<Vec<Arc<dyn Object>>>,
+    bvh: &Bvh<Vec<impl Object>>,
     max_bounces: u32,
+    roulette_after: Option<u32>,
+    ray_epsilon: f32,
     state: &mut WorkerState,
-    background: Color,
-) -> Color {
-    let mut emitting = Color::BLACK;
-    let mut attenuation = Color::WHITE;
+    background: &dyn Background,
+    sun: Option<&Sun>,
+    lights: &LightSelection,
+) -> PathSample {
+    let mut sample = PathSample {
+        albedo: background.color(ray.direction),
+        ..PathSample::EMPTY
+    };
+    let mut color = C::ZERO;
+    let mut attenuation = C::ONE;
+    // Origin and density of the current ray if its origin sampled the lights as well, in which
+    // case the light it hits is weighted against that
+    let mut light_sampled_from: Option<(Vector, f32)> = None;
 
-    for _ in 0..max_bounces {
+    for bounce in 0..max_bounces {
         state.arena().reset();
-        state.ray_number += 1;
-        match bvh.hit(ray, 0.0001..f32::INFINITY, state.arena()) {
-            Some(hit) => {
+        state.next_ray();
+        state.stats.rays += 1;
+        match hit_opaque(bvh, ray, ray_epsilon, state.arena()) {
+            Some((hit, index)) => {
+                state.stats.hits += 1;
+                // The segment up to the hit lies inside the medium the path has entered, if any
+                if let Some(transmittance) =
+                    state.medium_transmittance(hit.t * ray.direction.length())
+                {
+                    attenuation *= C::from_reflectance(transmittance, state);
+                }
+                if bounce == 0 {
+                    sample.normal = hit.normal;
+                    sample.albedo = hit.material.albedo();
+                    sample.depth = hit.t;
+                    sample.object_id = bvh.original_index(index) as i32;
+                    sample.coverage = 1.0;
+                }
+                let was_restricted = state.channel.is_some();
                 let material_hit = hit.material.hit(&hit, state);
-                emitting += attenuation * material_hit.emission;
+                let emission_weight = match light_sampled_from {
+                    Some((origin, scatter_pdf)) => {
+                        power_heuristic(scatter_pdf, lights.pdf(origin, ray.direction))
+                    }
+                    None => 1.0,
+                };
+                color +=
+                    attenuation * C::from_emission(material_hit.emission, state) * emission_weight;
                 match material_hit.reflection {
                     Some(Reflection {
                         ray: scatter_ray,
                         attenuation: attenuation_new,
                     }) => {
+                        state.stats.bounces += 1;
+                        light_sampled_from = None;
+                        if !lights.is_empty()
+                            && let Some((_, scatter_pdf)) =
+                                hit.material.scattering(&hit, scatter_ray.direction)
+                        {
+                            color += attenuation
+                                * sample_lights::<C>(&hit, bvh, ray_epsilon, state, lights);
+                            light_sampled_from = Some((hit.point, scatter_pdf));
+                        }
                         ray = scatter_ray;
-                        attenuation *= attenuation_new;
+                        attenuation *= C::from_reflectance(attenuation_new, state);
+                        if !was_restricted && let Some(channel) = state.channel {
+                            attenuation *= C::channel_weight(channel);
+                        }
+                        if let Some(min_bounces) = roulette_after
+                            && bounce >= min_bounces
+                        {
+                            let survival = attenuation.max_component().min(1.0);
+                            let [u, ..] = state.gen_random_floats();
+                            if u >= survival {
+                                break;
+                            }
+                            attenuation = attenuation * (1.0 / survival);
+                        }
                     }
                     None => break,
                 }
             }
             None => {
-                emitting += attenuation * background;
+                color += attenuation * C::from_emission(background.color(ray.direction), state);
+                if let Some(sun) = sun {
+                    color +=
+                        attenuation * C::from_emission(sun.emission_towards(ray.direction), state);
+                }
                 break;
             }
         }
     }
 
-    emitting
+    sample.color = color.to_rgb(state);
+    sample
+}
+
+/// Finds the nearest hit along `ray` whose material does not let it pass, along with the index
+/// of the object. Surfaces that let rays pass neither count as a hit nor as a bounce.
+fn hit_opaque<'a>(
+    bvh: &'a Bvh<Vec<impl Object>>,
+    ray: Ray,
+    ray_epsilon: f32,
+    arena: &Bump,
+) -> Option<(Hit<'a>, usize)> {
+    let mut t_min = ray_epsilon;
+    for _ in 0..MAX_PASS_THROUGHS {
+        let (hit, index) = bvh.hit_indexed(ray, t_min..f32::INFINITY, arena)?;
+        if !hit.material.passes_through(&hit) {
+            return Some((hit, index));
+        }
+        t_min = hit.t + ray_epsilon;
+    }
+    None
+}
+
+/// The lights of a job along with the probabilities of picking each one for next event
+/// estimation, which are proportional to their estimated power
+struct LightSelection<'a> {
+    lights: &'a [Arc<dyn LightSampleable>],
+    probabilities: Vec<f32>,
+    // Running sums of `probabilities`
+    cdf: Vec<f32>,
+}
+
+impl<'a> LightSelection<'a> {
+    fn new(lights: &'a [Arc<dyn LightSampleable>]) -> Self {
+        let powers: Vec<f32> = lights
+            .iter()
+            .map(|light| light.power())
+            .map(|power| {
+                if power.is_finite() {
+                    power.max(0.0)
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let total: f32 = powers.iter().sum();
+
+        // Without any usable estimate, all lights are equally likely
+        let probabilities: Vec<f32> = if total > 0.0 && total.is_finite() {
+            powers.iter().map(|power| power / total).collect()
+        } else {
+            vec![1.0 / lights.len() as f32; lights.len()]
+        };
+        let cdf = probabilities
+            .iter()
+            .scan(0.0, |sum, probability| {
+                *sum += probability;
+                Some(*sum)
+            })
+            .collect();
+
+        Self {
+            lights,
+            probabilities,
+            cdf,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Picks a light with the uniform random number `u`
+    fn pick(&self, u: f32) -> &dyn LightSampleable {
+        let index = self.cdf.partition_point(|&sum| sum <= u);
+        &*self.lights[index.min(self.lights.len() - 1)]
+    }
+
+    /// Density over solid angle of picking `direction` from `point` by choosing a light and
+    /// sampling it
+    fn pdf(&self, point: Vector, direction: Vector) -> f32 {
+        self.lights
+            .iter()
+            .zip(&self.probabilities)
+            .filter(|&(_, &probability)| probability > 0.0)
+            .map(|(light, probability)| probability * light.pdf_toward(point, direction))
+            .sum()
+    }
+}
+
+/// Estimates the light arriving at `hit` directly from one randomly chosen light and
+/// scattered back along the hit ray, weighted against finding it through scattered rays
+fn sample_lights<C: PathRadiance>(
+    hit: &Hit,
+    bvh: &Bvh<Vec<impl Object>>,
+    ray_epsilon: f32,
+    state: &mut WorkerState,
+    lights: &LightSelection,
+) -> C {
+    let [choice, ..] = state.gen_random_floats();
+    let light = lights.pick(choice);
+
+    let (direction, pdf) = light.sample_toward(hit.point, state);
+    if pdf <= 0.0 {
+        return C::ZERO;
+    }
+    let shadow_ray = Ray::new(hit.point, direction);
+    let Some((scattering, scatter_pdf)) = hit.material.scattering(hit, shadow_ray.direction) else {
+        return C::ZERO;
+    };
+    // The direction may also be reached through other lights, so its density is that of the
+    // whole light selection, which also covers lights partially hiding each other
+    let light_pdf = lights.pdf(hit.point, shadow_ray.direction);
+    if scatter_pdf <= 0.0 || light_pdf <= 0.0 {
+        return C::ZERO;
+    }
+
+    state.arena().reset();
+    state.stats.rays += 1;
+    match hit_opaque(bvh, shadow_ray, ray_epsilon, state.arena()) {
+        Some((light_hit, _)) => {
+            let weight = power_heuristic(light_pdf, scatter_pdf) / light_pdf;
+            let mut contribution = C::from_reflectance(scattering, state)
+                * C::from_emission(light_hit.material.emitted(&light_hit), state)
+                * weight;
+            // Absorbed like the scattered ray would be on its way to the light
+            if let Some(transmittance) =
+                state.medium_transmittance(light_hit.t * shadow_ray.direction.length())
+            {
+                contribution *= C::from_reflectance(transmittance, state);
+            }
+            contribution
+        }
+        None => C::ZERO,
+    }
+}
+
+/// Weight of a sample taken with density `pdf` against one other strategy with `other_pdf`
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let pdf2 = pdf * pdf;
+    pdf2 / (pdf2 + other_pdf * other_pdf)
+}
+
+fn normal_color(
+    ray: Ray,
+    bvh: &Bvh<Vec<impl Object>>,
+    ray_epsilon: f32,
+    state: &mut WorkerState,
+    background: &dyn Background,
+) -> PathSample {
+    state.arena().reset();
+    state.next_ray();
+    state.stats.rays += 1;
+    match hit_opaque(bvh, ray, ray_epsilon, state.arena()) {
+        Some((hit, index)) => {
+            state.stats.hits += 1;
+            let [x, y, z] = hit.geometric_normal.into();
+            PathSample {
+                color: Color::from_rgb(x + 1.0, y + 1.0, z + 1.0) * 0.5,
+                normal: hit.normal,
+                albedo: hit.material.albedo(),
+                depth: hit.t,
+                object_id: bvh.original_index(index) as i32,
+                coverage: 1.0,
+            }
+        }
+        None => PathSample {
+            albedo: background.color(ray.direction),
+            ..PathSample::EMPTY
+        },
+    }
+}
+
+fn cost_color(
+    ray: Ray,
+    bvh: &Bvh<Vec<impl Object>>,
+    ray_epsilon: f32,
+    state: &mut WorkerState,
+    background: &dyn Background,
+    max_visits: u32,
+) -> PathSample {
+    state.arena().reset();
+    state.next_ray();
+    state.stats.rays += 1;
+    let (hit, counts) = bvh.hit_counted(ray, ray_epsilon..f32::INFINITY, state.arena());
+    let color = heat_ramp((counts.nodes + counts.primitives) as f32 / max_visits.max(1) as f32);
+
+    match hit {
+        Some((hit, index)) => {
+            state.stats.hits += 1;
+            PathSample {
+                color,
+                normal: hit.normal,
+                albedo: hit.material.albedo(),
+                depth: hit.t,
+                object_id: bvh.original_index(index) as i32,
+                coverage: 1.0,
+            }
+        }
+        None => PathSample {
+            color,
+            albedo: background.color(ray.direction),
+            ..PathSample::EMPTY
+        },
+    }
+}
+
+fn heat_ramp(t: f32) -> Color {
+    const STOPS: [Color; 5] = [
+        Color::BLACK,
+        Color::from_rgb(0.0, 0.0, 1.0),
+        Color::from_rgb(0.0, 1.0, 0.0),
+        Color::from_rgb(1.0, 1.0, 0.0),
+        Color::from_rgb(1.0, 0.0, 0.0),
+    ];
+
+    let pos = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let idx = (pos as usize).min(STOPS.len() - 2);
+    STOPS[idx].lerp(STOPS[idx + 1], pos - idx as f32)
 }
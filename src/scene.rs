@@ -1,9 +1,25 @@
-use std::{collections::HashMap, fmt, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt, io, iter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::{
-    Camera, Color,
-    geometry::{Object, Sphere, bvh::Bvh},
-    material::{Dialectric, DiffuseLight, Lambertian, Material, Metal},
+    Camera, Color, Sun,
+    background::{Background, EnvironmentMap, Gradient},
+    camera,
+    geometry::{
+        Instance, LightSampleable, Object, Sphere, Transform, TriangleMesh,
+        bvh::{self, Bvh},
+    },
+    image::Image,
+    material::{
+        AnisotropicMetal, Dialectric, DiffuseLight, Isotropic, Lambertian, Material, Metal,
+        MixMaterial, OrenNayar, Pbr, Spotlight,
+    },
+    obj::{self, ObjError},
+    texture::{ImageTexture, Texture},
     vector::Vector,
 };
 use serde::{
@@ -20,10 +36,32 @@ impl From<Point> for Vector {
     }
 }
 
+fn default_true() -> bool {
+    true
+}
+
 fn default_up_vector() -> (f32, f32, f32) {
     (0.0, 1.0, 0.0)
 }
 
+// The width of a full-frame sensor
+fn default_sensor_width() -> f32 {
+    36.0
+}
+
+fn default_sun_color() -> Color {
+    Color::WHITE
+}
+
+fn default_sun_intensity() -> f32 {
+    1.0
+}
+
+// Larger than the real sun's 0.27° so that scenes converge without light sampling
+fn default_sun_angular_radius() -> f32 {
+    2.0
+}
+
 #[derive(Deserialize)]
 struct CameraDesc {
     position: Point,
@@ -31,9 +69,41 @@ struct CameraDesc {
     lookat: Point,
     #[serde(default = "default_up_vector")]
     up: (f32, f32, f32),
-    vfov: f32,
+    /// Vertical field of view in degrees. Can be left out in favor of `focal_length`.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    vfov: Option<f32>,
+    /// Focal length in millimetres, which gives the field of view along with `sensor_width`
+    #[serde(default, deserialize_with = "deserialize_some")]
+    focal_length: Option<f32>,
+    /// Sensor width in millimetres
+    #[serde(default = "default_sensor_width")]
+    sensor_width: f32,
     focus_distance: Option<f32>,
+    /// Lens diameter in scene units. Can be left out in favor of `f_number`.
+    #[serde(default)]
     aperture: f32,
+    /// Gives the aperture from the focal length, taking scene units to be metres
+    #[serde(default, deserialize_with = "deserialize_some")]
+    f_number: Option<f32>,
+    #[serde(default)]
+    aperture_blades: u32,
+}
+
+impl CameraDesc {
+    fn validate(&self) -> Result<(), SceneError> {
+        let reason = match (self.vfov, self.focal_length) {
+            (None, None) => "either vfov or focal_length is required",
+            (Some(_), Some(_)) => "vfov and focal_length cannot be combined",
+            _ if self.f_number.is_some() && self.focal_length.is_none() => {
+                "f_number requires focal_length"
+            }
+            _ if self.f_number.is_some() && self.aperture != 0.0 => {
+                "aperture and f_number cannot be combined"
+            }
+            _ => return Ok(()),
+        };
+        Err(SceneError::InvalidCamera { reason })
+    }
 }
 
 struct ColorVisitor;
@@ -80,41 +150,192 @@ where
     d.deserialize_str(ColorVisitor)
 }
 
+// Lets optional fields be written without `Some`, as they stand in for required ones
+fn deserialize_some<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(d).map(Some)
+}
+
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 enum MaterialDesc {
     Lambertian {
         #[serde(deserialize_with = "deserialize_color")]
         albedo: Color,
+        #[serde(deserialize_with = "deserialize_color", default)]
+        emission: Color,
     },
     Metal {
         #[serde(deserialize_with = "deserialize_color")]
         albedo: Color,
         #[serde(default)]
         fuzz: f32,
+        #[serde(deserialize_with = "deserialize_color", default)]
+        emission: Color,
+    },
+    AnisotropicMetal {
+        #[serde(deserialize_with = "deserialize_color")]
+        albedo: Color,
+        roughness_u: f32,
+        roughness_v: f32,
+        #[serde(default)]
+        tangent: Option<Point>,
     },
     Dialectric {
         refraction_index: f32,
+        #[serde(default)]
+        absorption: (f32, f32, f32),
+        #[serde(default)]
+        roughness: f32,
+        #[serde(default)]
+        abbe: Option<f32>,
     },
     DiffuseLight {
+        emit: TextureDesc,
+        #[serde(default = "default_true")]
+        two_sided: bool,
+    },
+    /// Scatters uniformly in all directions, for volumes or as a cheap stand-in for
+    /// subsurface scattering
+    Isotropic {
+        #[serde(deserialize_with = "deserialize_color")]
+        albedo: Color,
+    },
+    Mix {
+        first: String,
+        second: String,
+        factor: f32,
+    },
+    OrenNayar {
+        #[serde(deserialize_with = "deserialize_color")]
+        albedo: Color,
+        roughness: f32,
+    },
+    Pbr {
+        #[serde(deserialize_with = "deserialize_color")]
+        base_color: Color,
+        #[serde(default)]
+        metallic: f32,
+        roughness: f32,
+        #[serde(deserialize_with = "deserialize_color", default)]
+        emission: Color,
+    },
+    Spotlight {
         #[serde(deserialize_with = "deserialize_color")]
         emit: Color,
+        direction: Point,
+        /// Cone half-angles in degrees
+        inner_angle: f32,
+        outer_angle: f32,
     },
 }
 
-impl From<&MaterialDesc> for Arc<dyn Material> {
-    fn from(desc: &MaterialDesc) -> Self {
-        match desc {
-            MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian { albedo: *albedo }),
-            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal {
+/// Either a plain color string or an OpenEXR image relative to the scene file, which covers
+/// the texture coordinates of the surface
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureDesc {
+    Color(#[serde(deserialize_with = "deserialize_color")] Color),
+    Image { image: PathBuf },
+}
+
+impl TextureDesc {
+    fn construct(&self, base_dir: &Path) -> Result<Arc<dyn Texture>, SceneError> {
+        Ok(match self {
+            TextureDesc::Color(color) => Arc::new(*color),
+            TextureDesc::Image { image } => {
+                Arc::new(ImageTexture::new(load_image(base_dir.join(image))?))
+            }
+        })
+    }
+}
+
+impl MaterialDesc {
+    fn construct(
+        &self,
+        base_dir: &Path,
+        mut lookup: impl FnMut(&String) -> Result<Arc<dyn Material>, SceneError>,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        Ok(match self {
+            MaterialDesc::Lambertian { albedo, emission } => Arc::new(Lambertian {
+                albedo: *albedo,
+                emission: *emission,
+            }),
+            MaterialDesc::Metal {
+                albedo,
+                fuzz,
+                emission,
+            } => Arc::new(Metal {
                 albedo: *albedo,
                 fuzz: *fuzz,
+                emission: *emission,
             }),
-            MaterialDesc::Dialectric { refraction_index } => Arc::new(Dialectric {
+            MaterialDesc::AnisotropicMetal {
+                albedo,
+                roughness_u,
+                roughness_v,
+                tangent,
+            } => Arc::new(AnisotropicMetal {
+                albedo: *albedo,
+                roughness_u: *roughness_u,
+                roughness_v: *roughness_v,
+                tangent: tangent.map(Vector::from),
+            }),
+            MaterialDesc::Dialectric {
+                refraction_index,
+                absorption: (r, g, b),
+                roughness,
+                abbe,
+            } => Arc::new(Dialectric {
                 index: *refraction_index,
+                abbe: *abbe,
+                absorption: Color::from_rgb(*r, *g, *b),
+                roughness: *roughness,
             }),
-            MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight { emit: *emit }),
-        }
+            MaterialDesc::DiffuseLight { emit, two_sided } => Arc::new(DiffuseLight {
+                emit: emit.construct(base_dir)?,
+                two_sided: *two_sided,
+            }),
+            MaterialDesc::Isotropic { albedo } => Arc::new(Isotropic { albedo: *albedo }),
+            MaterialDesc::Mix {
+                first,
+                second,
+                factor,
+            } => Arc::new(MixMaterial {
+                first: lookup(first)?,
+                second: lookup(second)?,
+                factor: *factor,
+            }),
+            MaterialDesc::OrenNayar { albedo, roughness } => Arc::new(OrenNayar {
+                albedo: *albedo,
+                roughness: *roughness,
+            }),
+            MaterialDesc::Pbr {
+                base_color,
+                metallic,
+                roughness,
+                emission,
+            } => Arc::new(Pbr {
+                base_color: *base_color,
+                metallic: *metallic,
+                roughness: *roughness,
+                emission: *emission,
+            }),
+            MaterialDesc::Spotlight {
+                emit,
+                direction,
+                inner_angle,
+                outer_angle,
+            } => Arc::new(Spotlight {
+                emit: *emit,
+                direction: Vector::from(*direction),
+                inner_angle: inner_angle.to_radians(),
+                outer_angle: outer_angle.to_radians(),
+            }),
+        })
     }
 }
 
@@ -125,80 +346,819 @@ enum ObjectDesc {
         center: Point,
         radius: f32,
         material: String,
+        #[serde(default)]
+        scale: Option<Point>,
+        #[serde(default)]
+        rotate: Option<RotationDesc>,
+        #[serde(default)]
+        translate: Option<Point>,
+    },
+    MeshFile {
+        /// OBJ file, relative to the scene file
+        path: PathBuf,
+        material: String,
+        /// Skips hits on the back of triangles, see [`TriangleMesh::with_back_face_culling`]
+        #[serde(default)]
+        cull_back_faces: bool,
+        #[serde(default)]
+        scale: Option<Point>,
+        #[serde(default)]
+        rotate: Option<RotationDesc>,
+        #[serde(default)]
+        translate: Option<Point>,
     },
     Mesh {
         vertices: Vec<f32>,
         indices: Vec<u32>,
         material: String,
+        // Additional materials selected by `material_ids`; id 0 refers to `material`
+        #[serde(default)]
+        materials: Vec<String>,
+        #[serde(default)]
+        material_ids: Vec<u32>,
+        #[serde(default)]
+        cull_back_faces: bool,
+        #[serde(default)]
+        scale: Option<Point>,
+        #[serde(default)]
+        rotate: Option<RotationDesc>,
+        #[serde(default)]
+        translate: Option<Point>,
     },
 }
 
+#[derive(Clone, Copy, Deserialize)]
+struct RotationDesc {
+    axis: Point,
+    /// Counterclockwise rotation around the axis in degrees
+    angle: f32,
+}
+
+/// Places an object in the world, applying the transforms in the order scale, rotate,
+/// translate. Returns `None` if no transform is given.
+fn construct_transform(
+    scale: Option<Point>,
+    rotate: Option<RotationDesc>,
+    translate: Option<Point>,
+) -> Option<Transform> {
+    if scale.is_none() && rotate.is_none() && translate.is_none() {
+        return None;
+    }
+
+    let mut transform = Transform::IDENTITY;
+    if let Some(scale) = scale {
+        transform = transform.then(&Transform::scale(scale.into()));
+    }
+    if let Some(RotationDesc { axis, angle }) = rotate {
+        transform = transform.then(&Transform::rotate(axis.into(), angle.to_radians()));
+    }
+    if let Some(offset) = translate {
+        transform = transform.then(&Transform::translate(offset.into()));
+    }
+    Some(transform)
+}
+
+impl ObjectDesc {
+    fn transform(&self) -> Option<Transform> {
+        let (ObjectDesc::Sphere {
+            scale,
+            rotate,
+            translate,
+            ..
+        }
+        | ObjectDesc::Mesh {
+            scale,
+            rotate,
+            translate,
+            ..
+        }
+        | ObjectDesc::MeshFile {
+            scale,
+            rotate,
+            translate,
+            ..
+        }) = self;
+
+        construct_transform(*scale, *rotate, *translate)
+    }
+}
+
+/// Places a prototype in the world. The prototype's geometry is shared between all of its
+/// instances.
+#[derive(Deserialize)]
+struct InstanceDesc {
+    prototype: String,
+    #[serde(default)]
+    scale: Option<Point>,
+    #[serde(default)]
+    rotate: Option<RotationDesc>,
+    #[serde(default)]
+    translate: Option<Point>,
+}
+
+/// Either a plain color string or one of the typed backgrounds
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundDesc {
+    Color(#[serde(deserialize_with = "deserialize_color")] Color),
+    Typed(TypedBackgroundDesc),
+}
+
+impl Default for BackgroundDesc {
+    fn default() -> Self {
+        Self::Color(Color::BLACK)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum TypedBackgroundDesc {
+    Gradient {
+        #[serde(deserialize_with = "deserialize_color")]
+        bottom: Color,
+        #[serde(deserialize_with = "deserialize_color")]
+        top: Color,
+    },
+    /// Equirectangular OpenEXR image, relative to the scene file
+    Environment { path: PathBuf },
+}
+
+// Checks what `TriangleMesh` expects of its vertex positions and triangle indices
+fn check_mesh(vertices: &[f32], indices: &[u32]) -> Result<(), &'static str> {
+    if !vertices.len().is_multiple_of(3) {
+        return Err("vertex coordinates are not a multiple of three");
+    }
+    if !indices.len().is_multiple_of(3) {
+        return Err("triangle indices are not a multiple of three");
+    }
+    let num_vertices = vertices.len() / 3;
+    if indices.iter().any(|&index| index as usize >= num_vertices) {
+        return Err("triangle index out of range of the vertices");
+    }
+    Ok(())
+}
+
+fn load_image(path: PathBuf) -> Result<Image, SceneError> {
+    let is_exr = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+    if !is_exr {
+        return Err(SceneError::UnsupportedImageFormat { path });
+    }
+    load_exr(path)
+}
+
+#[cfg(feature = "exr")]
+fn load_exr(path: PathBuf) -> Result<Image, SceneError> {
+    Image::load_exr(&path).map_err(|source| SceneError::Exr { path, source })
+}
+
+#[cfg(not(feature = "exr"))]
+fn load_exr(path: PathBuf) -> Result<Image, SceneError> {
+    Err(SceneError::UnsupportedImageFormat { path })
+}
+
+#[derive(Deserialize)]
+struct SunDesc {
+    /// Direction towards the sun
+    direction: Point,
+    #[serde(deserialize_with = "deserialize_color", default = "default_sun_color")]
+    color: Color,
+    #[serde(default = "default_sun_intensity")]
+    intensity: f32,
+    /// Angular radius in degrees
+    #[serde(default = "default_sun_angular_radius")]
+    angular_radius: f32,
+}
+
 #[derive(Deserialize)]
 pub struct Scene {
     camera: CameraDesc,
     materials: HashMap<String, MaterialDesc>,
     objects: Vec<ObjectDesc>,
-    #[serde(deserialize_with = "deserialize_color", default)]
-    pub background: Color,
+    /// Named objects that are only placed in the world through `instances`
+    #[serde(default)]
+    prototypes: HashMap<String, ObjectDesc>,
+    #[serde(default)]
+    instances: Vec<InstanceDesc>,
+    #[serde(default)]
+    background: BackgroundDesc,
+    #[serde(default)]
+    sun: Option<SunDesc>,
+    /// Directory that relative paths in the scene are resolved against
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+/// Identifies the object description an error originates from
+#[derive(Debug)]
+pub enum ObjectRef {
+    /// Index into the scene's objects
+    Object(usize),
+    Prototype(String),
+}
+
+impl fmt::Display for ObjectRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Object(idx) => write!(f, "object {idx}"),
+            Self::Prototype(name) => write!(f, "prototype \"{name}\""),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: Box<ron::error::SpannedError>,
+    },
+    UndefinedMaterial {
+        object: ObjectRef,
+        material: String,
+    },
+    UndefinedMaterialReference {
+        material: String,
+        reference: String,
+    },
+    MaterialCycle {
+        material: String,
+    },
+    SingularTransform {
+        object: ObjectRef,
+    },
+    InvalidSphereRadius {
+        object: ObjectRef,
+        radius: f32,
+    },
+    MaterialIdCount {
+        object: ObjectRef,
+        triangles: usize,
+        material_ids: usize,
+    },
+    InvalidMaterialId {
+        object: ObjectRef,
+        id: u32,
+        materials: usize,
+    },
+    MaterialsWithoutIds {
+        object: ObjectRef,
+    },
+    InvalidMesh {
+        object: ObjectRef,
+        reason: &'static str,
+    },
+    InvalidCamera {
+        reason: &'static str,
+    },
+    SingularInstanceTransform {
+        instance: usize,
+    },
+    UndefinedPrototype {
+        instance: usize,
+        prototype: String,
+    },
+    Obj {
+        path: PathBuf,
+        source: ObjError,
+    },
+    UnsupportedMeshFormat {
+        path: PathBuf,
+    },
+    #[cfg(feature = "exr")]
+    Exr {
+        path: PathBuf,
+        source: exr::error::Error,
+    },
+    UnsupportedImageFormat {
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            Self::Parse { path, source } => {
+                let start = source.span.start;
+                write!(
+                    f,
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    start.line,
+                    start.col,
+                    source.code
+                )
+            }
+            Self::UndefinedMaterial { object, material } => {
+                write!(f, "{object} references undefined material \"{material}\"")
+            }
+            Self::UndefinedMaterialReference {
+                material,
+                reference,
+            } => write!(
+                f,
+                "material \"{material}\" references undefined material \"{reference}\""
+            ),
+            Self::MaterialCycle { material } => {
+                write!(f, "material \"{material}\" is part of a reference cycle")
+            }
+            Self::SingularTransform { object } => {
+                write!(f, "{object} has a transform that cannot be inverted")
+            }
+            Self::InvalidSphereRadius { object, radius } => {
+                write!(
+                    f,
+                    "{object} has radius {radius}, but spheres need a finite, non-zero radius"
+                )
+            }
+            Self::MaterialIdCount {
+                object,
+                triangles,
+                material_ids,
+            } => write!(
+                f,
+                "{object} has {triangles} triangles but {material_ids} material ids"
+            ),
+            Self::InvalidMaterialId {
+                object,
+                id,
+                materials,
+            } => write!(
+                f,
+                "{object} uses material id {id}, but only has {materials} materials"
+            ),
+            Self::MaterialsWithoutIds { object } => {
+                write!(
+                    f,
+                    "{object} lists materials but no material ids to select them"
+                )
+            }
+            Self::InvalidMesh { object, reason } => write!(f, "{object}: {reason}"),
+            Self::InvalidCamera { reason } => write!(f, "camera: {reason}"),
+            Self::SingularInstanceTransform { instance } => {
+                write!(
+                    f,
+                    "instance {instance} has a transform that cannot be inverted"
+                )
+            }
+            Self::UndefinedPrototype {
+                instance,
+                prototype,
+            } => write!(
+                f,
+                "instance {instance} references undefined prototype \"{prototype}\""
+            ),
+            Self::Obj { path, source } => write!(f, "{}: {source}", path.display()),
+            Self::UnsupportedMeshFormat { path } => {
+                write!(f, "{}: unsupported mesh format", path.display())
+            }
+            #[cfg(feature = "exr")]
+            Self::Exr { path, source } => write!(f, "{}: {source}", path.display()),
+            Self::UnsupportedImageFormat { path } => {
+                write!(f, "{}: unsupported image format", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Parse { source, .. } => Some(source),
+            Self::Obj { source, .. } => Some(source),
+            #[cfg(feature = "exr")]
+            Self::Exr { source, .. } => Some(source),
+            Self::UndefinedMaterial { .. }
+            | Self::UndefinedMaterialReference { .. }
+            | Self::MaterialCycle { .. }
+            | Self::SingularTransform { .. }
+            | Self::InvalidSphereRadius { .. }
+            | Self::MaterialIdCount { .. }
+            | Self::InvalidMaterialId { .. }
+            | Self::MaterialsWithoutIds { .. }
+            | Self::InvalidMesh { .. }
+            | Self::InvalidCamera { .. }
+            | Self::SingularInstanceTransform { .. }
+            | Self::UndefinedPrototype { .. }
+            | Self::UnsupportedMeshFormat { .. }
+            | Self::UnsupportedImageFormat { .. } => None,
+        }
+    }
 }
 
 impl Scene {
-    pub fn from_file(file: &Path) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(ron::from_str(&std::fs::read_to_string(file)?)?)
+    pub fn from_file(file: &Path) -> Result<Self, SceneError> {
+        let contents = std::fs::read_to_string(file).map_err(|source| SceneError::Io {
+            path: file.to_owned(),
+            source,
+        })?;
+        let mut scene: Self = ron::from_str(&contents).map_err(|source| SceneError::Parse {
+            path: file.to_owned(),
+            source: Box::new(source),
+        })?;
+        scene.base_dir = file.parent().map(Path::to_owned).unwrap_or_default();
+        scene.camera.validate()?;
+        Ok(scene)
+    }
+
+    pub fn construct_background(&self) -> Result<Arc<dyn Background>, SceneError> {
+        Ok(match &self.background {
+            BackgroundDesc::Color(color) => Arc::new(*color),
+            BackgroundDesc::Typed(TypedBackgroundDesc::Gradient { bottom, top }) => {
+                Arc::new(Gradient {
+                    bottom: *bottom,
+                    top: *top,
+                })
+            }
+            BackgroundDesc::Typed(TypedBackgroundDesc::Environment { path }) => {
+                Arc::new(EnvironmentMap::new(load_image(self.base_dir.join(path))?))
+            }
+        })
+    }
+
+    pub fn sun(&self) -> Option<Sun> {
+        self.sun.as_ref().map(|desc| Sun {
+            direction: desc.direction.into(),
+            emission: desc.color * desc.intensity,
+            angular_radius: desc.angular_radius.to_radians(),
+        })
     }
 
     pub fn construct_camera(&self, aspect_ratio: f32) -> Camera {
+        self.camera_at(self.camera.position.into(), aspect_ratio)
+    }
+
+    /// Constructs the camera after rotating its position by `angle` radians around the up
+    /// vector through the look-at point, e.g. for turntable animations
+    pub fn construct_orbit_camera(&self, aspect_ratio: f32, angle: f32) -> Camera {
+        let desc = &self.camera;
+        let lookat: Vector = desc.lookat.into();
+        let axis = Vector::from_xyz(desc.up.0, desc.up.1, desc.up.2).normalize_unchecked();
+        let offset = Vector::from(desc.position) - lookat;
+
+        let (sin, cos) = angle.sin_cos();
+        let rotated =
+            offset * cos + axis.cross3(offset) * sin + axis * (axis.dot(offset) * (1.0 - cos));
+
+        self.camera_at(lookat + rotated, aspect_ratio)
+    }
+
+    fn camera_at(&self, position: Vector, aspect_ratio: f32) -> Camera {
         let desc = &self.camera;
-        let position = desc.position.into();
         let lookat = desc.lookat.into();
 
+        let vfov = match desc.focal_length {
+            Some(focal_length) => {
+                camera::vfov_from_focal_length(focal_length, desc.sensor_width, aspect_ratio)
+            }
+            None => desc.vfov.unwrap_or_default(),
+        };
+        let aperture = match (desc.f_number, desc.focal_length) {
+            (Some(f_number), Some(focal_length)) => {
+                camera::aperture_from_f_number(focal_length, f_number)
+            }
+            _ => desc.aperture,
+        };
+
         Camera::new(
             position,
             lookat,
             Vector::from_xyz(desc.up.0, desc.up.1, desc.up.2),
-            desc.vfov,
+            vfov,
             aspect_ratio,
-            desc.aperture,
+            aperture,
+            desc.aperture_blades,
             desc.focus_distance
                 .unwrap_or_else(|| (lookat - position).length()),
         )
     }
 
-    pub fn construct_world(&self) -> Vec<Arc<dyn Object>> {
+    // Materials may reference other materials, so they are constructed depth first. `pending`
+    // holds the chain of materials currently being constructed to detect cycles.
+    fn construct_material(
+        &self,
+        name: &String,
+        materials: &mut HashMap<String, Arc<dyn Material>>,
+        pending: &mut Vec<String>,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        if let Some(material) = materials.get(name) {
+            return Ok(Arc::clone(material));
+        }
+        if pending.contains(name) {
+            return Err(SceneError::MaterialCycle {
+                material: name.clone(),
+            });
+        }
+        let desc =
+            self.materials
+                .get(name)
+                .ok_or_else(|| SceneError::UndefinedMaterialReference {
+                    material: pending.last().cloned().unwrap_or_default(),
+                    reference: name.clone(),
+                })?;
+
+        pending.push(name.clone());
+        let material = desc.construct(&self.base_dir, |reference: &String| {
+            self.construct_material(reference, materials, pending)
+        })?;
+        pending.pop();
+
+        materials.insert(name.clone(), Arc::clone(&material));
+        Ok(material)
+    }
+
+    fn construct_object(
+        &self,
+        desc: &ObjectDesc,
+        object_ref: impl Fn() -> ObjectRef,
+        materials: &HashMap<String, Arc<dyn Material>>,
+    ) -> Result<Arc<dyn Object>, SceneError> {
+        let lookup_material = |name: &String| {
+            materials
+                .get(name)
+                .map(Arc::clone)
+                .ok_or_else(|| SceneError::UndefinedMaterial {
+                    object: object_ref(),
+                    material: name.clone(),
+                })
+        };
+
+        let object: Arc<dyn Object> = match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+                ..
+            } => {
+                if *radius == 0.0 || !radius.is_finite() {
+                    return Err(SceneError::InvalidSphereRadius {
+                        object: object_ref(),
+                        radius: *radius,
+                    });
+                }
+                let material = lookup_material(material)?;
+                Arc::new(Sphere::new((*center).into(), *radius, material))
+            }
+            ObjectDesc::Mesh {
+                vertices,
+                indices,
+                material,
+                materials: extra_materials,
+                material_ids,
+                cull_back_faces,
+                ..
+            } => {
+                check_mesh(vertices, indices).map_err(|reason| SceneError::InvalidMesh {
+                    object: object_ref(),
+                    reason,
+                })?;
+
+                let vertices = vertices.clone().into_boxed_slice();
+                let indices = indices.clone().into_boxed_slice();
+                let mut mesh = if material_ids.is_empty() {
+                    if !extra_materials.is_empty() {
+                        return Err(SceneError::MaterialsWithoutIds {
+                            object: object_ref(),
+                        });
+                    }
+                    TriangleMesh::new(vertices, indices, lookup_material(material)?)
+                } else {
+                    let num_triangles = indices.len() / 3;
+                    if material_ids.len() != num_triangles {
+                        return Err(SceneError::MaterialIdCount {
+                            object: object_ref(),
+                            triangles: num_triangles,
+                            material_ids: material_ids.len(),
+                        });
+                    }
+                    let num_materials = 1 + extra_materials.len();
+                    if let Some(&id) = material_ids
+                        .iter()
+                        .find(|&&id| id as usize >= num_materials)
+                    {
+                        return Err(SceneError::InvalidMaterialId {
+                            object: object_ref(),
+                            id,
+                            materials: num_materials,
+                        });
+                    }
+
+                    let mesh_materials = iter::once(material)
+                        .chain(extra_materials)
+                        .map(lookup_material)
+                        .collect::<Result<_, _>>()?;
+                    TriangleMesh::with_materials(
+                        vertices,
+                        indices,
+                        Some(material_ids.clone().into_boxed_slice()),
+                        mesh_materials,
+                    )
+                };
+                if *cull_back_faces {
+                    mesh = mesh.with_back_face_culling();
+                }
+                Arc::new(Bvh::new(mesh, bvh::DEFAULT_MAX_LEAF_SIZE))
+            }
+            ObjectDesc::MeshFile {
+                path,
+                material,
+                cull_back_faces,
+                ..
+            } => {
+                let path = self.base_dir.join(path);
+                let is_obj = path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("obj"));
+                if !is_obj {
+                    return Err(SceneError::UnsupportedMeshFormat { path });
+                }
+
+                let contents = match std::fs::read_to_string(&path) {
+                    Ok(contents) => contents,
+                    Err(source) => return Err(SceneError::Io { path, source }),
+                };
+                let obj = match obj::parse(&contents) {
+                    Ok(obj) => obj,
+                    Err(source) => return Err(SceneError::Obj { path, source }),
+                };
+                check_mesh(&obj.vertices, &obj.indices).map_err(|reason| {
+                    SceneError::InvalidMesh {
+                        object: object_ref(),
+                        reason,
+                    }
+                })?;
+
+                let mut mesh = TriangleMesh::new(
+                    obj.vertices.into_boxed_slice(),
+                    obj.indices.into_boxed_slice(),
+                    lookup_material(material)?,
+                );
+                if *cull_back_faces {
+                    mesh = mesh.with_back_face_culling();
+                }
+                Arc::new(Bvh::new(mesh, bvh::DEFAULT_MAX_LEAF_SIZE))
+            }
+        };
+
+        match desc.transform() {
+            Some(transform) => {
+                let instance = Instance::new(object, transform).ok_or_else(|| {
+                    SceneError::SingularTransform {
+                        object: object_ref(),
+                    }
+                })?;
+                Ok(Arc::new(instance))
+            }
+            None => Ok(object),
+        }
+    }
+
+    pub fn construct_world(&self) -> Result<Vec<Arc<dyn Object>>, SceneError> {
         let mut objects = Vec::<Arc<dyn Object>>::new();
-        let materials: HashMap<String, Arc<dyn Material>> = self
-            .materials
-            .iter()
-            .map(|(name, desc)| (name.clone(), desc.into()))
-            .collect();
-
-        for object_desc in &self.objects {
-            match object_desc {
-                ObjectDesc::Sphere {
-                    center,
-                    radius,
-                    material,
-                } => {
-                    let material = materials.get(material).expect("undefined material");
-                    let sphere = Sphere::new((*center).into(), *radius, Arc::clone(material));
-                    objects.push(Arc::new(sphere));
+        let mut materials = HashMap::new();
+        for name in self.materials.keys() {
+            self.construct_material(name, &mut materials, &mut Vec::new())?;
+        }
+
+        for (i, object_desc) in self.objects.iter().enumerate() {
+            objects.push(self.construct_object(
+                object_desc,
+                || ObjectRef::Object(i),
+                &materials,
+            )?);
+        }
+
+        let mut prototypes = HashMap::new();
+        for (name, desc) in &self.prototypes {
+            let prototype =
+                self.construct_object(desc, || ObjectRef::Prototype(name.clone()), &materials)?;
+            prototypes.insert(name, prototype);
+        }
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            let prototype = prototypes.get(&instance.prototype).ok_or_else(|| {
+                SceneError::UndefinedPrototype {
+                    instance: i,
+                    prototype: instance.prototype.clone(),
                 }
-                ObjectDesc::Mesh {
-                    vertices,
-                    indices,
-                    material,
-                } => {
-                    let material = materials.get(material).expect("undefined material");
-                    let mesh = crate::geometry::TriangleMesh::new(
-                        vertices.clone().into_boxed_slice(),
-                        indices.clone().into_boxed_slice(),
-                        Arc::clone(material),
-                    );
-                    objects.push(Arc::new(Bvh::new(mesh)));
+            })?;
+            let prototype = Arc::clone(prototype);
+
+            match construct_transform(instance.scale, instance.rotate, instance.translate) {
+                Some(transform) => {
+                    let instance = Instance::new(prototype, transform)
+                        .ok_or(SceneError::SingularInstanceTransform { instance: i })?;
+                    objects.push(Arc::new(instance));
                 }
+                None => objects.push(prototype),
             }
         }
 
-        objects
+        Ok(objects)
     }
+
+    /// The lights among the objects that the render can sample directly, which are the
+    /// spheres with a `DiffuseLight` material and no transform
+    pub fn construct_lights(&self) -> Result<Vec<Arc<dyn LightSampleable>>, SceneError> {
+        let mut lights = Vec::<Arc<dyn LightSampleable>>::new();
+        let mut materials = HashMap::new();
+        for (i, object_desc) in self.objects.iter().enumerate() {
+            let ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+                ..
+            } = object_desc
+            else {
+                continue;
+            };
+            let is_light = matches!(
+                self.materials.get(material),
+                Some(MaterialDesc::DiffuseLight { .. })
+            );
+            if !is_light || object_desc.transform().is_some() {
+                continue;
+            }
+            if *radius == 0.0 || !radius.is_finite() {
+                return Err(SceneError::InvalidSphereRadius {
+                    object: ObjectRef::Object(i),
+                    radius: *radius,
+                });
+            }
+            let material = self.construct_material(material, &mut materials, &mut Vec::new())?;
+            lights.push(Arc::new(Sphere::new((*center).into(), *radius, material)));
+        }
+        Ok(lights)
+    }
+}
+
+/// The scene rendered for `builtin:spheres`: a grid of small metal spheres lit by a single
+/// spherical light
+pub fn builtin_spheres(aspect_ratio: f32) -> (Camera, Vec<Arc<dyn Object>>, Color) {
+    let lookfrom = Vector::from_xyz(13.0, 2.0, 3.0);
+    let lookat = Vector::from_xyz(0.0, 0.0, 0.0);
+    let vup = Vector::from_xyz(0.0, 1.0, 0.0);
+    let dist_to_focus = 10.0;
+    let aperture = 0.1;
+
+    let camera = Camera::new(
+        lookfrom,
+        lookat,
+        vup,
+        50.0,
+        aspect_ratio,
+        aperture,
+        0,
+        dist_to_focus,
+    );
+
+    let mut objects = Vec::<Arc<dyn Object>>::new();
+
+    let ground: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::from_rgb(0.5, 0.5, 0.5),
+        emission: Color::BLACK,
+    });
+    let floor_radius = 1000.0f32;
+    objects.push(Arc::new(Sphere::new(
+        Vector::from_xyz(0.0, -floor_radius, 0.0),
+        floor_radius,
+        Arc::clone(&ground),
+    )));
+
+    let num_spheres = 11;
+
+    for a in -num_spheres..num_spheres {
+        for b in -num_spheres..num_spheres {
+            let x = a as f32;
+            let z = b as f32;
+            let y = (-x * x - z * z + floor_radius * floor_radius).sqrt() - floor_radius;
+            let center = Vector::from_xyz(x, y + 0.2, z);
+
+            let albedo = Color::from_rgb(0.3, 0.7, 0.9);
+            let material = Arc::new(Metal {
+                albedo,
+                fuzz: 0.1,
+                emission: Color::BLACK,
+            });
+            objects.push(Arc::new(Sphere::new(center, 0.2, material)));
+        }
+    }
+
+    let light: Arc<dyn Material> = Arc::new(DiffuseLight {
+        emit: Arc::new(Color::WHITE * 4.0),
+        two_sided: true,
+    });
+    objects.push(Arc::new(Sphere::new(
+        Vector::from_xyz(0.0, 3.0, 0.0),
+        0.5,
+        Arc::clone(&light),
+    )));
+
+    (camera, objects, Color::BLACK)
 }
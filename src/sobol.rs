@@ -0,0 +1,37 @@
+// Owen-scrambled Sobol points following Burley, "Practical Hash-based Owen Scrambling" (2020).
+// Only the first two Sobol dimensions are used, higher dimensions are padded with
+// independently shuffled and scrambled pairs.
+
+pub fn sample_2d(index: u32, seeds: [u32; 3]) -> [u32; 2] {
+    let index = nested_uniform_scramble(index, seeds[0]);
+    [
+        nested_uniform_scramble(index.reverse_bits(), seeds[1]),
+        nested_uniform_scramble(sobol_dim1(index), seeds[2]),
+    ]
+}
+
+fn sobol_dim1(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut v = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
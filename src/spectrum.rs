@@ -0,0 +1,180 @@
+use std::{
+    ops::{AddAssign, Mul, MulAssign},
+    sync::LazyLock,
+};
+
+use crate::color::Color;
+
+/// Shortest wavelength in nanometers a spectral render samples
+pub const MIN_WAVELENGTH: f32 = 380.0;
+/// Longest wavelength in nanometers a spectral render samples
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+/// Number of wavelengths carried by a path
+pub const NUM_WAVELENGTHS: usize = 4;
+
+/// Picks the hero wavelength of a path from the uniform random number `u`, followed by the ones
+/// spaced evenly from it across the visible range, wrapping around at its end
+pub fn sample_wavelengths(u: f32) -> [f32; NUM_WAVELENGTHS] {
+    let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+    std::array::from_fn(|i| {
+        let offset = (u + i as f32 / NUM_WAVELENGTHS as f32).fract();
+        MIN_WAVELENGTH + offset * range
+    })
+}
+
+/// Value at `wavelength` of a smooth reflectance spectrum for the linear RGB `color`. The
+/// spectrum blends the channels over 475-515 nm and 565-605 nm, so gray colors become flat
+/// spectra and channels in [0, 1] stay within [0, 1].
+pub fn reflectance(color: Color, wavelength: f32) -> f32 {
+    let blue = 1.0 - smoothstep(475.0, 515.0, wavelength);
+    let red = smoothstep(565.0, 605.0, wavelength);
+    let green = 1.0 - blue - red;
+    color.r * red + color.g * green + color.b * blue
+}
+
+/// Value at `wavelength` of an emission spectrum for the linear RGB `color`, which is its
+/// [`reflectance`] applied to the CIE standard illuminant D65. White therefore emits D65, which
+/// the sensor maps back to white.
+pub fn illuminant(color: Color, wavelength: f32) -> f32 {
+    reflectance(color, wavelength) * d65(wavelength)
+}
+
+/// The CIE 1931 2° color-matching functions x̄, ȳ and z̄ at `wavelength` from the multi-lobe
+/// Gaussian fit by Wyman, Sloan and Shirley
+pub fn cie_xyz(wavelength: f32) -> [f32; 3] {
+    fn lobe(wavelength: f32, mean: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+        let sigma = if wavelength < mean {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (wavelength - mean) / sigma;
+        (-0.5 * t * t).exp()
+    }
+
+    let x = 1.056 * lobe(wavelength, 599.8, 37.9, 31.0)
+        + 0.362 * lobe(wavelength, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(wavelength, 501.1, 20.4, 26.2);
+    let y =
+        0.821 * lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * lobe(wavelength, 530.9, 16.3, 31.1);
+    let z =
+        1.217 * lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * lobe(wavelength, 459.0, 26.0, 13.8);
+    [x, y, z]
+}
+
+/// Linear sRGB response of the sensor to unit radiance at `wavelength`, scaled so that the D65
+/// spectrum integrates to white
+pub fn sensor_response(wavelength: f32) -> Color {
+    let white = *D65_RESPONSE;
+    let Color { r, g, b } = cie_rgb(wavelength);
+    Color::from_rgb(r / white.r, g / white.g, b / white.b)
+}
+
+/// Radiance or throughput of a path at its [`sample_wavelengths`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SampledSpectrum(pub [f32; NUM_WAVELENGTHS]);
+
+impl SampledSpectrum {
+    pub const ZERO: Self = Self([0.0; NUM_WAVELENGTHS]);
+    pub const ONE: Self = Self([1.0; NUM_WAVELENGTHS]);
+
+    pub fn from_reflectance(color: Color, wavelengths: &[f32; NUM_WAVELENGTHS]) -> Self {
+        Self(wavelengths.map(|wavelength| reflectance(color, wavelength)))
+    }
+
+    pub fn from_illuminant(color: Color, wavelengths: &[f32; NUM_WAVELENGTHS]) -> Self {
+        Self(wavelengths.map(|wavelength| illuminant(color, wavelength)))
+    }
+
+    /// Keeps only the hero wavelength, weighted by the number of wavelengths dropped with it
+    pub fn hero_only() -> Self {
+        let mut values = [0.0; NUM_WAVELENGTHS];
+        values[0] = NUM_WAVELENGTHS as f32;
+        Self(values)
+    }
+
+    /// Estimates the RGB color of the spectrum from its values at the uniformly sampled
+    /// `wavelengths`
+    pub fn to_rgb(self, wavelengths: &[f32; NUM_WAVELENGTHS]) -> Color {
+        let mut color = Color::BLACK;
+        for (value, &wavelength) in self.0.into_iter().zip(wavelengths) {
+            color += sensor_response(wavelength) * value;
+        }
+        color * ((MAX_WAVELENGTH - MIN_WAVELENGTH) / NUM_WAVELENGTHS as f32)
+    }
+}
+
+impl Mul<Self> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl MulAssign<Self> for SampledSpectrum {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl Mul<f32> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self(self.0.map(|value| value * rhs))
+    }
+}
+
+impl AddAssign<Self> for SampledSpectrum {
+    fn add_assign(&mut self, rhs: Self) {
+        for (value, rhs) in self.0.iter_mut().zip(rhs.0) {
+            *value += rhs;
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Color-matching functions converted to linear sRGB, without any white balance
+fn cie_rgb(wavelength: f32) -> Color {
+    let [x, y, z] = cie_xyz(wavelength);
+    Color::from_rgb(
+        3.240_454 * x - 1.537_139 * y - 0.498_531 * z,
+        -0.969_266 * x + 1.876_011 * y + 0.041_556 * z,
+        0.055_643 * x - 0.204_026 * y + 1.057_225 * z,
+    )
+}
+
+/// The unbalanced sRGB response to the D65 spectrum, integrated at 0.1 nm steps
+static D65_RESPONSE: LazyLock<Color> = LazyLock::new(|| {
+    const STEP: f32 = 0.1;
+    let num_steps = ((MAX_WAVELENGTH - MIN_WAVELENGTH) / STEP) as usize;
+    let mut response = Color::BLACK;
+    for i in 0..num_steps {
+        let wavelength = MIN_WAVELENGTH + (i as f32 + 0.5) * STEP;
+        response += cie_rgb(wavelength) * (d65(wavelength) * STEP);
+    }
+    response
+});
+
+/// Relative spectral power of the CIE standard illuminant D65, normalized to 100 at 560 nm
+fn d65(wavelength: f32) -> f32 {
+    // Tabulated at 10 nm steps from 380 nm to 780 nm
+    const TABLE: [f32; 41] = [
+        49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+        115.923, 108.811, 109.354, 107.802, 104.790, 107.689, 104.405, 104.046, 100.0, 96.3342,
+        95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778,
+        78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054,
+        63.3828,
+    ];
+
+    let position = ((wavelength - MIN_WAVELENGTH) / 10.0).clamp(0.0, (TABLE.len() - 1) as f32);
+    let index = (position as usize).min(TABLE.len() - 2);
+    let t = position - index as f32;
+    TABLE[index] + t * (TABLE[index + 1] - TABLE[index])
+}
@@ -10,6 +10,10 @@ impl<T> SyncUnsafeCell<T> {
     pub const fn get(&self) -> *mut T {
         self.0.get()
     }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
 }
 
 unsafe impl<T> Sync for SyncUnsafeCell<T> {}
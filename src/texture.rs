@@ -0,0 +1,80 @@
+use crate::{color::Color, geometry::Uv, image::Image};
+
+/// Color and coverage over the texture coordinates of a surface. The coordinates are passed
+/// unevaluated, so textures that don't vary spare their computation.
+pub trait Texture: Send + Sync {
+    fn color(&self, uv: Uv) -> Color;
+
+    /// Coverage in [0, 1], where 0 is fully transparent
+    fn alpha(&self, _uv: Uv) -> f32 {
+        1.0
+    }
+}
+
+impl Texture for Color {
+    fn color(&self, _uv: Uv) -> Color {
+        *self
+    }
+}
+
+/// An image covering the unit square of texture coordinates with (0, 0) at its bottom left
+/// corner and repeating beyond it. Colors are filtered bilinearly, wrapping around the edges,
+/// while coverage takes the nearest pixel, which keeps the edges of alpha masks sharp. Images
+/// without alpha are opaque.
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image) -> Self {
+        assert!(image.width() > 0 && image.height() > 0);
+        Self { image }
+    }
+
+    fn index(&self, uv: Uv) -> usize {
+        let [u, v] = uv.coordinates();
+        let width = self.image.width();
+        let height = self.image.height();
+        let x = ((u * width as f32).floor() as i64).rem_euclid(width as i64) as usize;
+        let y = (((1.0 - v) * height as f32).floor() as i64).rem_euclid(height as i64) as usize;
+        y * width as usize + x
+    }
+
+    /// The four pixels around the texture coordinates, wrapped into the image, each with its
+    /// bilinear weight
+    fn neighbors(&self, uv: Uv) -> [(usize, f32); 4] {
+        let [u, v] = uv.coordinates();
+        let width = self.image.width() as i64;
+        let height = self.image.height() as i64;
+        // Pixel centers sit half a pixel inside their cells
+        let x = u * width as f32 - 0.5;
+        let y = (1.0 - v) * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let column = |offset: i64| (x0 as i64 + offset).rem_euclid(width) as usize;
+        let row = |offset: i64| (y0 as i64 + offset).rem_euclid(height) as usize * width as usize;
+        [
+            (row(0) + column(0), (1.0 - fx) * (1.0 - fy)),
+            (row(0) + column(1), fx * (1.0 - fy)),
+            (row(1) + column(0), (1.0 - fx) * fy),
+            (row(1) + column(1), fx * fy),
+        ]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn color(&self, uv: Uv) -> Color {
+        self.neighbors(uv)
+            .into_iter()
+            .fold(Color::BLACK, |color, (index, weight)| {
+                color + self.image.pixels[index] * weight
+            })
+    }
+
+    fn alpha(&self, uv: Uv) -> f32 {
+        self.image
+            .alpha
+            .as_ref()
+            .map_or(1.0, |alpha| alpha[self.index(uv)])
+    }
+}
@@ -50,10 +50,25 @@ impl Vector {
         self.length_squared().sqrt()
     }
 
+    /// Scales the vector to unit length. The vector must not be (almost) zero, otherwise the
+    /// result has infinite or NaN components.
     pub fn normalize_unchecked(self) -> Self {
         self / self.length()
     }
 
+    /// Scales the vector to unit length, or returns `None` if it is almost zero
+    pub fn normalize(self) -> Option<Self> {
+        (!self.is_almost_zero()).then(|| self.normalize_unchecked())
+    }
+
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        self + t * (other - self)
+    }
+
+    pub fn distance(self, other: Self) -> f32 {
+        (other - self).length()
+    }
+
     pub fn dot(self, other: Self) -> f32 {
         self.x() * other.x() + self.y() * other.y() + self.z() * other.z() + self.w() * other.w()
     }
@@ -66,6 +81,42 @@ impl Vector {
         Self::from_xyzw(x, y, z, self.w())
     }
 
+    /// Mirrors the vector at the plane with the given unit `normal`
+    ///
+    /// ```
+    /// # use raybow::vector::Vector;
+    /// // A ray coming down at 45° bounces off the ground and leaves upwards at 45°
+    /// let incoming = Vector::from_xyz(1.0, -1.0, 0.0);
+    /// let up = Vector::from_xyz(0.0, 1.0, 0.0);
+    /// assert_eq!(incoming.reflect(up), Vector::from_xyz(1.0, 1.0, 0.0));
+    /// ```
+    pub fn reflect(self, normal: Self) -> Self {
+        self - 2.0 * self.dot(normal) * normal
+    }
+
+    /// Refracts the unit vector through a surface with the unit `normal` facing against it,
+    /// where `eta` is the ratio of the refractive indices of the incident and transmitting
+    /// media. Returns `None` on total internal reflection.
+    ///
+    /// ```
+    /// # use raybow::vector::Vector;
+    /// let up = Vector::from_xyz(0.0, 1.0, 0.0);
+    ///
+    /// // Straight down passes through a horizontal surface without bending
+    /// let down = Vector::from_xyz(0.0, -1.0, 0.0);
+    /// assert_eq!(down.refract(up, 1.0 / 1.5), Some(down));
+    ///
+    /// // At a grazing angle, light leaving glass is reflected back inside
+    /// let grazing = Vector::from_xyz(0.9, -0.1, 0.0).normalize().unwrap();
+    /// assert_eq!(grazing.refract(up, 1.5), None);
+    /// ```
+    pub fn refract(self, normal: Self, eta: f32) -> Option<Self> {
+        let cos_theta = (-self).dot(normal).min(1.0);
+        let perp = eta * (self + cos_theta * normal);
+        let k = 1.0 - perp.length_squared();
+        (k >= 0.0).then(|| perp - k.sqrt() * normal)
+    }
+
     pub fn is_almost_zero(self) -> bool {
         self.0.into_iter().all(|v| v.abs() < 1e-8)
     }
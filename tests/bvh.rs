@@ -0,0 +1,134 @@
+use std::{ops::Range, sync::Arc};
+
+use raybow::{
+    Color,
+    bumpalo::Bump,
+    geometry::{Aabb, Bvh, Hit, Instance, Object, Sphere, Transform, World, bvh},
+    material::{Lambertian, Material},
+    ray::Ray,
+    vector::Vector,
+};
+
+/// A row of unit spheres along the x axis with a blocker at x = 5
+fn spheres() -> Bvh<Vec<Arc<dyn Object>>> {
+    let material: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::WHITE,
+        emission: Color::BLACK,
+    });
+    let objects = [-10.0, 5.0, 20.0, 30.0, 40.0]
+        .into_iter()
+        .map(|x| {
+            Arc::new(Sphere::new(
+                Vector::from_xyz(x, 0.0, 0.0),
+                1.0,
+                material.clone(),
+            )) as Arc<dyn Object>
+        })
+        .collect();
+    Bvh::new(objects, bvh::DEFAULT_MAX_LEAF_SIZE)
+}
+
+#[test]
+fn blocked_segment_is_occluded() {
+    let bvh = spheres();
+    let arena = Bump::new();
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(1.0, 0.0, 0.0));
+
+    assert!(bvh.occluded(ray, 10.0, &arena));
+    assert!(bvh.any_hit(ray, 0.0..f32::INFINITY, &arena));
+}
+
+#[test]
+fn segment_ending_before_blocker_is_not_occluded() {
+    let bvh = spheres();
+    let arena = Bump::new();
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(1.0, 0.0, 0.0));
+
+    // The blocker's surface starts at a distance of 4
+    assert!(!bvh.occluded(ray, 3.9, &arena));
+    assert!(bvh.occluded(ray, 4.1, &arena));
+}
+
+#[test]
+fn missing_ray_is_not_occluded() {
+    let bvh = spheres();
+    let arena = Bump::new();
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(0.0, 1.0, 0.0));
+
+    assert!(!bvh.occluded(ray, f32::INFINITY, &arena));
+    assert!(bvh.hit(ray, 0.0..f32::INFINITY, &arena).is_none());
+}
+
+#[test]
+fn trace_reports_nearest_hit() {
+    let bvh = spheres();
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(1.0, 0.0, 0.0));
+
+    let info = bvh.trace(ray).unwrap();
+    // The blocker is the second sphere passed to the BVH
+    assert_eq!(info.object, 1);
+    assert!((info.t - 4.0).abs() < 1e-5);
+    assert!(info.front_face);
+    assert!((info.normal - Vector::from_xyz(-1.0, 0.0, 0.0)).length() < 1e-5);
+
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(0.0, 1.0, 0.0));
+    assert!(bvh.trace(ray).is_none());
+}
+
+#[test]
+fn counted_hits_include_nested_bvhs() {
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(1.0, 0.0, 0.0));
+    let arena = Bump::new();
+
+    let flat = spheres();
+    let (hit, flat_counts) = flat.hit_counted(ray, 0.0..f32::INFINITY, &arena);
+    let index = flat.hit_indexed(ray, 0.0..f32::INFINITY, &arena).unwrap().1;
+    assert_eq!(hit.unwrap().1, index);
+    assert!(flat_counts.nodes > 0 && flat_counts.primitives > 0);
+
+    // The same spheres one level down count the inner traversal on top of the outer one
+    let nested = Bvh::new(vec![Arc::new(spheres()) as Arc<dyn Object>], 1);
+    let (hit, nested_counts) = nested.hit_counted(ray, 0.0..f32::INFINITY, &arena);
+    assert!((hit.unwrap().0.t - 4.0).abs() < 1e-5);
+    assert_eq!(nested_counts.nodes, flat_counts.nodes + 1);
+    assert_eq!(nested_counts.primitives, flat_counts.primitives + 1);
+}
+
+/// A unit cube around the origin that only answers occlusion queries, so any nearest hit
+/// search that reaches it fails the test
+struct OccluderOnly;
+
+impl Object for OccluderOnly {
+    fn hit(&self, _: Ray, _: Range<f32>, _: &Bump) -> Option<Hit<'_>> {
+        panic!("searched for the nearest hit instead of asking for occlusion");
+    }
+
+    fn occludes(&self, ray: Ray, t_range: Range<f32>, _: &Bump) -> bool {
+        // Only rays along the x axis from below -1 are used
+        t_range.contains(&((-1.0 - ray.origin.x()) / ray.direction.x()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb {
+            minimum: Vector::from_xyz(-1.0, -1.0, -1.0),
+            maximum: Vector::from_xyz(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+#[test]
+fn occlusion_reaches_into_nested_objects() {
+    let inner = Bvh::new(vec![Arc::new(OccluderOnly) as Arc<dyn Object>], 1);
+    let instance = Instance::new(
+        Arc::new(World::new(vec![Arc::new(inner) as Arc<dyn Object>])),
+        Transform::translate(Vector::from_xyz(5.0, 0.0, 0.0)),
+    )
+    .unwrap();
+    let outer = Bvh::new(vec![Arc::new(instance) as Arc<dyn Object>], 1);
+    let arena = Bump::new();
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(1.0, 0.0, 0.0));
+
+    // The cube's near side is at x = 4 once translated
+    assert!(outer.occluded(ray, 4.1, &arena));
+    assert!(!outer.occluded(ray, 3.9, &arena));
+}
@@ -0,0 +1,59 @@
+use raybow::Color;
+
+fn assert_close(a: Color, b: Color) {
+    let difference = (a.r - b.r)
+        .abs()
+        .max((a.g - b.g).abs())
+        .max((a.b - b.b).abs());
+    assert!(difference < 1e-5, "{a:?} != {b:?}");
+}
+
+#[test]
+fn luminance_of_primaries() {
+    assert!((Color::WHITE.luminance() - 1.0).abs() < 1e-6);
+    assert_eq!(Color::from_rgb(1.0, 0.0, 0.0).luminance(), 0.2126);
+    assert_eq!(Color::BLACK.luminance(), 0.0);
+}
+
+#[test]
+fn hsv_round_trip() {
+    let colors = [
+        Color::from_rgb(1.0, 0.0, 0.0),
+        Color::from_rgb(0.2, 0.7, 0.1),
+        Color::from_rgb(0.3, 0.4, 0.9),
+        Color::from_rgb(0.9, 0.1, 0.6),
+        Color::from_rgb(0.5, 0.5, 0.5),
+        Color::BLACK,
+    ];
+    for color in colors {
+        let (hue, saturation, value) = color.to_hsv();
+        assert!((0.0..360.0).contains(&hue), "hue {hue} of {color:?}");
+        assert_close(Color::from_hsv(hue, saturation, value), color);
+    }
+
+    assert_eq!(Color::from_rgb(0.0, 1.0, 0.0).to_hsv(), (120.0, 1.0, 1.0));
+    assert_close(
+        Color::from_hsv(240.0, 1.0, 0.5),
+        Color::from_rgb(0.0, 0.0, 0.5),
+    );
+}
+
+#[test]
+fn out_of_range_channels_saturate() {
+    let color = Color::from_rgb(4.0, -0.5, 0.25);
+    assert_eq!(color.saturate(), Color::from_rgb(1.0, 0.0, 0.25));
+    assert_eq!(color.clamp(0.0, 2.0), Color::from_rgb(2.0, 0.0, 0.25));
+
+    // HDR values become full intensity instead of wrapping around
+    assert_eq!(color.to_rgb_bytes_8bit(), [255, 0, 64]);
+    assert_eq!(
+        Color::from_rgb(300.0, -1.0, 1.0).to_rgb_bytes_16bit(),
+        [0xff, 0xff, 0, 0, 0xff, 0xff]
+    );
+}
+
+#[test]
+#[should_panic]
+fn clamp_with_inverted_bounds_panics() {
+    Color::WHITE.clamp(1.0, 0.0);
+}
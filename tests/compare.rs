@@ -0,0 +1,46 @@
+use raybow::{
+    Color,
+    compare::{self, CompareError},
+    image::Image,
+};
+
+fn gradient(width: u32, height: u32, offset: u32) -> Image {
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let value = ((x + offset) % width) as f32 / width as f32;
+            image.pixels[(y * width + x) as usize] =
+                Color::from_rgb(value, value * 0.5, 1.0 - value);
+        }
+    }
+    image
+}
+
+#[test]
+fn identical_images() {
+    let image = gradient(16, 16, 0);
+
+    assert_eq!(compare::psnr(&image, &image).unwrap(), f32::INFINITY);
+    assert!((compare::ssim(&image, &image).unwrap() - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn shifted_image() {
+    let image = gradient(16, 16, 0);
+    let shifted = gradient(16, 16, 3);
+
+    let psnr = compare::psnr(&image, &shifted).unwrap();
+    assert!(psnr.is_finite() && psnr > 0.0);
+    assert!(compare::ssim(&image, &shifted).unwrap() < 0.99);
+
+    // A smaller shift must compare better
+    let slightly_shifted = gradient(16, 16, 1);
+    assert!(compare::psnr(&image, &slightly_shifted).unwrap() > psnr);
+}
+
+#[test]
+fn mismatched_sizes() {
+    let result = compare::psnr(&Image::new(4, 4), &Image::new(4, 5));
+    assert!(matches!(result, Err(CompareError::SizeMismatch { .. })));
+    assert!(compare::ssim(&Image::new(4, 4), &Image::new(5, 4)).is_err());
+}
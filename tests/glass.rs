@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use raybow::{
+    Aovs, Camera, Color, RenderJob,
+    geometry::{LightSampleable, Object, Sphere},
+    image::Image,
+    material::{Dialectric, DiffuseLight, Lambertian},
+    render,
+    vector::Vector,
+};
+
+// A light inside a tinted glass sphere, seen through the sphere along its center. The path
+// enters the glass and ends at the light half a unit further, without leaving the medium.
+#[test]
+fn light_inside_tinted_glass_is_absorbed() {
+    let absorption = 2.0;
+    let objects: Vec<Arc<dyn Object>> = vec![
+        Arc::new(Sphere::new(
+            Vector::ZERO,
+            1.0,
+            Arc::new(Dialectric {
+                index: 1.5,
+                abbe: None,
+                absorption: Color::from_rgb(absorption, absorption, absorption),
+                roughness: 0.0,
+            }),
+        )),
+        Arc::new(Sphere::new(
+            Vector::ZERO,
+            0.5,
+            Arc::new(DiffuseLight {
+                emit: Arc::new(Color::WHITE),
+                two_sided: true,
+            }),
+        )),
+    ];
+    let camera = Camera::new(
+        Vector::from_xyz(0.0, 0.0, 5.0),
+        Vector::ZERO,
+        Vector::from_xyz(0.0, 1.0, 0.0),
+        1.0,
+        1.0,
+        0.0,
+        0,
+        5.0,
+    );
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .background(Color::BLACK)
+        .samples(256)
+        .seed(1)
+        .build()
+        .unwrap();
+
+    let mut image = Image::new(4, 4);
+    render(job, &mut image, &mut Aovs::default(), |_, _| {});
+    let mean = image
+        .pixels
+        .iter()
+        .map(|color| color.luminance())
+        .sum::<f32>()
+        / image.pixels.len() as f32;
+
+    // Schlick's approximation reflects 4% of the light at normal incidence
+    let expected = 0.96 * (-absorption * 0.5).exp();
+    assert!(
+        (mean - expected).abs() < 0.03,
+        "mean luminance {mean}, expected {expected}"
+    );
+}
+
+// A diffuse sphere, lit by a light next to it, seen through the large tinted glass sphere both
+// are inside of
+fn mean_luminance_inside_glass(sample_light: bool, samples: u32) -> f32 {
+    let light = Arc::new(Sphere::new(
+        Vector::from_xyz(0.8, 0.0, 0.0),
+        0.3,
+        Arc::new(DiffuseLight {
+            emit: Arc::new(Color::WHITE * 4.0),
+            two_sided: true,
+        }),
+    ));
+    let objects: Vec<Arc<dyn Object>> = vec![
+        Arc::new(Sphere::new(
+            Vector::ZERO,
+            2.0,
+            Arc::new(Dialectric {
+                index: 1.5,
+                abbe: None,
+                absorption: Color::from_rgb(1.0, 1.0, 1.0),
+                roughness: 0.0,
+            }),
+        )),
+        Arc::new(Sphere::new(
+            Vector::from_xyz(-0.8, 0.0, 0.0),
+            0.6,
+            Arc::new(Lambertian {
+                albedo: Color::from_rgb(0.8, 0.8, 0.8),
+                emission: Color::BLACK,
+            }),
+        )),
+        light.clone(),
+    ];
+    let lights: Vec<Arc<dyn LightSampleable>> = if sample_light {
+        vec![light]
+    } else {
+        Vec::new()
+    };
+    let camera = Camera::new(
+        Vector::from_xyz(-0.8, 0.0, 6.0),
+        Vector::from_xyz(-0.8, 0.0, 0.0),
+        Vector::from_xyz(0.0, 1.0, 0.0),
+        8.0,
+        1.0,
+        0.0,
+        0,
+        6.0,
+    );
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .lights(lights)
+        .background(Color::BLACK)
+        .samples(samples)
+        .seed(1)
+        .build()
+        .unwrap();
+
+    let mut image = Image::new(16, 16);
+    render(job, &mut image, &mut Aovs::default(), |_, _| {});
+    image
+        .pixels
+        .iter()
+        .map(|color| color.luminance())
+        .sum::<f32>()
+        / image.pixels.len() as f32
+}
+
+// Light sampling only changes the noise, so its shadow rays must be absorbed by the glass like
+// the scattered rays that find the light on their own
+#[test]
+fn light_sampling_inside_tinted_glass_matches_brute_force() {
+    let brute_force = mean_luminance_inside_glass(false, 2048);
+    let sampled = mean_luminance_inside_glass(true, 256);
+
+    let relative_difference = (sampled - brute_force).abs() / brute_force;
+    assert!(
+        relative_difference < 0.03,
+        "light sampling gives {sampled}, brute force {brute_force}"
+    );
+}
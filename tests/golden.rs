@@ -0,0 +1,431 @@
+use std::{env, fs, ops::Range, path::Path};
+
+use raybow::{
+    Aovs, Color, RenderJob, RenderScratch, debug_pixel,
+    image::Image,
+    render, render_reusing, render_to_vec, render_views,
+    scene::{self, Scene},
+};
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 18;
+
+// Renders are deterministic for a given seed, but SIMD and non-SIMD code paths may round
+// differently
+const MAX_DIFFERENCE: f32 = 1e-3;
+
+/// Largest absolute difference between any channel of two equally sized buffers
+fn max_abs_difference(a: &[Color], b: &[Color]) -> f32 {
+    assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b)
+        .flat_map(|(a, b)| [a.r - b.r, a.g - b.g, a.b - b.b])
+        .map(f32::abs)
+        .fold(0.0, f32::max)
+}
+
+fn read_reference(path: &Path) -> Vec<Color> {
+    let bytes = fs::read(path).unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+    let floats: Vec<_> = bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    floats
+        .chunks_exact(3)
+        .map(|rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]))
+        .collect()
+}
+
+fn write_reference(path: &Path, pixels: &[Color]) {
+    let bytes: Vec<_> = pixels
+        .iter()
+        .flat_map(|color| [color.r, color.g, color.b])
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    fs::write(path, bytes).unwrap();
+}
+
+/// Compares against the stored reference, or replaces it when `RAYBOW_BLESS` is set
+fn check_golden(name: &str, image: &Image) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(format!("{name}.f32"));
+
+    if env::var_os("RAYBOW_BLESS").is_some() {
+        write_reference(&path, &image.pixels);
+        return;
+    }
+
+    let reference = read_reference(&path);
+    let difference = max_abs_difference(&image.pixels, &reference);
+    assert!(
+        difference <= MAX_DIFFERENCE,
+        "{name} differs from the reference by up to {difference}"
+    );
+}
+
+#[test]
+fn builtin_spheres() {
+    let (camera, objects, background) = scene::builtin_spheres(WIDTH as f32 / HEIGHT as f32);
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .background(background)
+        .samples(8)
+        .seed(1)
+        .workers(2)
+        .build()
+        .unwrap();
+
+    let mut image = Image::new(WIDTH, HEIGHT);
+    render(job, &mut image, &mut Aovs::default(), |_, _| {});
+
+    check_golden("builtin_spheres", &image);
+}
+
+#[test]
+fn render_to_vec_matches_golden() {
+    let (camera, objects, background) = scene::builtin_spheres(WIDTH as f32 / HEIGHT as f32);
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .background(background)
+        .samples(8)
+        .seed(1)
+        .build()
+        .unwrap();
+    let (pixels, _) = render_to_vec(job, WIDTH, HEIGHT);
+
+    let mut image = Image::new(WIDTH, HEIGHT);
+    image.pixels = pixels
+        .into_iter()
+        .map(|[r, g, b]| Color::from_rgb(r, g, b))
+        .collect();
+    check_golden("builtin_spheres", &image);
+}
+
+// The builtin scene is mostly dark at this resolution, so this one covers the diffuse, glass
+// and metal materials under a bright background
+#[test]
+fn spheres_scene_file() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/spheres.ron");
+    let scene = Scene::from_file(&path).unwrap();
+    let camera = scene.construct_camera(WIDTH as f32 / HEIGHT as f32);
+    let job = RenderJob::builder(&camera)
+        .objects(scene.construct_world().unwrap())
+        .background(scene.construct_background().unwrap())
+        .samples(8)
+        .seed(1)
+        .workers(2)
+        .build()
+        .unwrap();
+
+    let mut image = Image::new(WIDTH, HEIGHT);
+    render(job, &mut image, &mut Aovs::default(), |_, _| {});
+
+    check_golden("spheres_scene_file", &image);
+}
+
+// A scratch left over from a render of a different size and worker count must not leak into
+// the next one
+#[test]
+fn reused_scratch_matches_golden() {
+    let (camera, objects, background) = scene::builtin_spheres(WIDTH as f32 / HEIGHT as f32);
+    let mut scratch = RenderScratch::default();
+
+    let job = RenderJob::builder(&camera)
+        .objects(objects.clone())
+        .samples(3)
+        .seed(7)
+        .workers(3)
+        .build()
+        .unwrap();
+    let mut image = Image::new(2 * WIDTH, HEIGHT);
+    render_reusing(
+        job,
+        &mut image,
+        &mut Aovs::default(),
+        &mut scratch,
+        |_, _| {},
+    );
+
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .background(background)
+        .samples(8)
+        .seed(1)
+        .workers(2)
+        .build()
+        .unwrap();
+    let mut image = Image::new(WIDTH, HEIGHT);
+    render_reusing(
+        job,
+        &mut image,
+        &mut Aovs::default(),
+        &mut scratch,
+        |_, _| {},
+    );
+
+    check_golden("builtin_spheres", &image);
+}
+
+#[test]
+fn split_sample_ranges_match_full_render() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/spheres.ron");
+    let scene = Scene::from_file(&path).unwrap();
+    let camera = scene.construct_camera(WIDTH as f32 / HEIGHT as f32);
+    let render_range = |samples: Range<u32>| {
+        let job = RenderJob::builder(&camera)
+            .objects(scene.construct_world().unwrap())
+            .background(scene.construct_background().unwrap())
+            .sample_range(samples)
+            .seed(1)
+            .workers(2)
+            .build()
+            .unwrap();
+        let mut image = Image::new(WIDTH, HEIGHT);
+        render(job, &mut image, &mut Aovs::default(), |_, _| {});
+        image
+    };
+
+    let full = render_range(0..8);
+    let first = render_range(0..3);
+    let second = render_range(3..8);
+
+    let merged: Vec<_> = first
+        .pixels
+        .iter()
+        .zip(&second.pixels)
+        .map(|(&a, &b)| (a * 3.0 + b * 5.0) / 8.0)
+        .collect();
+    let difference = max_abs_difference(&merged, &full.pixels);
+    assert!(
+        difference < 1e-5,
+        "merged render differs by up to {difference}"
+    );
+}
+
+// Views share the BVH but not their random numbers, so the same camera twice gives two
+// different estimates, the first of which is the plain render
+#[test]
+fn first_view_matches_golden() {
+    let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
+    let (camera, objects, background) = scene::builtin_spheres(aspect_ratio);
+    let cameras = [camera, scene::builtin_spheres(aspect_ratio).0];
+    let job = RenderJob::builder(&cameras[0])
+        .objects(objects)
+        .background(background)
+        .samples(8)
+        .seed(1)
+        .workers(2)
+        .build()
+        .unwrap();
+
+    let (images, stats) = render_views(job, &cameras, WIDTH, HEIGHT, |_, _, _| {});
+    assert_eq!(images.len(), 2);
+    assert_eq!(stats.passes, 16);
+
+    check_golden("builtin_spheres", &images[0]);
+    assert_ne!(images[0].pixels, images[1].pixels);
+}
+
+// Russian roulette only trades noise for fewer rays, so the mean brightness stays the same
+#[test]
+fn russian_roulette_keeps_mean_brightness() {
+    let render_mean = |russian_roulette: bool| {
+        let (camera, objects, background) = scene::builtin_spheres(WIDTH as f32 / HEIGHT as f32);
+        let mut builder = RenderJob::builder(&camera)
+            .objects(objects)
+            .background(background)
+            .samples(256)
+            .seed(1);
+        if russian_roulette {
+            builder = builder.russian_roulette(1);
+        }
+
+        let mut image = Image::new(WIDTH, HEIGHT);
+        let stats = render(
+            builder.build().unwrap(),
+            &mut image,
+            &mut Aovs::default(),
+            |_, _| {},
+        );
+        let sum: f32 = image.pixels.iter().map(|color| color.luminance()).sum();
+        (sum / image.pixels.len() as f32, stats.rays)
+    };
+
+    let (mean, rays) = render_mean(false);
+    let (roulette_mean, roulette_rays) = render_mean(true);
+    assert!(roulette_rays < rays);
+    assert!(
+        (roulette_mean - mean).abs() < 0.02 * mean,
+        "mean luminance {roulette_mean} with Russian roulette, {mean} without"
+    );
+}
+
+// The counters alone already keep the noise of adjacent pixels uncorrelated, so scrambling can
+// only be checked to keep it that way, not to improve on it
+#[test]
+fn scrambled_pixels_keep_noise_uncorrelated() {
+    let render_luminance = |scramble_pixels: bool, seed: u64| {
+        // The scene's own lights leave most of a single sample black, so a white sky lights it
+        let (camera, objects, _) = scene::builtin_spheres(WIDTH as f32 / HEIGHT as f32);
+        let mut builder = RenderJob::builder(&camera)
+            .objects(objects)
+            .background(Color::WHITE)
+            .samples(1)
+            .seed(seed);
+        if scramble_pixels {
+            builder = builder.scramble_pixels();
+        }
+
+        let mut image = Image::new(4 * WIDTH, 4 * HEIGHT);
+        render(
+            builder.build().unwrap(),
+            &mut image,
+            &mut Aovs::default(),
+            |_, _| {},
+        );
+        image
+            .pixels
+            .iter()
+            .map(|color| color.luminance() / (1.0 + color.luminance()))
+            .collect::<Vec<_>>()
+    };
+
+    // Correlation between the noise of horizontally adjacent pixels, taking the difference of
+    // two renders with different seeds as the noise. Luminance is tone mapped so that a few
+    // bright outliers can't dominate the estimate.
+    let neighbour_correlation = |scramble_pixels: bool| {
+        let noise: Vec<f32> = render_luminance(scramble_pixels, 1)
+            .iter()
+            .zip(render_luminance(scramble_pixels, 2))
+            .map(|(a, b)| a - b)
+            .collect();
+        let mean = noise.iter().sum::<f32>() / noise.len() as f32;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for row in noise.chunks(4 * WIDTH as usize) {
+            for pair in row.windows(2) {
+                covariance += (pair[0] - mean) * (pair[1] - mean);
+                variance += (pair[0] - mean).powi(2);
+            }
+        }
+        covariance / variance
+    };
+
+    let scrambled = render_luminance(true, 1);
+    assert_eq!(scrambled, render_luminance(true, 1));
+    assert_ne!(scrambled, render_luminance(false, 1));
+    for scramble_pixels in [false, true] {
+        let correlation = neighbour_correlation(scramble_pixels);
+        assert!(
+            correlation.abs() < 0.1,
+            "neighbour correlation {correlation} with scramble_pixels: {scramble_pixels}"
+        );
+    }
+}
+
+#[test]
+fn debug_pixel_matches_render() {
+    // Lit by a white sky, since the scene's own lights leave most single samples black
+    let (camera, objects, _) = scene::builtin_spheres(WIDTH as f32 / HEIGHT as f32);
+    let job = || {
+        RenderJob::builder(&camera)
+            .objects(objects.clone())
+            .background(Color::WHITE)
+            .sample_range(5..6)
+            .seed(1)
+            .build()
+            .unwrap()
+    };
+    let mut image = Image::new(WIDTH, HEIGHT);
+    render(job(), &mut image, &mut Aovs::default(), |_, _| {});
+
+    for (x, y) in [
+        (0, 0),
+        (WIDTH / 2, HEIGHT / 2),
+        (WIDTH - 1, HEIGHT - 1),
+        (3, 11),
+    ] {
+        let color = debug_pixel(job(), WIDTH, HEIGHT, x, y, 5);
+        assert!(color != Color::BLACK);
+        assert_eq!(Some(color), image.pixel(x, y), "pixel ({x}, {y})");
+    }
+}
+
+#[test]
+fn tiny_images_write_every_pixel() {
+    for (width, height) in [(1, 1), (3, 2), (1, 5)] {
+        let (camera, objects, _) = scene::builtin_spheres(width as f32 / height as f32);
+        let job = RenderJob::builder(&camera)
+            .objects(objects)
+            .background(Color::WHITE)
+            .samples(2)
+            .seed(1)
+            .workers(4)
+            .build()
+            .unwrap();
+
+        let mut image = Image::new(width, height);
+        image
+            .pixels
+            .fill(Color::from_rgb(f32::NAN, f32::NAN, f32::NAN));
+        render(job, &mut image, &mut Aovs::default(), |_, _| {});
+
+        for (idx, color) in image.pixels.iter().enumerate() {
+            assert!(
+                color.is_finite() && *color != Color::BLACK,
+                "pixel {idx} of {width}x{height} is {color:?}"
+            );
+        }
+    }
+}
+
+// Random numbers are keyed by pixel and sample rather than by worker, so the result must not
+// even differ by rounding
+#[test]
+fn worker_count_does_not_change_output() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/spheres.ron");
+    let scene = Scene::from_file(&path).unwrap();
+    let camera = scene.construct_camera(WIDTH as f32 / HEIGHT as f32);
+    let render_with_workers = |workers: usize| {
+        let job = RenderJob::builder(&camera)
+            .objects(scene.construct_world().unwrap())
+            .background(scene.construct_background().unwrap())
+            .samples(4)
+            .seed(3)
+            .workers(workers)
+            .build()
+            .unwrap();
+        let mut image = Image::new(WIDTH, HEIGHT);
+        image.alpha = Some(vec![0.0; image.pixels.len()].into_boxed_slice());
+        let mut aovs = Aovs {
+            normal: Some(Image::new(WIDTH, HEIGHT)),
+            depth: Some(vec![0.0; image.pixels.len()].into_boxed_slice()),
+            ..Aovs::default()
+        };
+        render(job, &mut image, &mut aovs, |_, _| {});
+        (image, aovs)
+    };
+
+    // Bit patterns, which unlike `==` also tell apart zeros of different sign
+    let bits = |colors: &[Color]| -> Vec<[u32; 3]> {
+        colors
+            .iter()
+            .map(|color| [color.r, color.g, color.b].map(f32::to_bits))
+            .collect()
+    };
+    let float_bits = |values: &[f32]| -> Vec<u32> { values.iter().map(|v| v.to_bits()).collect() };
+
+    let (image, aovs) = render_with_workers(1);
+    let (parallel_image, parallel_aovs) = render_with_workers(8);
+
+    assert!(bits(&image.pixels) == bits(&parallel_image.pixels));
+    assert!(
+        float_bits(image.alpha.as_deref().unwrap())
+            == float_bits(parallel_image.alpha.as_deref().unwrap())
+    );
+    assert!(bits(&aovs.normal.unwrap().pixels) == bits(&parallel_aovs.normal.unwrap().pixels));
+    assert!(
+        float_bits(aovs.depth.as_deref().unwrap())
+            == float_bits(parallel_aovs.depth.as_deref().unwrap())
+    );
+}
@@ -0,0 +1,81 @@
+use raybow::{Color, image::Image};
+
+#[test]
+fn bloom_spreads_bright_pixel_and_keeps_energy() {
+    let mut image = Image::new(21, 21);
+    image.pixels[10 * 21 + 10] = Color::WHITE * 100.0;
+    image.bloom(1.0, 4.0, 1.0);
+
+    let center = image.pixel(10, 10).unwrap();
+    let neighbor = image.pixel(12, 10).unwrap();
+    assert!(center.r < 100.0 && center.r > neighbor.r);
+    assert!(neighbor.r > 0.0);
+    assert!((neighbor.r - image.pixel(10, 12).unwrap().r).abs() < 1e-5);
+
+    let total = image.pixels.iter().map(|color| color.r).sum::<f32>();
+    assert!((total - 100.0).abs() < 1e-3, "total energy {total}");
+}
+
+#[test]
+fn bloom_ignores_pixels_below_threshold() {
+    let mut image = Image::new(8, 8);
+    image.pixels[3 * 8 + 3] = Color::from_rgb(0.5, 0.5, 0.5);
+    let before = image.content_hash();
+    image.bloom(1.0, 2.0, 1.0);
+    assert_eq!(image.content_hash(), before);
+}
+
+#[test]
+fn vignette_keeps_center_and_darkens_corners() {
+    let mut image = Image::new(9, 7);
+    image.pixels.fill(Color::WHITE);
+    image.vignette(0.0);
+    assert!(image.pixels.iter().all(|&color| color == Color::WHITE));
+
+    image.vignette(0.5);
+    assert_eq!(image.pixel(4, 3), Some(Color::WHITE));
+    let corner = image.pixel(0, 0).unwrap();
+    let edge = image.pixel(0, 3).unwrap();
+    assert!(corner.r < edge.r && edge.r < 1.0 && corner.r > 0.5);
+}
+
+/// 4x2 image whose pixels encode their coordinates
+fn gradient() -> Image {
+    let mut image = Image::new(4, 2);
+    for y in 0..2 {
+        for x in 0..4 {
+            image.pixels[y * 4 + x] = Color::from_rgb(x as f32, y as f32, 10.0);
+        }
+    }
+    image
+}
+
+#[test]
+fn crop_copies_region() {
+    let cropped = gradient().crop(1, 1, 2, 1);
+    assert_eq!((cropped.width(), cropped.height()), (2, 1));
+    assert_eq!(cropped.pixel(0, 0), Some(Color::from_rgb(1.0, 1.0, 10.0)));
+    assert_eq!(cropped.pixel(1, 0), Some(Color::from_rgb(2.0, 1.0, 10.0)));
+}
+
+#[test]
+#[should_panic(expected = "crop region out of bounds")]
+fn crop_out_of_bounds_panics() {
+    gradient().crop(3, 0, 2, 1);
+}
+
+#[test]
+fn resize_averages_covered_pixels() {
+    let image = gradient();
+
+    // Each new pixel averages a 2x2 block, and values above 1 are not clamped
+    let halved = image.resize(2, 1);
+    assert_eq!(halved.pixel(0, 0), Some(Color::from_rgb(0.5, 0.5, 10.0)));
+    assert_eq!(halved.pixel(1, 0), Some(Color::from_rgb(2.5, 0.5, 10.0)));
+
+    let same = image.resize(4, 2);
+    assert_eq!(same.pixels, image.pixels);
+
+    let doubled = image.resize(8, 4);
+    assert_eq!(doubled.pixel(7, 3), Some(Color::from_rgb(3.0, 1.0, 10.0)));
+}
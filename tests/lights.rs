@@ -0,0 +1,158 @@
+use std::sync::Arc;
+
+use raybow::{
+    Aovs, Camera, Color, RenderJob,
+    geometry::{LightSampleable, Object, Sphere},
+    image::Image,
+    material::{DiffuseLight, Lambertian, Material},
+    render,
+    vector::Vector,
+};
+
+fn diffuse(albedo: Color) -> Arc<dyn Material> {
+    Arc::new(Lambertian {
+        albedo,
+        emission: Color::BLACK,
+    })
+}
+
+fn mean_luminance(
+    lights: Vec<Arc<dyn LightSampleable>>,
+    emitters: &[Arc<Sphere>],
+    samples: u32,
+) -> f32 {
+    let camera = Camera::new(
+        Vector::from_xyz(0.0, 1.5, 6.0),
+        Vector::from_xyz(0.0, 0.5, 0.0),
+        Vector::from_xyz(0.0, 1.0, 0.0),
+        35.0,
+        16.0 / 9.0,
+        0.0,
+        0,
+        6.0,
+    );
+    let mut objects: Vec<Arc<dyn Object>> = vec![
+        Arc::new(Sphere::new(
+            Vector::from_xyz(0.0, -1000.0, 0.0),
+            1000.0,
+            diffuse(Color::from_rgb(0.7, 0.7, 0.7)),
+        )),
+        Arc::new(Sphere::new(
+            Vector::from_xyz(-0.6, 0.5, 0.0),
+            0.5,
+            diffuse(Color::from_rgb(0.8, 0.3, 0.2)),
+        )),
+    ];
+    for emitter in emitters {
+        objects.push(emitter.clone());
+    }
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .lights(lights)
+        .samples(samples)
+        .seed(1)
+        .build()
+        .unwrap();
+
+    let mut image = Image::new(32, 18);
+    render(job, &mut image, &mut Aovs::default(), |_, _| {});
+    let sum: f32 = image.pixels.iter().map(|color| color.luminance()).sum();
+    sum / image.pixels.len() as f32
+}
+
+// Sampling the light only changes the noise, so a few samples with it must agree with many
+// samples where the light is only found by scattered rays
+#[test]
+fn light_sampling_matches_brute_force() {
+    let light = Arc::new(Sphere::new(
+        Vector::from_xyz(1.0, 3.0, 1.5),
+        0.5,
+        Arc::new(DiffuseLight {
+            emit: Arc::new(Color::WHITE * 4.0),
+            two_sided: true,
+        }),
+    ));
+
+    let brute_force = mean_luminance(Vec::new(), std::slice::from_ref(&light), 1024);
+    let sampled = mean_luminance(vec![light.clone()], &[light], 64);
+
+    let relative_difference = (sampled - brute_force).abs() / brute_force;
+    assert!(
+        relative_difference < 0.03,
+        "light sampling gives {sampled}, brute force {brute_force}"
+    );
+}
+
+// Lights are picked by power, which again must only change the noise. The large dim light
+// and the small bright one differ in power by a factor of 30.
+#[test]
+fn power_weighted_selection_matches_brute_force() {
+    let light = |center: Vector, radius: f32, emit: f32| {
+        Arc::new(Sphere::new(
+            center,
+            radius,
+            Arc::new(DiffuseLight {
+                emit: Arc::new(Color::WHITE * emit),
+                two_sided: true,
+            }),
+        ))
+    };
+    let dim = light(Vector::from_xyz(-2.0, 3.0, -1.0), 1.0, 0.3);
+    let bright = light(Vector::from_xyz(1.0, 2.0, 1.5), 0.15, 400.0);
+    assert!(bright.power() > 10.0 * dim.power());
+
+    let emitters = [dim.clone(), bright.clone()];
+    let brute_force = mean_luminance(Vec::new(), &emitters, 2048);
+    let sampled = mean_luminance(vec![dim, bright], &emitters, 64);
+
+    let relative_difference = (sampled - brute_force).abs() / brute_force;
+    assert!(
+        relative_difference < 0.03,
+        "light sampling gives {sampled}, brute force {brute_force}"
+    );
+}
+
+// The emission texture is red in its top half and blue in its bottom half, which map to the
+// upper and lower hemispheres of the light
+#[cfg(feature = "exr")]
+#[test]
+fn textured_light_from_scene_file() {
+    use std::{env, fs};
+
+    use raybow::scene::Scene;
+
+    let dir = env::temp_dir().join(format!("raybow-textured-light-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // Two rows of each color, so the filtering only blends them near the equator and the poles
+    let mut texture = Image::new(1, 4);
+    let (red, blue) = (
+        Color::from_rgb(1.0, 0.0, 0.0),
+        Color::from_rgb(0.0, 0.0, 1.0),
+    );
+    texture.pixels = Box::new([red, red, blue, blue]);
+    texture.save_exr(&dir.join("emit.exr")).unwrap();
+    fs::write(
+        dir.join("scene.ron"),
+        r##"Scene(
+            camera: (position: (0, 0, 3), lookat: (0, 0, 0), vfov: 60, aperture: 0),
+            materials: { "light": (type: "DiffuseLight", emit: (image: "emit.exr")) },
+            objects: [(type: "Sphere", center: (0, 0, 0), radius: 1, material: "light")],
+        )"##,
+    )
+    .unwrap();
+
+    let result = Scene::from_file(&dir.join("scene.ron"))
+        .and_then(|scene| Ok((scene.construct_camera(1.0), scene.construct_world()?)));
+    fs::remove_dir_all(&dir).unwrap();
+    let (camera, objects) = result.unwrap();
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .samples(4)
+        .build()
+        .unwrap();
+    let mut image = Image::new(8, 8);
+    render(job, &mut image, &mut Aovs::default(), |_, _| {});
+
+    assert!(image.pixels.contains(&red));
+    assert!(image.pixels.contains(&blue));
+}
@@ -0,0 +1,263 @@
+use std::{env, fs, sync::Arc};
+
+use raybow::{
+    Aovs, Camera, Color, RenderJob,
+    bumpalo::Bump,
+    geometry::{Bvh, Object, TriangleMesh, bvh},
+    image::Image,
+    material::{AlphaMask, Lambertian, Material},
+    ray::Ray,
+    render,
+    scene::{ObjectRef, Scene, SceneError},
+    texture::ImageTexture,
+    vector::Vector,
+};
+
+/// A unit quad in the z = 0 plane followed by a triangle with collinear vertices and one with
+/// two identical vertices
+fn mesh_with_degenerate_triangles() -> TriangleMesh {
+    #[rustfmt::skip]
+    let vertices = [
+        -1.0, -1.0, 0.0,
+        1.0, -1.0, 0.0,
+        1.0, 1.0, 0.0,
+        -1.0, 1.0, 0.0,
+        0.0, 0.0, 0.0,
+    ];
+    let indices = [0, 1, 2, 0, 2, 3, 0, 4, 2, 1, 1, 3];
+    let material: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::from_rgb(0.5, 0.5, 0.5),
+        emission: Color::BLACK,
+    });
+
+    TriangleMesh::new(vertices.into(), indices.into(), material)
+}
+
+#[test]
+fn degenerate_triangles_are_skipped() {
+    let mesh = mesh_with_degenerate_triangles();
+    assert_eq!(mesh.num_degenerate(), 2);
+
+    // The diagonal the collinear triangle lies on is still hit through the quad
+    let bvh = Bvh::new(mesh, bvh::DEFAULT_MAX_LEAF_SIZE);
+    let arena = Bump::new();
+    let ray = Ray::new(
+        Vector::from_xyz(0.0, 0.0, 1.0),
+        Vector::from_xyz(0.0, 0.0, -1.0),
+    );
+    let hit = bvh.hit(ray, 0.0..f32::INFINITY, &arena).unwrap();
+    assert!(hit.normal.0.iter().all(|v| v.is_finite()));
+    assert!((hit.t - 1.0).abs() < 1e-6);
+}
+
+// Triangles facing each axis in both directions, each looked at from both sides along
+// directions that are dominated by different axes
+#[test]
+fn culled_back_faces_let_rays_pass() {
+    let material: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Color::WHITE,
+        emission: Color::BLACK,
+    });
+    let axes = [
+        Vector::from_xyz(1.0, 0.0, 0.0),
+        Vector::from_xyz(0.0, 1.0, 0.0),
+        Vector::from_xyz(0.0, 0.0, 1.0),
+    ];
+    let arena = Bump::new();
+
+    for (i, normal) in axes.into_iter().enumerate() {
+        let tangent = axes[(i + 1) % 3];
+        let bitangent = axes[(i + 2) % 3];
+        for side in [1.0, -1.0] {
+            // Counterclockwise around the normal, so the front faces it
+            let normal = normal * side;
+            let mut corners = [-tangent - bitangent, tangent - bitangent, bitangent];
+            if side < 0.0 {
+                corners.reverse();
+            }
+            let vertices: Box<[f32]> = corners
+                .iter()
+                .flat_map(|corner| [corner.x(), corner.y(), corner.z()])
+                .collect();
+            let mesh = TriangleMesh::new(vertices, [0, 1, 2].into(), Arc::clone(&material))
+                .with_back_face_culling();
+            let bvh = Bvh::new(mesh, bvh::DEFAULT_MAX_LEAF_SIZE);
+
+            // All rays pass through the origin, which lies within the triangle
+            for offset in [Vector::ZERO, tangent * 0.9, bitangent * -2.0] {
+                let front = normal * 2.0 + offset;
+                let ray = Ray::new(front, -front);
+                let hit = bvh.hit(ray, 0.0..f32::INFINITY, &arena);
+                assert!(
+                    hit.is_some_and(|hit| hit.front_face),
+                    "{normal:?} from the front"
+                );
+
+                let ray = Ray::new(-front, front);
+                let hit = bvh.hit(ray, 0.0..f32::INFINITY, &arena);
+                assert!(hit.is_none(), "{normal:?} from behind");
+            }
+        }
+    }
+}
+
+#[test]
+fn render_has_no_nans() {
+    let mesh = mesh_with_degenerate_triangles();
+    let objects: Vec<Arc<dyn Object>> = vec![Arc::new(Bvh::new(mesh, bvh::DEFAULT_MAX_LEAF_SIZE))];
+    let camera = Camera::new(
+        Vector::from_xyz(0.0, 0.0, 3.0),
+        Vector::ZERO,
+        Vector::from_xyz(0.0, 1.0, 0.0),
+        60.0,
+        1.0,
+        0.0,
+        0,
+        3.0,
+    );
+
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .background(Color::WHITE)
+        .samples(4)
+        .workers(1)
+        .build()
+        .unwrap();
+    let mut image = Image::new(16, 16);
+    let stats = render(job, &mut image, &mut Aovs::default(), |_, _| {});
+
+    assert_eq!(stats.rejected_samples, 0);
+    assert!(image.pixels.iter().all(|pixel| pixel.is_finite()));
+}
+
+// One half of the card is cut out by its mask, so the background shows through there
+#[test]
+fn alpha_masked_card_lets_rays_pass() {
+    let mut mask = Image::new(2, 1);
+    mask.alpha = Some(Box::new([0.0, 1.0]));
+    let card_material: Arc<dyn Material> = Arc::new(AlphaMask {
+        material: Arc::new(Lambertian {
+            albedo: Color::BLACK,
+            emission: Color::from_rgb(0.0, 1.0, 0.0),
+        }),
+        mask: Arc::new(ImageTexture::new(mask)),
+        threshold: 0.5,
+    });
+    #[rustfmt::skip]
+    let vertices = [
+        -1.0, -1.0, 0.0,
+        1.0, -1.0, 0.0,
+        1.0, 1.0, 0.0,
+        -1.0, 1.0, 0.0,
+    ];
+    let uvs = [0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+    let card = TriangleMesh::new(vertices.into(), [0, 1, 2, 0, 2, 3].into(), card_material)
+        .with_uvs(uvs.into());
+
+    let objects: Vec<Arc<dyn Object>> = vec![Arc::new(Bvh::new(card, bvh::DEFAULT_MAX_LEAF_SIZE))];
+    let camera = Camera::new(
+        Vector::from_xyz(0.0, 0.0, 1.0),
+        Vector::ZERO,
+        Vector::from_xyz(0.0, 1.0, 0.0),
+        40.0,
+        2.0,
+        0.0,
+        0,
+        1.0,
+    );
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .background(Color::from_rgb(1.0, 0.0, 0.0))
+        .samples(4)
+        .build()
+        .unwrap();
+    let mut image = Image::new(2, 1);
+    let stats = render(job, &mut image, &mut Aovs::default(), |_, _| {});
+
+    assert!(image.pixels.contains(&Color::from_rgb(1.0, 0.0, 0.0)));
+    assert!(image.pixels.contains(&Color::from_rgb(0.0, 1.0, 0.0)));
+    // Only the opaque half counts as a hit
+    assert_eq!(stats.hits, 4);
+}
+
+const QUAD: &str =
+    "vertices: [-1, -1, 0, 1, -1, 0, 1, 1, 0, -1, 1, 0], indices: [0, 1, 2, 0, 2, 3],";
+
+/// Constructs a scene with a mesh as its second object, where `mesh_fields` holds its geometry
+/// and material settings
+fn construct_mesh_scene(mesh_fields: &str) -> Result<Vec<Arc<dyn Object>>, SceneError> {
+    let path = env::temp_dir().join(format!("raybow-material-ids-{}.ron", std::process::id()));
+    fs::write(
+        &path,
+        format!(
+            r##"Scene(
+                camera: (position: (0, 0, 5), vfov: 40, aperture: 0.0),
+                materials: {{
+                    "red": (type: "Lambertian", albedo: "#FF0000"),
+                    "green": (type: "Lambertian", albedo: "#00FF00"),
+                }},
+                objects: [
+                    (type: "Sphere", center: (0, 0, -5), radius: 1, material: "red"),
+                    (type: "Mesh", {mesh_fields}),
+                ],
+            )"##
+        ),
+    )
+    .unwrap();
+
+    let result = Scene::from_file(&path).and_then(|scene| scene.construct_world());
+    fs::remove_file(&path).unwrap();
+    result
+}
+
+fn construct_quad_scene(material_fields: &str) -> Result<Vec<Arc<dyn Object>>, SceneError> {
+    construct_mesh_scene(&format!("{QUAD} {material_fields}"))
+}
+
+#[test]
+fn invalid_material_ids_in_scene_are_rejected() {
+    assert!(
+        construct_quad_scene(r#"material: "red", materials: ["green"], material_ids: [0, 1]"#)
+            .is_ok()
+    );
+
+    assert!(matches!(
+        construct_quad_scene(r#"material: "red", materials: ["green"], material_ids: [1]"#),
+        Err(SceneError::MaterialIdCount {
+            object: ObjectRef::Object(1),
+            triangles: 2,
+            material_ids: 1,
+        })
+    ));
+    assert!(matches!(
+        construct_quad_scene(r#"material: "red", materials: ["green"], material_ids: [0, 2]"#),
+        Err(SceneError::InvalidMaterialId {
+            object: ObjectRef::Object(1),
+            id: 2,
+            materials: 2,
+        })
+    ));
+    assert!(matches!(
+        construct_quad_scene(r#"material: "red", materials: ["green"]"#),
+        Err(SceneError::MaterialsWithoutIds {
+            object: ObjectRef::Object(1),
+        })
+    ));
+
+    for geometry in [
+        "vertices: [0, 0, 0, 1, 0, 0, 0, 1, 0], indices: [0, 1, 7],",
+        "vertices: [0, 0, 0, 1, 0, 0, 0, 1, 0], indices: [0, 1],",
+        "vertices: [0, 0, 0, 1, 0, 0, 0, 1], indices: [0, 1, 2],",
+    ] {
+        assert!(
+            matches!(
+                construct_mesh_scene(&format!(r#"{geometry} material: "red""#)),
+                Err(SceneError::InvalidMesh {
+                    object: ObjectRef::Object(1),
+                    ..
+                })
+            ),
+            "{geometry}"
+        );
+    }
+}
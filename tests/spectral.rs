@@ -0,0 +1,83 @@
+#![cfg(feature = "spectral")]
+
+use std::sync::Arc;
+
+use raybow::{
+    Aovs, Camera, Color, ColorModel, RenderJob,
+    geometry::{Object, Sphere},
+    image::Image,
+    material::{Lambertian, Material},
+    render, spectrum,
+    vector::Vector,
+};
+
+fn gray(albedo: f32) -> Arc<dyn Material> {
+    Arc::new(Lambertian {
+        albedo: Color::from_rgb(albedo, albedo, albedo),
+        emission: Color::BLACK,
+    })
+}
+
+fn mean_color(color_model: ColorModel) -> Color {
+    let camera = Camera::new(
+        Vector::from_xyz(0.0, 1.0, 4.0),
+        Vector::from_xyz(0.0, 0.5, 0.0),
+        Vector::from_xyz(0.0, 1.0, 0.0),
+        40.0,
+        1.0,
+        0.0,
+        0,
+        4.0,
+    );
+    let objects: Vec<Arc<dyn Object>> = vec![
+        Arc::new(Sphere::new(
+            Vector::from_xyz(0.0, -1000.0, 0.0),
+            1000.0,
+            gray(0.5),
+        )),
+        Arc::new(Sphere::new(Vector::from_xyz(0.0, 0.5, 0.0), 0.5, gray(0.8))),
+    ];
+    let job = RenderJob::builder(&camera)
+        .objects(objects)
+        .background(Color::WHITE)
+        .color_model(color_model)
+        .samples(64)
+        .seed(1)
+        .build()
+        .unwrap();
+
+    let mut image = Image::new(24, 24);
+    render(job, &mut image, &mut Aovs::default(), |_, _| {});
+    let sum = image
+        .pixels
+        .iter()
+        .fold(Color::BLACK, |sum, &color| sum + color);
+    sum / image.pixels.len() as f32
+}
+
+// Gray surfaces under white light reflect the same spectrum they receive, so both color models
+// must converge to the same image
+#[test]
+fn neutral_scene_matches_rgb() {
+    let rgb = mean_color(ColorModel::Rgb);
+    let spectral = mean_color(ColorModel::Spectral);
+
+    for (rgb, spectral) in [
+        (rgb.r, spectral.r),
+        (rgb.g, spectral.g),
+        (rgb.b, spectral.b),
+    ] {
+        assert!(
+            (spectral - rgb).abs() < 0.02 * rgb,
+            "spectral render gives {spectral:?}, RGB {rgb:?}"
+        );
+    }
+}
+
+#[test]
+fn white_reflectance_is_flat() {
+    for wavelength in [380.0, 450.0, 495.0, 550.0, 585.0, 700.0, 780.0] {
+        let value = spectrum::reflectance(Color::WHITE, wavelength);
+        assert!((value - 1.0).abs() < 1e-6, "{value} at {wavelength} nm");
+    }
+}
@@ -0,0 +1,103 @@
+use std::{env, fs, sync::Arc};
+
+use raybow::{
+    Color,
+    bumpalo::Bump,
+    geometry::{Object, Sphere},
+    material::{Lambertian, Material},
+    ray::Ray,
+    scene::{Scene, SceneError},
+    vector::Vector,
+};
+
+fn material() -> Arc<dyn Material> {
+    Arc::new(Lambertian {
+        albedo: Color::WHITE,
+        emission: Color::BLACK,
+    })
+}
+
+#[test]
+fn negative_radius_is_inside_out() {
+    let sphere = Sphere::new(Vector::ZERO, -1.0, material());
+    let arena = Bump::new();
+
+    // The bounds match those of a sphere with the positive radius
+    let bounds = sphere.bounding_box();
+    assert_eq!(bounds.minimum, Vector::from_xyz(-1.0, -1.0, -1.0));
+    assert_eq!(bounds.maximum, Vector::from_xyz(1.0, 1.0, 1.0));
+
+    // From outside, the ray hits the back of the surface
+    let ray = Ray::new(
+        Vector::from_xyz(0.0, 0.0, 3.0),
+        Vector::from_xyz(0.0, 0.0, -1.0),
+    );
+    let hit = sphere.hit(ray, 0.0..f32::INFINITY, &arena).unwrap();
+    assert!((hit.t - 2.0).abs() < 1e-6);
+    assert!(!hit.front_face);
+    // The normal still faces against the ray, while the geometric one points inward
+    assert!(hit.normal.dot(ray.direction) < 0.0);
+    assert!((hit.geometric_normal - Vector::from_xyz(0.0, 0.0, -1.0)).length() < 1e-6);
+
+    // From inside, the ray hits the front
+    let ray = Ray::new(Vector::ZERO, Vector::from_xyz(0.0, 0.0, -1.0));
+    let hit = sphere.hit(ray, 0.0..f32::INFINITY, &arena).unwrap();
+    assert!((hit.t - 1.0).abs() < 1e-6);
+    assert!(hit.front_face);
+    assert!((hit.geometric_normal - hit.normal).length() < 1e-6);
+}
+
+#[test]
+#[should_panic(expected = "sphere radius must be finite and non-zero")]
+fn zero_radius_panics() {
+    Sphere::new(Vector::ZERO, 0.0, material());
+}
+
+#[test]
+fn zero_radius_in_scene_is_rejected() {
+    let path = env::temp_dir().join(format!("raybow-zero-radius-{}.ron", std::process::id()));
+    fs::write(
+        &path,
+        r##"Scene(
+            camera: (position: (0, 0, 5), vfov: 40, aperture: 0.0),
+            materials: { "white": (type: "Lambertian", albedo: "#FFFFFF") },
+            objects: [(type: "Sphere", center: (0, 0, 0), radius: 0, material: "white")],
+        )"##,
+    )
+    .unwrap();
+
+    let result = Scene::from_file(&path).and_then(|scene| scene.construct_world());
+    fs::remove_file(&path).unwrap();
+
+    assert!(matches!(
+        result,
+        Err(SceneError::InvalidSphereRadius { radius: 0.0, .. })
+    ));
+}
+
+#[test]
+fn ray_from_inside_hits_exit() {
+    let sphere = Sphere::new(Vector::from_xyz(1.0, 2.0, 3.0), 2.0, material());
+    let arena = Bump::new();
+
+    // From the center, every direction exits after exactly one radius
+    for direction in [
+        Vector::from_xyz(1.0, 0.0, 0.0),
+        Vector::from_xyz(0.0, -1.0, 0.0),
+        Vector::from_xyz(0.0, 0.6, 0.8),
+    ] {
+        let ray = Ray::new(Vector::from_xyz(1.0, 2.0, 3.0), direction);
+        let hit = sphere.hit(ray, 0.0..f32::INFINITY, &arena).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-6, "t = {}", hit.t);
+        assert!(!hit.front_face);
+    }
+
+    // Off-center and pointing away from the center, which the early return used to miss
+    let ray = Ray::new(
+        Vector::from_xyz(1.0, 2.0, 2.0),
+        Vector::from_xyz(0.0, 0.0, -1.0),
+    );
+    let hit = sphere.hit(ray, 0.0..f32::INFINITY, &arena).unwrap();
+    assert!((hit.t - 1.0).abs() < 1e-6, "t = {}", hit.t);
+    assert!((hit.point - Vector::from_xyz(1.0, 2.0, 1.0)).length() < 1e-6);
+}
@@ -0,0 +1,22 @@
+use raybow::{Color, geometry::Uv, image::Image, texture::ImageTexture, texture::Texture};
+
+// Colors blend between pixel centers and wrap around the edges, while coverage stays sharp
+#[test]
+fn image_textures_filter_colors_bilinearly() {
+    let mut image = Image::new(2, 1);
+    image.pixels[1] = Color::WHITE;
+    image.alpha = Some(Box::new([0.0, 1.0]));
+    let texture = ImageTexture::new(image);
+
+    let red = |u: f32| texture.color(Uv::Planar([u, 0.5])).r;
+    assert_eq!(red(0.25), 0.0);
+    assert_eq!(red(0.75), 1.0);
+    assert!((red(0.5) - 0.5).abs() < 1e-6);
+    assert!((red(0.625) - 0.75).abs() < 1e-6);
+    assert!((red(0.0) - 0.5).abs() < 1e-6);
+    assert!((red(1.0) - 0.5).abs() < 1e-6);
+    assert!((red(-0.75) - red(0.25)).abs() < 1e-6);
+
+    assert_eq!(texture.alpha(Uv::Planar([0.45, 0.5])), 0.0);
+    assert_eq!(texture.alpha(Uv::Planar([0.55, 0.5])), 1.0);
+}